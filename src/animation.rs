@@ -0,0 +1,295 @@
+use std::ops::{Add, Mul, Sub};
+
+use crate::geometry::{HitRecord, Hittable};
+use crate::interval::Interval;
+use crate::ray::Ray;
+use crate::vec3::*;
+
+/// How values are interpolated between keyframes
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Interpolation {
+    #[default]
+    Linear,
+    /// Smooth curve through the keys, with tangents taking the spacing of
+    /// the keys into account
+    CatmullRom,
+}
+
+/// Values that can be keyframed
+pub trait Keyframe:
+    Copy + Add<Output = Self> + Sub<Output = Self> + Mul<f64, Output = Self>
+{
+}
+
+impl<T> Keyframe for T where T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f64, Output = T> {}
+
+/// Value changing over time, given by keys at increasing times in seconds.
+/// It holds the first and last keys before and after them, and always has
+/// at least one key
+#[derive(Debug, Clone, PartialEq)]
+pub struct Track<T> {
+    keys: Vec<(f64, T)>,
+    interpolation: Interpolation,
+}
+
+impl<T: Keyframe> Track<T> {
+    /// Track starting with the key `value` at `time`
+    pub fn new(interpolation: Interpolation, time: f64, value: T) -> Self {
+        Track {
+            keys: vec![(time, value)],
+            interpolation,
+        }
+    }
+
+    pub fn constant(value: T) -> Self {
+        Track::new(Interpolation::Linear, 0., value)
+    }
+
+    /// Adds a key, keeping them sorted. A key at the same time as an
+    /// existing one replaces it
+    pub fn add_key(&mut self, time: f64, value: T) -> &mut Self {
+        match self
+            .keys
+            .binary_search_by(|(key_time, _)| key_time.total_cmp(&time))
+        {
+            Ok(index) => self.keys[index].1 = value,
+            Err(index) => self.keys.insert(index, (time, value)),
+        }
+        self
+    }
+
+    /// Value at `time`
+    pub fn value(&self, time: f64) -> T {
+        let next = self.keys.partition_point(|(key_time, _)| *key_time <= time);
+        if next == 0 {
+            return self.keys[0].1;
+        }
+        if next == self.keys.len() {
+            return self.keys[next - 1].1;
+        }
+
+        let (t1, p1) = self.keys[next - 1];
+        let (t2, p2) = self.keys[next];
+        let s = (time - t1) / (t2 - t1);
+        match self.interpolation {
+            Interpolation::Linear => p1 + (p2 - p1) * s,
+            Interpolation::CatmullRom => {
+                // Cubic Hermite curve with tangents from the neighboring keys
+                let m1 = self.tangent(next - 1) * (t2 - t1);
+                let m2 = self.tangent(next) * (t2 - t1);
+                let (s2, s3) = (s * s, s * s * s);
+                p1 * (2. * s3 - 3. * s2 + 1.)
+                    + m1 * (s3 - 2. * s2 + s)
+                    + p2 * (-2. * s3 + 3. * s2)
+                    + m2 * (s3 - s2)
+            }
+        }
+    }
+
+    // Rate of change at a key, one sided at the ends
+    fn tangent(&self, index: usize) -> T {
+        let (t0, p0) = self.keys[index.saturating_sub(1)];
+        let (t1, p1) = self.keys[(index + 1).min(self.keys.len() - 1)];
+        (p1 - p0) * (1. / (t1 - t0))
+    }
+}
+
+impl<T: Keyframe + Default> Default for Track<T> {
+    fn default() -> Self {
+        Track::constant(T::default())
+    }
+}
+
+impl<T: Keyframe> From<T> for Track<T> {
+    fn from(value: T) -> Self {
+        Track::constant(value)
+    }
+}
+
+/// Placement of an object: scaled, then rotated by Euler angles in degrees
+/// around the x, y and z axes in that order, then translated
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transform {
+    pub translation: Track<Vec3>,
+    pub rotation: Track<Vec3>,
+    pub scale: Track<f64>,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform {
+            translation: Track::constant(Vec3::default()),
+            rotation: Track::constant(Vec3::default()),
+            scale: Track::constant(1.),
+        }
+    }
+}
+
+impl Transform {
+    // Rotation angles in radians and scale at `time`
+    fn at(&self, time: f64) -> (Vec3, Vec3, f64) {
+        let degrees = self.rotation.value(time);
+        let radians = Vec3 {
+            x: degrees.x.to_radians(),
+            y: degrees.y.to_radians(),
+            z: degrees.z.to_radians(),
+        };
+        (
+            self.translation.value(time),
+            radians,
+            self.scale.value(time),
+        )
+    }
+}
+
+fn rotate(v: &Vec3, angles: &Vec3) -> Vec3 {
+    let v = rotate_axis(v, angles.x, 0);
+    let v = rotate_axis(&v, angles.y, 1);
+    rotate_axis(&v, angles.z, 2)
+}
+
+fn rotate_inverse(v: &Vec3, angles: &Vec3) -> Vec3 {
+    let v = rotate_axis(v, -angles.z, 2);
+    let v = rotate_axis(&v, -angles.y, 1);
+    rotate_axis(&v, -angles.x, 0)
+}
+
+// Counterclockwise rotation around the x (0), y (1) or z (2) axis
+fn rotate_axis(v: &Vec3, angle: f64, axis: usize) -> Vec3 {
+    let (sin, cos) = angle.sin_cos();
+    match axis {
+        0 => Vec3 {
+            x: v.x,
+            y: cos * v.y - sin * v.z,
+            z: sin * v.y + cos * v.z,
+        },
+        1 => Vec3 {
+            x: cos * v.x + sin * v.z,
+            y: v.y,
+            z: -sin * v.x + cos * v.z,
+        },
+        _ => Vec3 {
+            x: cos * v.x - sin * v.y,
+            y: sin * v.x + cos * v.y,
+            z: v.z,
+        },
+    }
+}
+
+/// Object moving with a keyframed transform. Rays see it where it is at
+/// their time, which blurs it when the camera shutter stays open
+pub struct Animated<H: Hittable> {
+    pub object: H,
+    pub transform: Transform,
+}
+
+impl<H: Hittable> Hittable for Animated<H> {
+    fn hit(&self, ray: &mut Ray, ray_t: &Interval) -> Option<HitRecord> {
+        let (translation, angles, scale) = self.transform.at(ray.time);
+
+        // The same t reaches the same point in both spaces
+        let mut local_ray = ray.spawn(
+            rotate_inverse(&(ray.orig - translation), &angles) / scale,
+            rotate_inverse(&ray.dir, &angles) / scale,
+        );
+        let mut record = self.object.hit(&mut local_ray, ray_t)?;
+
+        record.point = ray.at(record.t);
+        record.normal = rotate(&record.normal, &angles);
+        record.tangent = rotate(&record.tangent, &angles);
+        record.bitangent = rotate(&record.bitangent, &angles);
+        Some(record)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::animation::*;
+    use crate::geometry::Sphere;
+    use crate::material::Lambertian;
+
+    #[test]
+    fn test_linear_track() {
+        let mut track = Track::new(Interpolation::Linear, 2., 10.);
+        track.add_key(0., 0.).add_key(3., 40.);
+        assert_eq!(track.value(-1.), 0.);
+        assert_eq!(track.value(1.), 5.);
+        assert_eq!(track.value(2.5), 25.);
+        assert_eq!(track.value(5.), 40.);
+    }
+
+    #[test]
+    fn test_catmull_rom_track() {
+        let mut track = Track::new(Interpolation::CatmullRom, 0., 0.);
+        track.add_key(1., 1.).add_key(2., 0.);
+        // Goes through the keys and overshoots the linear interpolation
+        // toward the peak
+        assert_eq!(track.value(1.), 1.);
+        assert!(track.value(0.75) > 0.75);
+
+        // Keys on a line stay on it
+        let mut line = Track::new(Interpolation::CatmullRom, 0., 0.);
+        line.add_key(1., 2.).add_key(3., 6.);
+        for time in [0.3, 1.5, 2.9] {
+            assert!((line.value(time) - 2. * time).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_animated_object_moves() {
+        let mut translation = Track::new(Interpolation::Linear, 0., Vec3::default());
+        translation.add_key(
+            1.,
+            Vec3 {
+                x: 0.,
+                y: 10.,
+                z: 0.,
+            },
+        );
+        let sphere = Animated {
+            object: Sphere {
+                center: Point3 {
+                    x: 0.,
+                    y: 0.,
+                    z: -5.,
+                },
+                radius: 1.,
+                material: Arc::new(Lambertian {
+                    albedo: Color::default(),
+                }),
+            },
+            transform: Transform {
+                translation,
+                rotation: Track::constant(Vec3 {
+                    x: 0.,
+                    y: 90.,
+                    z: 0.,
+                }),
+                scale: Track::constant(2.),
+            },
+        };
+
+        let interval = Interval {
+            min: 0.001,
+            max: f64::INFINITY,
+        };
+        // Rotated by 90° around y and scaled, the sphere is centered at
+        // (-10, 0, 0) at time 0, then moves up
+        let mut ray = Ray::new(
+            Point3::default(),
+            Vec3 {
+                x: -1.,
+                y: 0.,
+                z: 0.,
+            },
+        );
+        let record = sphere.hit(&mut ray, &interval).unwrap();
+        assert!((record.t - 8.).abs() < 1e-9);
+        assert!((record.normal.x - 1.).abs() < 1e-9);
+
+        ray.time = 1.;
+        assert!(sphere.hit(&mut ray, &interval).is_none());
+    }
+}
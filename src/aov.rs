@@ -0,0 +1,285 @@
+use std::collections::HashMap;
+use std::io::{Error, Result};
+
+use raster::Image;
+
+use crate::film::save_pfm;
+use crate::geometry::HitRecord;
+use crate::ray::Ray;
+use crate::sampler::{hash, to_unit};
+use crate::vec3::*;
+
+/// Auxiliary output describing the surfaces seen through each pixel
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Aov {
+    /// Distance from the camera to the first hit
+    Depth,
+    /// World space shading normal, facing the camera
+    Normal,
+    Albedo,
+    /// World space position of the first hit
+    Position,
+    MaterialId,
+    ObjectId,
+}
+
+/// First hit values averaged over the samples of a pixel. Pixels seeing
+/// only the background have an infinite depth and zero ids
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct AovPixel {
+    pub depth: f64,
+    pub normal: Vec3,
+    pub albedo: Color,
+    pub position: Point3,
+    pub material_id: u32,
+    pub object_id: u32,
+}
+
+/// First hit values of the camera samples of a pixel, summed with the
+/// absolute values of their reconstruction filter weights so the averages
+/// stay within the range of the values
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct AovSums {
+    /// Sum of the weights of the samples hitting a surface
+    pub hit_weight: f64,
+    /// Sum of the weights of all the samples
+    pub weight: f64,
+    pub depth: f64,
+    pub normal: Vec3,
+    pub albedo: Color,
+    pub position: Point3,
+}
+
+impl AovSums {
+    /// Adds the first hit of a camera ray with the filter weight of its
+    /// sample, None for a ray missing the scene
+    pub fn add(&mut self, weight: f64, ray: &Ray, record: Option<&HitRecord>) {
+        let weight = weight.abs();
+        self.weight += weight;
+        let Some(record) = record else {
+            return;
+        };
+        self.hit_weight += weight;
+        self.depth += weight * record.t * ray.dir.len();
+        self.normal += weight * record.normal;
+        self.albedo += weight * record.material.albedo(record);
+        self.position += weight * record.point;
+    }
+
+    /// Averages of the samples, the ids are left to the caller
+    pub fn average(&self) -> AovPixel {
+        let mut pixel = AovPixel::default();
+        if self.hit_weight == 0. {
+            pixel.depth = f64::INFINITY;
+            return pixel;
+        }
+        pixel.depth = self.depth / self.hit_weight;
+        if !self.normal.near_zero() {
+            pixel.normal = self.normal.unit_vector();
+        }
+        pixel.position = self.position / self.hit_weight;
+        // Samples missing the scene see a black albedo
+        pixel.albedo = self.albedo / self.weight;
+        pixel
+    }
+}
+
+/// Auxiliary outputs of an image, stored row by row from the top-left corner
+pub struct Aovs {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<AovPixel>,
+}
+
+impl Aovs {
+    pub fn pixel(&self, x: u32, y: u32) -> &AovPixel {
+        &self.pixels[(y * self.width + x) as usize]
+    }
+
+    /// Writes one output. `.pfm` files hold the raw floating point values,
+    /// other formats a visualization: normalized depth and position, normals
+    /// mapped to [0, 1] and a random color per id
+    pub fn save(&self, aov: Aov, filename: &str) -> Result<()> {
+        if filename.ends_with(".pfm") {
+            return self.save_pfm(aov, filename);
+        }
+
+        let max_depth = self
+            .pixels
+            .iter()
+            .map(|pixel| pixel.depth)
+            .filter(|depth| depth.is_finite())
+            .fold(0., f64::max);
+        let (low, high) = self.bounds();
+
+        let mut image = Image::blank(self.width as i32, self.height as i32);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let pixel = self.pixel(x, y);
+                let color = match aov {
+                    Aov::Depth if pixel.depth.is_finite() => {
+                        let value = 1. - pixel.depth / max_depth.max(1e-9);
+                        Color {
+                            x: value,
+                            y: value,
+                            z: value,
+                        }
+                    }
+                    Aov::Depth => Color::default(),
+                    Aov::Normal => {
+                        0.5 * pixel.normal
+                            + Vec3 {
+                                x: 0.5,
+                                y: 0.5,
+                                z: 0.5,
+                            }
+                    }
+                    Aov::Albedo => pixel.albedo,
+                    Aov::Position => {
+                        let extent = high - low;
+                        Color {
+                            x: (pixel.position.x - low.x) / extent.x.max(1e-9),
+                            y: (pixel.position.y - low.y) / extent.y.max(1e-9),
+                            z: (pixel.position.z - low.z) / extent.z.max(1e-9),
+                        }
+                    }
+                    Aov::MaterialId => id_color(pixel.material_id),
+                    Aov::ObjectId => id_color(pixel.object_id),
+                };
+                let to_byte = |value: f64| (255. * value.clamp(0., 1.)).round() as u8;
+                let color =
+                    raster::Color::rgb(to_byte(color.x), to_byte(color.y), to_byte(color.z));
+                image
+                    .set_pixel(x as i32, y as i32, color)
+                    .map_err(|_| Error::other("Invalid pixel"))?;
+            }
+        }
+        raster::save(&image, filename).map_err(|error| Error::other(format!("{error:?}")))
+    }
+
+    // Bounding box of the positions of the hits
+    fn bounds(&self) -> (Point3, Point3) {
+        let mut low = Point3 {
+            x: f64::INFINITY,
+            y: f64::INFINITY,
+            z: f64::INFINITY,
+        };
+        let mut high = -low;
+        for pixel in self.pixels.iter().filter(|pixel| pixel.depth.is_finite()) {
+            let p = pixel.position;
+            low = Point3 {
+                x: low.x.min(p.x),
+                y: low.y.min(p.y),
+                z: low.z.min(p.z),
+            };
+            high = Point3 {
+                x: high.x.max(p.x),
+                y: high.y.max(p.y),
+                z: high.z.max(p.z),
+            };
+        }
+        if low.x > high.x {
+            return (Point3::default(), Point3::default());
+        }
+        (low, high)
+    }
+
+    fn save_pfm(&self, aov: Aov, filename: &str) -> Result<()> {
+        let values: Vec<Vec3> = self
+            .pixels
+            .iter()
+            .map(|pixel| match aov {
+                Aov::Depth => Vec3 {
+                    x: pixel.depth,
+                    y: pixel.depth,
+                    z: pixel.depth,
+                },
+                Aov::Normal => pixel.normal,
+                Aov::Albedo => pixel.albedo,
+                Aov::Position => pixel.position,
+                Aov::MaterialId | Aov::ObjectId => {
+                    let id = if aov == Aov::MaterialId {
+                        pixel.material_id
+                    } else {
+                        pixel.object_id
+                    } as f64;
+                    Vec3 {
+                        x: id,
+                        y: id,
+                        z: id,
+                    }
+                }
+            })
+            .collect();
+        save_pfm(self.width, self.height, &values, filename)
+    }
+}
+
+/// Turns arbitrary keys into ids numbered from 1 in order of first
+/// appearance, 0 staying the background. This keeps ids stable between runs
+/// when the keys are addresses
+pub fn number_ids(keys: &[usize]) -> Vec<u32> {
+    let mut ids = HashMap::new();
+    keys.iter()
+        .map(|&key| {
+            if key == 0 {
+                return 0;
+            }
+            let next = ids.len() as u32 + 1;
+            *ids.entry(key).or_insert(next)
+        })
+        .collect()
+}
+
+// Distinct bright color for an id, black for the background
+fn id_color(id: u32) -> Color {
+    if id == 0 {
+        return Color::default();
+    }
+    let channel = |i: u64| 0.2 + 0.8 * to_unit(hash(&[id as u64, i]));
+    Color {
+        x: channel(0),
+        y: channel(1),
+        z: channel(2),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use crate::aov::*;
+
+    #[test]
+    fn test_ids_follow_first_appearance() {
+        assert_eq!(
+            number_ids(&[0, 4096, 8, 4096, 0, 8, 12]),
+            vec![0, 1, 2, 1, 0, 2, 3]
+        );
+    }
+
+    #[test]
+    fn test_pfm_layout() {
+        let mut pixels = vec![AovPixel::default(); 2];
+        pixels[0].depth = 2.;
+        pixels[1].depth = f64::INFINITY;
+        let aovs = Aovs {
+            width: 1,
+            height: 2,
+            pixels,
+        };
+        let filename = std::env::temp_dir().join("raytrace_test_depth.pfm");
+        let filename = filename.to_str().unwrap();
+        aovs.save(Aov::Depth, filename).unwrap();
+        let bytes = fs::read(filename).unwrap();
+        fs::remove_file(filename).unwrap();
+
+        let header = b"PF\n1 2\n-1.0\n";
+        assert_eq!(&bytes[..header.len()], header);
+        // The bottom row comes first
+        let first = f32::from_le_bytes(bytes[header.len()..header.len() + 4].try_into().unwrap());
+        let last = f32::from_le_bytes(bytes[bytes.len() - 4..].try_into().unwrap());
+        assert_eq!(first, f32::INFINITY);
+        assert_eq!(last, 2.);
+    }
+}
@@ -1,9 +1,163 @@
+use std::io::ErrorKind;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::animation::Track;
+use crate::aov::{number_ids, Aov, AovSums, Aovs};
+use crate::checkpoint::{Checkpoint, RenderSettings};
+use crate::denoise::Denoiser;
+use crate::environment::Environment;
+use crate::film::{reject_outliers, save_pfm, Film, Pixel};
+use crate::filter::{Filter, FilterSampler};
 use crate::interval::Interval;
+use crate::lens::LensEffects;
+use crate::lens_system::LensSystem;
+use crate::projection::Projection;
+use crate::sampler::{hash, Sampler, SamplerKind};
+use crate::sampling::power_heuristic;
+use crate::spectrum::Wavelengths;
+use crate::stereo::{Eye, Stereo, StereoLayout};
 use crate::vec3::*;
-use crate::{geometry::HittableList, ray::Ray};
+use crate::{
+    geometry::{HitRecord, HittableList},
+    ray::Ray,
+};
 use raster::error::RasterError;
 use raster::Image;
 
+/// Adaptive sampling settings: every pixel takes `min_samples`, then keeps
+/// sampling until the relative standard error of its luminance falls below
+/// `threshold` or `max_samples` are taken
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdaptiveSampling {
+    pub min_samples: u32,
+    pub max_samples: u32,
+    pub threshold: f64,
+}
+
+/// Progressive rendering settings: the whole image is rendered with 1 sample
+/// per pixel, then 2, 4... up to the target. The image file is updated with
+/// the current state at most every `snapshot_interval`, and rendering stops
+/// early once `time_budget` is spent
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProgressiveRendering {
+    pub snapshot_interval: Duration,
+    pub time_budget: Option<Duration>,
+}
+
+/// Periodically saves the state of the render to `filename` so it can be
+/// continued with `resume` after being interrupted, or to reach a higher
+/// sample count. The seed and sampler have to stay the same to resume
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpointing {
+    pub filename: String,
+    pub interval: Duration,
+    pub resume: bool,
+}
+
+/// Firefly suppression, off by default since it loses energy and biases the
+/// image. The clamps bound the largest channel of each sample's light going
+/// through direct and indirect lighting at the first hit, rescaling it to keep
+/// its hue. Pixels brighter than `outlier_ratio` times their brightest
+/// neighbor are replaced by the average of their neighbors
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct FireflySuppression {
+    pub max_direct: Option<f64>,
+    pub max_indirect: Option<f64>,
+    pub outlier_ratio: Option<f64>,
+}
+
+impl FireflySuppression {
+    fn clamp(color: Color, max: Option<f64>) -> Color {
+        let largest = color.x.max(color.y).max(color.z);
+        match max {
+            Some(max) if largest > max => (max / largest) * color,
+            _ => color,
+        }
+    }
+}
+
+/// Frames rendered by `render_frames`, frame n showing the scene at n /
+/// `frames_per_second` seconds. The shutter stays open for the `shutter`
+/// fraction of a frame, 0 giving no motion blur and 0.5 the usual 180° shutter
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameSequence {
+    pub first_frame: u32,
+    pub last_frame: u32,
+    pub frames_per_second: f64,
+    pub shutter: f64,
+}
+
+impl FrameSequence {
+    /// Inserts the frame number before the extension: `image.png` gives
+    /// `image_0012.png` for frame 12
+    pub fn file_name(filename: &str, frame: u32) -> String {
+        let path = Path::new(filename);
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let name = match path.extension() {
+            Some(extension) => format!("{stem}_{frame:04}.{}", extension.to_string_lossy()),
+            None => format!("{stem}_{frame:04}"),
+        };
+        path.with_file_name(name).to_string_lossy().into_owned()
+    }
+}
+
+/// Part of the image to render, in pixels from its top-left corner. The
+/// output only holds that part, rendered as it is in the whole image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Crop {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+// Position in the pixel and choices of the lens of a camera ray
+#[derive(Debug, Clone, Copy, Default)]
+struct CameraSample {
+    offset_u: f64,
+    offset_v: f64,
+    // Color channel traced for chromatic aberration
+    channel: Option<usize>,
+    // Picks the point of the lens, the ray going through its center without
+    lens_sample: Option<(f64, f64)>,
+    time: f64,
+}
+
+// Surface bounces and scattering events in participating media a path has
+// left, bounded separately so dense media do not end paths on their first
+// surfaces
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct PathDepth {
+    bounces: u32,
+    scattering_events: u32,
+}
+
+impl PathDepth {
+    fn bounce(self) -> Self {
+        PathDepth {
+            bounces: self.bounces - 1,
+            ..self
+        }
+    }
+}
+
+// Luminance under which pixels are considered black when estimating the
+// relative error, so dark pixels do not take all the samples
+const ADAPTIVE_MIN_LUMINANCE: f64 = 0.01;
+
+// Parameters of the rays along which the first hit is searched
+const FIRST_HIT: Interval = Interval {
+    min: 0.001,
+    max: f64::INFINITY,
+};
+
+// Rows each thread renders between checks of the time budget and checkpoints
+const ROWS_PER_THREAD: u32 = 4;
+
 #[derive(Default)]
 pub struct Camera {
     aspect_ratio: f64,
@@ -14,8 +168,36 @@ pub struct Camera {
     pixel_delta_u: Point3,
     pixel_delta_v: Point3,
     samples_per_pixel: u32,
-    pixel_samples_scale: f64,
     max_depth: u32,
+    max_scattering_events: u32,
+    environment: Box<dyn Environment>,
+    spectral: bool,
+    sampler: SamplerKind,
+    seed: u64,
+    adaptive: Option<AdaptiveSampling>,
+    progressive: Option<ProgressiveRendering>,
+    checkpointing: Option<Checkpointing>,
+    sample_count_file: Option<String>,
+    aov_outputs: Vec<(Aov, String)>,
+    denoiser: Option<Denoiser>,
+    filter: FilterSampler,
+    fireflies: FireflySuppression,
+    projection: Projection,
+    stereo: Option<Stereo>,
+    lens: LensEffects,
+    lens_system: Option<LensSystem>,
+    // Eye being rendered in stereo mode
+    eye: Option<Eye>,
+    look_from: Track<Point3>,
+    look_at: Track<Point3>,
+    view_up: Vec3,
+    vertical_fov: Track<f64>,
+    // The camera rays see the scene from `shutter_open` to `shutter_open +
+    // shutter_duration` seconds
+    shutter_open: f64,
+    shutter_duration: f64,
+    threads: usize,
+    crop: Option<Crop>,
 }
 
 impl Camera {
@@ -25,10 +207,170 @@ impl Camera {
             image_width,
             samples_per_pixel: 100,
             max_depth: 50,
+            max_scattering_events: 256,
+            look_at: Track::constant(Point3 {
+                x: 0.,
+                y: 0.,
+                z: -1.,
+            }),
+            view_up: Vec3 {
+                x: 0.,
+                y: 1.,
+                z: 0.,
+            },
+            vertical_fov: Track::constant(90.),
+            threads: thread::available_parallelism().map_or(1, |threads| threads.get()),
             ..Default::default()
         }
     }
 
+    /// Position of the camera, the origin by default. Takes a point or a
+    /// track to animate it
+    pub fn set_look_from(&mut self, look_from: impl Into<Track<Point3>>) {
+        self.look_from = look_from.into();
+    }
+
+    /// Point the camera looks at, down the -z axis by default
+    pub fn set_look_at(&mut self, look_at: impl Into<Track<Point3>>) {
+        self.look_at = look_at.into();
+    }
+
+    /// Direction appearing up in the image, +y by default
+    pub fn set_view_up(&mut self, view_up: Vec3) {
+        self.view_up = view_up;
+    }
+
+    /// Vertical field of view in degrees of the perspective projection, 90
+    /// by default
+    pub fn set_vertical_fov(&mut self, vertical_fov: impl Into<Track<f64>>) {
+        self.vertical_fov = vertical_fov.into();
+    }
+
+    /// Moment in seconds the image shows, and how long the shutter stays
+    /// open to blur what moves
+    pub fn set_shutter(&mut self, open: f64, duration: f64) {
+        self.shutter_open = open;
+        self.shutter_duration = duration.max(0.);
+    }
+
+    /// Maximum number of bounces of the rays, 50 by default
+    pub fn set_max_depth(&mut self, max_depth: u32) {
+        self.max_depth = max_depth;
+    }
+
+    /// Maximum number of times a ray scatters inside participating media,
+    /// counted apart from the bounces on surfaces, 256 by default
+    pub fn set_max_scattering_events(&mut self, max_scattering_events: u32) {
+        self.max_scattering_events = max_scattering_events;
+    }
+
+    /// Number of threads rendering the image, all the processors by default.
+    /// The image does not depend on it
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads.max(1);
+    }
+
+    /// Only renders a part of the image. The crop is clipped to the image
+    pub fn set_crop(&mut self, crop: Crop) {
+        self.crop = Some(crop);
+    }
+
+    /// Replaces the default sky gradient seen by rays leaving the scene
+    pub fn set_environment(&mut self, environment: impl Environment + 'static) {
+        self.environment = Box::new(environment);
+    }
+
+    /// Traces each sample at a few random wavelengths instead of RGB, needed
+    /// for dispersion and more accurate colors through interference
+    pub fn set_spectral(&mut self, spectral: bool) {
+        self.spectral = spectral;
+    }
+
+    /// Selects how the random numbers of the samples are generated. Low
+    /// discrepancy sequences give less noise with the same number of samples
+    pub fn set_sampler(&mut self, sampler: SamplerKind) {
+        self.sampler = sampler;
+    }
+
+    /// Every sample draws its random numbers from a stream derived from the
+    /// seed, the same seed and settings always give the same image
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    /// Target number of samples per pixel, 100 by default
+    pub fn set_samples_per_pixel(&mut self, samples_per_pixel: u32) {
+        self.samples_per_pixel = samples_per_pixel.max(1);
+    }
+
+    /// Shows the image while it converges instead of only at the end
+    pub fn set_progressive(&mut self, progressive: ProgressiveRendering) {
+        self.progressive = Some(progressive);
+    }
+
+    pub fn set_checkpointing(&mut self, checkpointing: Checkpointing) {
+        self.checkpointing = Some(checkpointing);
+    }
+
+    /// Spends the samples where the image is noisy instead of taking the same
+    /// number in every pixel
+    pub fn set_adaptive_sampling(&mut self, adaptive: AdaptiveSampling) {
+        self.adaptive = Some(adaptive);
+    }
+
+    /// Also writes an auxiliary output of the first hits to `filename`, see
+    /// `Aovs::save` for the formats
+    pub fn add_aov_output(&mut self, aov: Aov, filename: &str) {
+        self.aov_outputs.push((aov, filename.to_string()));
+    }
+
+    /// Also writes a grayscale image of the samples taken in each pixel, white
+    /// being the maximum, to tune adaptive sampling
+    pub fn set_sample_count_output(&mut self, filename: &str) {
+        self.sample_count_file = Some(filename.to_string());
+    }
+
+    /// Selects between the pinhole camera, orthographic views, fisheye lenses
+    /// and panoramas
+    pub fn set_projection(&mut self, projection: Projection) {
+        self.projection = projection;
+    }
+
+    /// Distortion, vignetting, chromatic aberration and depth of field of a
+    /// photographic lens
+    pub fn set_lens_effects(&mut self, lens: LensEffects) {
+        self.lens = lens;
+    }
+
+    /// Traces the camera rays through the elements of a real lens instead of
+    /// using the projection and the thin lens aperture
+    pub fn set_lens_system(&mut self, lens_system: LensSystem) {
+        self.lens_system = Some(lens_system);
+    }
+
+    /// Renders a pair of images for the left and right eyes. Snapshots,
+    /// checkpoints and auxiliary outputs get one file per eye
+    pub fn set_stereo(&mut self, stereo: Stereo) {
+        self.stereo = Some(stereo);
+    }
+
+    /// Reconstruction filter weighting the samples of each pixel, a box
+    /// covering the pixel by default. Panics on invalid filter parameters,
+    /// see `FilterSampler::new`
+    pub fn set_filter(&mut self, filter: Filter) {
+        self.filter = FilterSampler::new(filter);
+    }
+
+    pub fn set_firefly_suppression(&mut self, fireflies: FireflySuppression) {
+        self.fireflies = fireflies;
+    }
+
+    /// Filters the noise out of the final image, guided by the normals and
+    /// albedos of the first hits. Snapshots are saved without denoising
+    pub fn set_denoiser(&mut self, denoiser: Denoiser) {
+        self.denoiser = Some(denoiser);
+    }
+
     fn linear_to_gamma(linear_component: f64) -> f64 {
         if linear_component > 0. {
             linear_component.sqrt()
@@ -53,37 +395,436 @@ impl Camera {
     }
 
     pub fn render_to_file(&mut self, filename: &str, world: &HittableList) {
-        self.initialize();
+        let Some(stereo) = self.stereo else {
+            let colors = self.render_view(filename, world);
+            let region = self.region();
+            Self::save_final_image(region.width, region.height, &colors, filename);
+            return;
+        };
+
+        let mut views = Vec::new();
+        for eye in [Eye::Left, Eye::Right] {
+            self.eye = Some(eye);
+            let eye_filename = eye.file_name(filename);
+            let colors = self.render_view(&eye_filename, world);
+            if stereo.layout == StereoLayout::SeparateFiles {
+                let region = self.region();
+                Self::save_final_image(region.width, region.height, &colors, &eye_filename);
+            }
+            views.push(colors);
+        }
+        self.eye = None;
+
+        if stereo.layout != StereoLayout::SeparateFiles {
+            let region = self.region();
+            let (width, height, colors) =
+                stereo.combine(region.width, region.height, &views[0], &views[1]);
+            Self::save_final_image(width, height, &colors, filename);
+        }
+    }
+
+    /// Renders an animation to numbered files, see `FrameSequence::file_name`.
+    /// The noise changes from frame to frame, as with film grain
+    pub fn render_frames(&mut self, filename: &str, world: &HittableList, frames: FrameSequence) {
+        let seed = self.seed;
+        let frame_duration = 1. / frames.frames_per_second;
+        for frame in frames.first_frame..=frames.last_frame {
+            self.set_shutter(
+                frame as f64 * frame_duration,
+                frames.shutter * frame_duration,
+            );
+            self.seed = hash(&[seed, frame as u64]);
+            self.render_to_file(&FrameSequence::file_name(filename, frame), world);
+        }
+        self.seed = seed;
+    }
 
+    // Renders the image and its auxiliary outputs, and returns the final
+    // colors after the post processing
+    fn render_view(&mut self, filename: &str, world: &HittableList) -> Vec<Color> {
         println!("Rendering to the file {filename}");
 
-        let mut image = Image::blank(self.image_width as i32, self.image_height as i32);
+        let snapshot_interval = self
+            .progressive
+            .map(|progressive| progressive.snapshot_interval);
+        let mut last_snapshot = Instant::now();
+        let film = self.render(world, |film| {
+            if snapshot_interval.is_some_and(|interval| last_snapshot.elapsed() >= interval) {
+                if Self::save_image(film, filename).is_err() {
+                    println!("Error saving a snapshot.");
+                }
+                last_snapshot = Instant::now();
+            }
+        });
+
+        let mut colors = film.colors();
+        if let Some(ratio) = self.fireflies.outlier_ratio {
+            reject_outliers(&mut colors, film.width, film.height, ratio);
+        }
+        if self.renders_aovs() {
+            let aovs = self.aovs(&film, world);
+            for (aov, aov_file) in &self.aov_outputs {
+                if aovs.save(*aov, &self.eye_file(aov_file)).is_err() {
+                    println!("Error saving the {aov:?} output.");
+                }
+            }
+            if let Some(denoiser) = &self.denoiser {
+                println!("Denoising");
+                colors = denoiser.denoise(&colors, &aovs);
+            }
+        }
+        if let Some(sample_count_file) = &self.sample_count_file {
+            if Self::save_sample_counts(&film, &self.eye_file(sample_count_file)).is_err() {
+                println!("Error saving the sample counts.");
+            }
+        }
+        colors
+    }
+
+    fn save_final_image(width: u32, height: u32, colors: &[Color], filename: &str) {
+        if Self::save_colors(width, height, colors, filename).is_ok() {
+            println!("Done!");
+        } else {
+            println!("Error saving the image. Aborting...");
+        }
+    }
+
+    // Output file of the eye being rendered in stereo mode
+    fn eye_file(&self, filename: &str) -> String {
+        match self.eye {
+            Some(eye) => eye.file_name(filename),
+            None => filename.to_string(),
+        }
+    }
+
+    /// Renders the image into a film. In progressive mode `pass_done` is
+    /// called with the film after every pass
+    pub fn render(&mut self, world: &HittableList, mut pass_done: impl FnMut(&Film)) -> Film {
+        self.initialize();
+
+        let (_, max_samples) = self.sample_range();
+        let region = self.region();
+        let mut film = self
+            .resumed_film()
+            .unwrap_or_else(|| Film::new(region.width, region.height));
+        let start = Instant::now();
+        let mut last_checkpoint = start;
+
+        // Passes double the number of samples until the target is reached
+        let mut pass_samples = if self.progressive.is_some() {
+            1
+        } else {
+            max_samples
+        };
+        'passes: loop {
+            let target = pass_samples.min(max_samples);
+            if self.progressive.is_some() {
+                println!("Rendering {target} samples per pixel");
+            }
+
+            let mut j = 0;
+            while j < region.height {
+                let out_of_time = self
+                    .progressive
+                    .and_then(|progressive| progressive.time_budget)
+                    .is_some_and(|budget| start.elapsed() >= budget);
+                if out_of_time {
+                    println!("Time budget exhausted");
+                    break 'passes;
+                }
+
+                if let Some(checkpointing) = &self.checkpointing {
+                    if last_checkpoint.elapsed() >= checkpointing.interval {
+                        self.save_checkpoint(&film);
+                        last_checkpoint = Instant::now();
+                    }
+                }
+
+                if self.progressive.is_none() {
+                    let remaining = region.height - j;
+                    println!("Scanlines remaining: {remaining}");
+                }
+
+                let rows = j..(j + ROWS_PER_THREAD * self.threads as u32).min(region.height);
+                j = rows.end;
+                self.render_rows(rows, target, &mut film, world);
+            }
+
+            if target == max_samples {
+                break;
+            }
+            pass_done(&film);
+            pass_samples *= 2;
+        }
+
+        if self.checkpointing.is_some() {
+            self.save_checkpoint(&film);
+        }
+        film
+    }
 
-        for j in 0..self.image_height {
-            let remaining = self.image_height - j;
+    // Renders the rows of the film in parallel, each thread taking the next
+    // row left when done with one
+    fn render_rows(&self, rows: Range<u32>, target: u32, film: &mut Film, world: &HittableList) {
+        let (_, max_samples) = self.sample_range();
+        let region = self.region();
+        let width = film.width as usize;
+        let pixels = &mut film.pixels[rows.start as usize * width..rows.end as usize * width];
+        let threads = self.threads.min(rows.len());
+        let rows = Mutex::new(pixels.chunks_mut(width).zip(rows));
 
-            println!("Scanlines remaining: {remaining}");
+        thread::scope(|scope| {
+            for _ in 0..threads {
+                scope.spawn(|| {
+                    let mut sampler = self.sampler.create(max_samples, self.seed);
+                    loop {
+                        let Some((row, j)) = rows.lock().unwrap().next() else {
+                            break;
+                        };
+                        for (i, pixel) in (region.x..).zip(row) {
+                            let y = region.y + j;
+                            self.render_pixel(i, y, target, pixel, world, sampler.as_mut());
+                        }
+                    }
+                });
+            }
+        });
+    }
+
+    // Part of the image rendered, the whole image without a crop
+    fn region(&self) -> Crop {
+        let Some(crop) = self.crop else {
+            return Crop {
+                x: 0,
+                y: 0,
+                width: self.image_width,
+                height: self.image_height,
+            };
+        };
+        let x = crop.x.min(self.image_width - 1);
+        let y = crop.y.min(self.image_height - 1);
+        Crop {
+            x,
+            y,
+            width: crop.width.clamp(1, self.image_width - x),
+            height: crop.height.clamp(1, self.image_height - y),
+        }
+    }
 
-            for i in 0..self.image_width {
-                let mut pixel_color = Color::default();
-                for _ in 0..self.samples_per_pixel {
-                    let mut ray = self.get_ray_from_pixel_position(i, j);
-                    pixel_color += Self::ray_color(&mut ray, self.max_depth, world);
+    // Film of the checkpoint to resume from, if there is a matching one
+    fn resumed_film(&self) -> Option<Film> {
+        let checkpointing = self.checkpointing.as_ref().filter(|c| c.resume)?;
+        let filename = self.eye_file(&checkpointing.filename);
+        match Checkpoint::load(&filename) {
+            Ok(checkpoint) if self.render_settings().can_resume(&checkpoint.settings) => {
+                println!("Resuming from the checkpoint {filename}");
+                Some(checkpoint.film)
+            }
+            Ok(_) => {
+                println!("The checkpoint does not match the render settings, starting over");
+                None
+            }
+            Err(error) if error.kind() == ErrorKind::NotFound => None,
+            Err(error) => {
+                println!("Error loading the checkpoint ({error}), starting over");
+                None
+            }
+        }
+    }
+
+    // Settings stored in checkpoints, the render has to share them to resume
+    fn render_settings(&self) -> RenderSettings {
+        let (_, max_samples) = self.sample_range();
+        RenderSettings {
+            seed: self.seed,
+            sampler: self.sampler,
+            sampler_samples: max_samples,
+            spectral: self.spectral,
+            aovs: self.renders_aovs(),
+            max_depth: self.max_depth,
+            max_scattering_events: self.max_scattering_events,
+            filter: self.filter.filter,
+            image_width: self.image_width,
+            image_height: self.image_height,
+            region: self.region(),
+        }
+    }
+
+    fn save_checkpoint(&self, film: &Film) {
+        let Some(checkpointing) = &self.checkpointing else {
+            return;
+        };
+        let checkpoint = Checkpoint {
+            settings: self.render_settings(),
+            film: film.clone(),
+        };
+        if checkpoint
+            .save(&self.eye_file(&checkpointing.filename))
+            .is_err()
+        {
+            println!("Error saving the checkpoint.");
+        }
+    }
+
+    // Whether the samples also accumulate the auxiliary outputs
+    fn renders_aovs(&self) -> bool {
+        !self.aov_outputs.is_empty() || self.denoiser.is_some()
+    }
+
+    /// Auxiliary outputs of a film rendered with some requested. The ids come
+    /// from the first camera ray of each pixel, traced again as they can not
+    /// be averaged or stored in checkpoints
+    pub fn aovs(&self, film: &Film, world: &HittableList) -> Aovs {
+        let (_, max_samples) = self.sample_range();
+        let mut sampler = self.sampler.create(max_samples, self.seed);
+        let region = self.region();
+        let mut pixels = Vec::new();
+        let mut material_keys = Vec::new();
+        for j in 0..region.height {
+            for i in 0..region.width {
+                let mut pixel = film.pixel(i, j).aov.average();
+                let mut material_key = 0;
+                let (ray, _, _) = self.camera_ray(region.x + i, region.y + j, 0, sampler.as_mut());
+                if let Some(mut ray) = ray {
+                    if let Some(record) = world.hit(&mut ray, &FIRST_HIT) {
+                        material_key = Arc::as_ptr(&record.material) as *const () as usize;
+                        pixel.object_id = record.object_id as u32 + 1;
+                    }
                 }
+                pixels.push(pixel);
+                material_keys.push(material_key);
+            }
+        }
+
+        for (pixel, material_id) in pixels.iter_mut().zip(number_ids(&material_keys)) {
+            pixel.material_id = material_id;
+        }
+        Aovs {
+            width: region.width,
+            height: region.height,
+            pixels,
+        }
+    }
+
+    fn save_image(film: &Film, filename: &str) -> Result<(), RasterError> {
+        Self::save_colors(film.width, film.height, &film.colors(), filename)
+    }
 
-                if Self::write_color(&mut image, self.pixel_samples_scale * pixel_color, i, j)
-                    .is_err()
+    // Writes an image stored row by row from the top-left corner. `.pfm`
+    // files keep the linear colors
+    fn save_colors(
+        width: u32,
+        height: u32,
+        colors: &[Color],
+        filename: &str,
+    ) -> Result<(), RasterError> {
+        if filename.ends_with(".pfm") {
+            return save_pfm(width, height, colors, filename).map_err(RasterError::Io);
+        }
+        let mut image = Image::blank(width as i32, height as i32);
+        for j in 0..height {
+            for i in 0..width {
+                Self::write_color(&mut image, colors[(j * width + i) as usize], i, j)?;
+            }
+        }
+        raster::save(&image, filename)
+    }
+
+    fn save_sample_counts(film: &Film, filename: &str) -> Result<(), RasterError> {
+        let mut image = Image::blank(film.width as i32, film.height as i32);
+        let max_samples = film.max_samples().max(1);
+        for j in 0..film.height {
+            for i in 0..film.width {
+                let count = (255 * film.pixel(i, j).samples / max_samples) as u8;
+                image.set_pixel(i as i32, j as i32, raster::Color::rgb(count, count, count))?;
+            }
+        }
+        raster::save(&image, filename)
+    }
+
+    // Minimum and maximum number of samples per pixel
+    fn sample_range(&self) -> (u32, u32) {
+        match self.adaptive {
+            Some(adaptive) => {
+                let min_samples = adaptive.min_samples.max(2);
+                (min_samples, adaptive.max_samples.max(min_samples))
+            }
+            None => (self.samples_per_pixel, self.samples_per_pixel),
+        }
+    }
+
+    // Adds samples to the pixel until it holds `target` of them, or it is
+    // converged when sampling adaptively
+    fn render_pixel(
+        &self,
+        x: u32,
+        y: u32,
+        target: u32,
+        pixel: &mut Pixel,
+        world: &HittableList,
+        sampler: &mut dyn Sampler,
+    ) {
+        let (min_samples, _) = self.sample_range();
+        let renders_aovs = self.renders_aovs();
+        while pixel.samples < target {
+            if let Some(adaptive) = self.adaptive {
+                if pixel.samples >= min_samples
+                    && pixel.relative_error(ADAPTIVE_MIN_LUMINANCE) < adaptive.threshold
                 {
-                    println!("Error writing to the image. Aborting...");
                     return;
                 }
             }
+            let aov = renders_aovs.then_some(&mut pixel.aov);
+            let (color, weight) = self.sample_pixel(x, y, pixel.samples, world, sampler, aov);
+            pixel.add_weighted(color, weight);
         }
-        if raster::save(&image, filename).is_ok() {
-            println!("Done!");
+    }
+
+    // Color of one sample and its reconstruction filter weight. The first hit
+    // of the camera ray is added to `aov` when given
+    fn sample_pixel(
+        &self,
+        x: u32,
+        y: u32,
+        index: u32,
+        world: &HittableList,
+        sampler: &mut dyn Sampler,
+        aov: Option<&mut AovSums>,
+    ) -> (Color, f64) {
+        let (ray, weight, throughput) = self.camera_ray(x, y, index, sampler);
+        let Some(mut ray) = ray else {
+            return (Color::default(), weight);
+        };
+        if let Some(aov) = aov {
+            let mut first_ray = ray.spawn(ray.orig, ray.dir);
+            let record = world.hit(&mut first_ray, &FIRST_HIT);
+            aov.add(weight, &first_ray, record.as_ref());
+        }
+        let color = if self.spectral {
+            let wavelengths = Wavelengths::sample(sampler.get_1d());
+            ray.wavelengths = Some(wavelengths);
+            let radiance = self.ray_color(&mut ray, self.path_start(), world, None, sampler);
+            wavelengths.to_rgb(&radiance)
         } else {
-            println!("Error saving the image. Aborting...");
+            self.ray_color(&mut ray, self.path_start(), world, None, sampler)
+        };
+        (throughput * color, weight)
+    }
+
+    // Camera ray of a sample of a pixel, see `get_ray_from_pixel_position`
+    fn camera_ray(
+        &self,
+        x: u32,
+        y: u32,
+        index: u32,
+        sampler: &mut dyn Sampler,
+    ) -> (Option<Ray>, f64, Color) {
+        sampler.start_pixel_sample(x, y, index);
+        let (mut ray, weight, throughput) = self.get_ray_from_pixel_position(x, y, sampler);
+        if let Some(ray) = &mut ray {
+            ray.sample_id = hash(&[self.seed, x as u64, y as u64, index as u64]);
         }
+        (ray, weight, throughput)
     }
 
     pub fn initialize(&mut self) {
@@ -92,8 +833,6 @@ impl Camera {
             self.image_height = 1;
         }
 
-        self.pixel_samples_scale = 1.0 / self.samples_per_pixel as f64;
-
         self.camera_center = Point3 {
             x: 0.,
             y: 0.,
@@ -138,72 +877,779 @@ impl Camera {
         self.pixel00_loc = viewport_upper_left + 0.5 * (self.pixel_delta_u + self.pixel_delta_v);
     }
 
-    fn ray_color(ray: &mut Ray, depth: u32, world: &HittableList) -> Color {
+    // Bounces and scattering events left to a camera ray
+    fn path_start(&self) -> PathDepth {
+        PathDepth {
+            bounces: self.max_depth,
+            scattering_events: self.max_scattering_events,
+        }
+    }
+
+    // `scatter_pdf` is the density the previous bounce sampled the ray with,
+    // None for camera rays and mirror-like bounces
+    fn ray_color(
+        &self,
+        ray: &mut Ray,
+        depth: PathDepth,
+        world: &HittableList,
+        scatter_pdf: Option<f64>,
+        sampler: &mut dyn Sampler,
+    ) -> Color {
         // When too many colisions - return black
-        if depth == 0 {
+        if depth.bounces == 0 {
             return Color::default();
         }
+        let first_hit = depth == self.path_start();
 
-        if let Some(record) = world.hit(
+        let hit = world.hit(
             ray,
             &Interval {
                 min: 0.001,
                 max: f64::INFINITY,
             },
-        ) {
-            if let Some((attenuation, mut scattered)) = record.material.scatter(ray, &record) {
-                return attenuation * Self::ray_color(&mut scattered, depth - 1, world);
+        );
+
+        // Inside a medium light may scatter before reaching the next surface
+        let mut transmittance = Color {
+            x: 1.,
+            y: 1.,
+            z: 1.,
+        };
+        if let Some(medium) = ray.medium {
+            let t_max = hit.as_ref().map_or(f64::INFINITY, |record| record.t);
+            let (scatter_t, weight) = medium.sample(ray, t_max, sampler);
+            if let Some(t) = scatter_t {
+                if depth.scattering_events == 0 {
+                    return Color::default();
+                }
+                let depth = PathDepth {
+                    scattering_events: depth.scattering_events - 1,
+                    ..depth
+                };
+                let mut scattered = ray.spawn(ray.at(t), Vec3::random_unit_vector(sampler));
+                let indirect = weight * self.ray_color(&mut scattered, depth, world, None, sampler);
+                return if first_hit {
+                    FireflySuppression::clamp(indirect, self.fireflies.max_indirect)
+                } else {
+                    indirect
+                };
+            }
+            transmittance = weight;
+        }
+
+        if let Some(record) = hit {
+            if let Some((attenuation, mut scattered)) =
+                record.material.scatter(ray, &record, sampler)
+            {
+                let direct = self.sample_environment(ray, &record, world, sampler);
+                let pdf = record
+                    .material
+                    .eval(ray, &record, &scattered.dir)
+                    .map(|(_, pdf)| pdf);
+                let direct = transmittance * direct;
+                let indirect = transmittance
+                    * attenuation
+                    * self.ray_color(&mut scattered, depth.bounce(), world, pdf, sampler);
+                // Only the light reaching the camera through the first hit is
+                // clamped, deeper bounces are part of its indirect light
+                if first_hit {
+                    return FireflySuppression::clamp(direct, self.fireflies.max_direct)
+                        + FireflySuppression::clamp(indirect, self.fireflies.max_indirect);
+                }
+                return direct + indirect;
             } else {
                 return Color::default();
             }
-            // let direction = record.normal + Vec3::random_unit();
-            // return 0.5
-            //     * Self::ray_color(
-            //         &mut Ray {
-            //             orig: record.point,
-            //             dir: direction,
-            //         },
-            //         depth - 1,
-            //         world,
-            //     );
-        }
-
-        let unit_direction = ray.dir.unit_vector();
-        let coeff = 0.5 * (unit_direction.y + 1.0);
-        (1.0 - coeff)
-            * Color {
-                x: 1.0,
-                y: 1.0,
-                z: 1.0,
-            }
-            + coeff
-                * Color {
-                    x: 0.5,
-                    y: 0.7,
-                    z: 1.0,
-                }
+        }
+
+        let background = ray.sample_color(&self.environment.color(&ray.dir));
+        transmittance
+            * match scatter_pdf {
+                // The same direction could have been picked by environment sampling
+                Some(pdf) => power_heuristic(pdf, self.environment.pdf(&ray.dir)) * background,
+                None => background,
+            }
     }
 
-    fn sample_square() -> Vec3 {
-        Vec3 {
-            x: rand::random::<f64>() - 0.5,
-            y: rand::random::<f64>() - 0.5,
-            z: 0.,
+    // Next event estimation: light arriving directly from the environment
+    fn sample_environment(
+        &self,
+        ray: &Ray,
+        record: &HitRecord,
+        world: &HittableList,
+        sampler: &mut dyn Sampler,
+    ) -> Color {
+        let Some((direction, radiance, light_pdf)) = self.environment.sample(sampler) else {
+            return Color::default();
+        };
+        let Some((bsdf, scatter_pdf)) = record.material.eval(ray, record, &direction) else {
+            return Color::default();
+        };
+
+        let mut shadow_ray = ray.spawn(record.point, direction);
+        let occluded = world
+            .hit(
+                &mut shadow_ray,
+                &Interval {
+                    min: 0.001,
+                    max: f64::INFINITY,
+                },
+            )
+            .is_some();
+        if occluded {
+            return Color::default();
         }
+        // The light reaches the surface through the medium around it
+        let transmittance = match shadow_ray.medium {
+            Some(medium) => medium.transmittance(&shadow_ray, f64::INFINITY),
+            None => Color {
+                x: 1.,
+                y: 1.,
+                z: 1.,
+            },
+        };
+
+        (power_heuristic(light_pdf, scatter_pdf) / light_pdf)
+            * transmittance
+            * bsdf
+            * ray.sample_color(&radiance)
+    }
+
+    // Ray through a point around the pixel sampled following the filter,
+    // with the weight of the sample
+    // Also returns the fraction of the light of the ray reaching the pixel
+    // through the lens, which keeps a single channel with chromatic aberration
+    fn get_ray_from_pixel_position(
+        &self,
+        x: u32,
+        y: u32,
+        sampler: &mut dyn Sampler,
+    ) -> (Option<Ray>, f64, Color) {
+        let (offset_u, offset_v, weight) = self.filter.sample(sampler.get_2d());
+        let channel = self
+            .lens
+            .splits_channels()
+            .then(|| ((3. * sampler.get_1d()) as usize).min(2));
+        let lens_sample =
+            (self.lens.aperture.is_some() || self.lens_system.is_some()).then(|| sampler.get_2d());
+        let time = if self.shutter_duration > 0. {
+            self.shutter_open + self.shutter_duration * sampler.get_1d()
+        } else {
+            self.shutter_open
+        };
+
+        let sample = CameraSample {
+            offset_u,
+            offset_v,
+            channel,
+            lens_sample,
+            time,
+        };
+        let Some((origin, direction)) = self.get_ray_through(x, y, &sample) else {
+            return (None, weight, Color::default());
+        };
+        let vignetting = self.lens.vignetting(&direction);
+        let throughput = match channel {
+            // Each channel is traced by a third of the samples
+            Some(channel) => {
+                let mut throughput = [0.; 3];
+                throughput[channel] = 3. * vignetting;
+                Color {
+                    x: throughput[0],
+                    y: throughput[1],
+                    z: throughput[2],
+                }
+            }
+            None => Color {
+                x: vignetting,
+                y: vignetting,
+                z: vignetting,
+            },
+        };
+        (
+            Some(self.to_world(origin, direction, time)),
+            weight,
+            throughput,
+        )
     }
 
-    fn get_ray_from_pixel_position(&self, x: u32, y: u32) -> Ray {
-        let offset = Self::sample_square();
+    // Origin and direction in camera space of the ray through the point of
+    // the pixel (x, y) at the offset of the sample from its center, None where
+    // the projection does not cover the image
+    fn get_ray_through(&self, x: u32, y: u32, sample: &CameraSample) -> Option<(Point3, Vec3)> {
         let pixel_sample = self.pixel00_loc
-            + ((x as f64 + offset.x) * self.pixel_delta_u)
-            + ((y as f64 + offset.y) * self.pixel_delta_v);
+            + ((x as f64 + sample.offset_u) * self.pixel_delta_u)
+            + ((y as f64 + sample.offset_v) * self.pixel_delta_v);
 
-        let ray_origin = self.camera_center;
-        let ray_direction = pixel_sample - ray_origin;
+        let viewport_point = pixel_sample - self.camera_center;
+        let half_width = self.pixel_delta_u.x * self.image_width as f64 / 2.;
+        let (viewport_x, viewport_y) =
+            self.lens
+                .distort(viewport_point.x, viewport_point.y, sample.channel);
+        let (mut origin, mut direction) = match &self.lens_system {
+            Some(lens_system) => {
+                lens_system.ray(viewport_x, viewport_y, half_width, sample.lens_sample)?
+            }
+            None => self.projection.ray(viewport_x, viewport_y, half_width)?,
+        };
+        if self.projection == Projection::Perspective && self.lens_system.is_none() {
+            let scale = (self.vertical_fov.value(sample.time).to_radians() / 2.).tan();
+            direction.x *= scale;
+            direction.y *= scale;
+        }
+        if let (Some(stereo), Some(eye)) = (self.stereo, self.eye) {
+            (origin, direction) = stereo.eye_ray(eye, &self.projection, origin, direction);
+        }
+        if let (Some(aperture), Some(lens_sample), None) =
+            (self.lens.aperture, sample.lens_sample, &self.lens_system)
+        {
+            let lens_point = aperture.sample(lens_sample);
+            (origin, direction) = aperture.defocus(origin, direction, lens_point);
+        }
 
-        Ray {
-            orig: ray_origin,
-            dir: ray_direction,
+        Some((origin, direction))
+    }
+
+    // Camera space ray placed in the scene where the camera is at `time`
+    fn to_world(&self, origin: Point3, direction: Vec3, time: f64) -> Ray {
+        let look_from = self.look_from.value(time);
+        let w = (look_from - self.look_at.value(time)).unit_vector();
+        let mut u = self.view_up.cross(&w);
+        if u.near_zero() {
+            // Looking along the up direction, which leaves the roll of the
+            // camera free: take the axis furthest from the view direction
+            let [x, y, z] = [w.x.abs(), w.y.abs(), w.z.abs()];
+            let axis = if x <= y && x <= z {
+                Vec3 {
+                    x: 1.,
+                    y: 0.,
+                    z: 0.,
+                }
+            } else if y <= z {
+                Vec3 {
+                    x: 0.,
+                    y: 1.,
+                    z: 0.,
+                }
+            } else {
+                Vec3 {
+                    x: 0.,
+                    y: 0.,
+                    z: 1.,
+                }
+            };
+            u = axis.cross(&w);
+        }
+        let u = u.unit_vector();
+        let v = w.cross(&u);
+        let to_world = |a: Vec3| a.x * u + a.y * v + a.z * w;
+
+        let mut ray = Ray::new(look_from + to_world(origin), to_world(direction));
+        ray.time = time;
+        ray
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::animation::Interpolation;
+    use crate::camera::*;
+    use crate::environment::ConstantEnvironment;
+    use crate::geometry::Sphere;
+    use crate::lens::Aperture;
+    use crate::material::{Lambertian, Subsurface};
+    use crate::sampler::IndependentSampler;
+
+    // Film accumulating the auxiliary outputs, `render` does not write them
+    fn render_film_with_aovs(camera: &mut Camera, world: &HittableList) -> Film {
+        camera.add_aov_output(Aov::Depth, "unused.pfm");
+        camera.render(world, |_| {})
+    }
+
+    fn render_aovs(camera: &mut Camera, world: &HittableList) -> Aovs {
+        let film = render_film_with_aovs(camera, world);
+        camera.aovs(&film, world)
+    }
+
+    fn adaptive_camera() -> Camera {
+        let mut camera = Camera::new(1., 10);
+        camera.set_adaptive_sampling(AdaptiveSampling {
+            min_samples: 8,
+            max_samples: 64,
+            threshold: 0.01,
+        });
+        camera.initialize();
+        camera
+    }
+
+    #[test]
+    fn test_flat_pixel_stops_at_minimum() {
+        let mut camera = adaptive_camera();
+        camera.set_environment(ConstantEnvironment {
+            color: Color {
+                x: 0.5,
+                y: 0.5,
+                z: 0.5,
+            },
+        });
+        let world = HittableList::new();
+        let mut pixel = Pixel::default();
+        let mut sampler = IndependentSampler::new(0);
+        camera.render_pixel(5, 5, 64, &mut pixel, &world, &mut sampler);
+        assert_eq!(pixel.samples, 8);
+        assert!((pixel.color().x - 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_filters_keep_flat_colors() {
+        let color = Color {
+            x: 0.2,
+            y: 0.5,
+            z: 0.8,
+        };
+        for filter in [Filter::Tent { radius: 1. }, Filter::mitchell(2.)] {
+            let mut camera = Camera::new(1., 4);
+            camera.set_environment(ConstantEnvironment { color });
+            camera.set_samples_per_pixel(16);
+            camera.set_filter(filter);
+            let film = camera.render(&HittableList::new(), |_| {});
+            for pixel in &film.pixels {
+                assert!((pixel.color() - color).len() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn test_negative_lobes_with_few_samples() {
+        let mut world = HittableList::new();
+        world.add(Sphere {
+            center: Point3 {
+                x: 0.,
+                y: 0.,
+                z: -1.,
+            },
+            radius: 0.5,
+            material: Arc::new(Lambertian {
+                albedo: Color::default(),
+            }),
+        });
+        let sky = Color {
+            x: 1.,
+            y: 1.,
+            z: 1.,
+        };
+        for filter in [Filter::mitchell(2.), Filter::lanczos(3.)] {
+            for samples in [1, 2, 3] {
+                let mut camera = Camera::new(1., 8);
+                camera.set_environment(ConstantEnvironment { color: sky });
+                camera.set_samples_per_pixel(samples);
+                camera.set_filter(filter);
+                let film = camera.render(&world, |_| {});
+                // Edges between the black sphere and the sky stay in range
+                for color in film.colors() {
+                    assert!((0. ..=4.).contains(&color.x), "{filter:?}: {color:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_clamping_bounds_first_hit_light() {
+        let mut world = HittableList::new();
+        world.add(Sphere {
+            center: Point3 {
+                x: 0.,
+                y: 0.,
+                z: -1.,
+            },
+            radius: 0.5,
+            material: Arc::new(Lambertian {
+                albedo: Color {
+                    x: 0.5,
+                    y: 0.5,
+                    z: 0.5,
+                },
+            }),
+        });
+        let mut camera = Camera::new(1., 5);
+        camera.set_environment(ConstantEnvironment {
+            color: Color {
+                x: 10.,
+                y: 10.,
+                z: 10.,
+            },
+        });
+        camera.set_samples_per_pixel(4);
+        camera.set_firefly_suppression(FireflySuppression {
+            max_direct: Some(0.5),
+            max_indirect: Some(1.),
+            outlier_ratio: None,
+        });
+        let film = camera.render(&world, |_| {});
+
+        assert!(film.pixel(2, 2).color().x <= 1.5 + 1e-9);
+        // The background is seen directly and stays as is
+        assert_eq!(film.pixel(0, 0).color().x, 10.);
+    }
+
+    #[test]
+    fn test_noisy_pixel_takes_more_samples() {
+        let camera = adaptive_camera();
+        let mut world = HittableList::new();
+        world.add(Sphere {
+            center: Point3 {
+                x: 0.,
+                y: 0.,
+                z: -1.,
+            },
+            radius: 0.5,
+            material: Arc::new(Lambertian {
+                albedo: Color {
+                    x: 0.5,
+                    y: 0.5,
+                    z: 0.5,
+                },
+            }),
+        });
+        let mut pixel = Pixel::default();
+        let mut sampler = IndependentSampler::new(0);
+        camera.render_pixel(5, 5, 64, &mut pixel, &world, &mut sampler);
+        assert!(pixel.samples > 8);
+    }
+
+    #[test]
+    fn test_medium_scattering_has_its_own_bound() {
+        let white = Color {
+            x: 1.,
+            y: 1.,
+            z: 1.,
+        };
+        let mut world = HittableList::new();
+        world.add(Sphere {
+            center: Point3 {
+                x: 0.,
+                y: 0.,
+                z: -3.,
+            },
+            radius: 1.,
+            material: Arc::new(Subsurface::new(0.2 * white, white, 1., 0.)),
+        });
+        let brightness = |max_scattering_events| {
+            let mut camera = Camera::new(1., 9);
+            camera.set_environment(ConstantEnvironment { color: white });
+            camera.set_max_depth(4);
+            camera.set_max_scattering_events(max_scattering_events);
+            camera.initialize();
+            let mut pixel = Pixel::default();
+            let mut sampler = IndependentSampler::new(0);
+            camera.render_pixel(4, 4, 200, &mut pixel, &world, &mut sampler);
+            pixel.color().x
+        };
+        // Light goes through the white medium however many surface bounces
+        // are allowed
+        assert!(brightness(256) > 0.9);
+        assert!(brightness(0) < 0.5);
+    }
+
+    #[test]
+    fn test_progressive_matches_single_pass() {
+        let mut camera = Camera::new(1., 4);
+        camera.set_samples_per_pixel(5);
+        let world = HittableList::new();
+        let single_pass = camera.render(&world, |_| {});
+
+        camera.set_progressive(ProgressiveRendering {
+            snapshot_interval: Duration::ZERO,
+            time_budget: None,
+        });
+        let mut passes = vec![];
+        let progressive = camera.render(&world, |film| passes.push(film.max_samples()));
+        assert_eq!(passes, vec![1, 2, 4]);
+        assert_eq!(progressive, single_pass);
+    }
+
+    #[test]
+    fn test_resume_matches_uninterrupted_render() {
+        let world = HittableList::new();
+        let mut camera = Camera::new(1., 4);
+        camera.set_seed(7);
+        camera.set_samples_per_pixel(6);
+        let uninterrupted = camera.render(&world, |_| {});
+
+        let filename = std::env::temp_dir().join("raytrace_test_resume.checkpoint");
+        let filename = filename.to_str().unwrap().to_string();
+        camera.set_samples_per_pixel(3);
+        camera.set_checkpointing(Checkpointing {
+            filename: filename.clone(),
+            interval: Duration::from_secs(3600),
+            resume: false,
+        });
+        camera.render(&world, |_| {});
+
+        camera.set_samples_per_pixel(6);
+        camera.set_checkpointing(Checkpointing {
+            filename: filename.clone(),
+            interval: Duration::from_secs(3600),
+            resume: true,
+        });
+        let resumed = camera.render(&world, |_| {});
+        std::fs::remove_file(&filename).unwrap();
+        assert_eq!(resumed, uninterrupted);
+    }
+
+    #[test]
+    fn test_stratified_resume() {
+        let world = HittableList::new();
+        let filename = std::env::temp_dir().join("raytrace_test_stratified.checkpoint");
+        let filename = filename.to_str().unwrap().to_string();
+        let mut camera = Camera::new(1., 4);
+        camera.set_sampler(SamplerKind::Stratified);
+        camera.set_samples_per_pixel(6);
+        camera.set_checkpointing(Checkpointing {
+            filename: filename.clone(),
+            interval: Duration::from_secs(3600),
+            resume: true,
+        });
+        let uninterrupted = camera.render(&world, |_| {});
+
+        // Render stopped after the first rows
+        let mut interrupted = uninterrupted.clone();
+        for pixel in &mut interrupted.pixels[8..] {
+            *pixel = Pixel::default();
+        }
+        camera.save_checkpoint(&interrupted);
+        let resumed = camera.render(&world, |_| {});
+        assert_eq!(resumed, uninterrupted);
+
+        // The strata depend on the sample count
+        camera.save_checkpoint(&interrupted);
+        camera.set_samples_per_pixel(12);
+        assert!(camera.resumed_film().is_none());
+        camera.set_samples_per_pixel(6);
+        camera.set_max_depth(10);
+        assert!(camera.resumed_film().is_none());
+        camera.set_max_depth(50);
+        camera.set_filter(Filter::mitchell(2.));
+        assert!(camera.resumed_film().is_none());
+        camera.set_filter(Filter::default());
+        assert!(camera.resumed_film().is_some());
+
+        // Crops of a wider image give films of the same size, but of other
+        // pixels
+        camera.set_crop(Crop {
+            x: 0,
+            y: 0,
+            width: 4,
+            height: 4,
+        });
+        assert!(camera.resumed_film().is_some());
+        camera.image_width = 8;
+        assert!(camera.resumed_film().is_none());
+        camera.save_checkpoint(&interrupted);
+        assert!(camera.resumed_film().is_some());
+        camera.set_crop(Crop {
+            x: 4,
+            y: 0,
+            width: 4,
+            height: 4,
+        });
+        assert!(camera.resumed_film().is_none());
+        std::fs::remove_file(&filename).unwrap();
+    }
+
+    #[test]
+    fn test_aovs_see_first_hit() {
+        let mut world = HittableList::new();
+        world.add(Sphere {
+            center: Point3 {
+                x: 0.,
+                y: 0.,
+                z: -1.,
+            },
+            radius: 0.5,
+            material: Arc::new(Lambertian {
+                albedo: Color {
+                    x: 0.2,
+                    y: 0.4,
+                    z: 0.6,
+                },
+            }),
+        });
+        let mut camera = Camera::new(1., 9);
+        camera.set_samples_per_pixel(16);
+        let aovs = render_aovs(&mut camera, &world);
+
+        let center = aovs.pixel(4, 4);
+        assert!((center.depth - 0.5).abs() < 0.01);
+        assert!(center.normal.z > 0.99);
+        assert!((center.albedo.y - 0.4).abs() < 1e-9);
+        assert_eq!((center.material_id, center.object_id), (1, 1));
+
+        let corner = aovs.pixel(0, 0);
+        assert_eq!(corner.depth, f64::INFINITY);
+        assert_eq!((corner.material_id, corner.object_id), (0, 0));
+    }
+
+    #[test]
+    fn test_aovs_follow_the_aperture() {
+        let mut world = HittableList::new();
+        world.add(Sphere {
+            center: Point3 {
+                x: 0.,
+                y: 0.,
+                z: -1.,
+            },
+            radius: 0.3,
+            material: Arc::new(Lambertian {
+                albedo: Color::default(),
+            }),
+        });
+        let mut camera = Camera::new(1., 9);
+        camera.set_samples_per_pixel(64);
+        let film = render_film_with_aovs(&mut camera, &world);
+        let sharp = film.pixel(4, 4).aov;
+        assert_eq!(sharp.hit_weight, sharp.weight);
+
+        // Focused far behind it, the sphere is blurred over the lens
+        camera.set_lens_effects(LensEffects {
+            aperture: Some(Aperture {
+                radius: 0.5,
+                focus_distance: 10.,
+                blades: 0,
+                rotation: 0.,
+            }),
+            ..Default::default()
+        });
+        let film = render_film_with_aovs(&mut camera, &world);
+        let blurred = film.pixel(4, 4).aov;
+        assert!(blurred.hit_weight > 0. && blurred.hit_weight < 0.9 * blurred.weight);
+    }
+
+    #[test]
+    fn test_animated_camera_turns_and_zooms() {
+        let mut world = HittableList::new();
+        world.add(Sphere {
+            center: Point3 {
+                x: 10.,
+                y: 0.,
+                z: 0.,
+            },
+            radius: 1.,
+            material: Arc::new(Lambertian {
+                albedo: Color::default(),
+            }),
+        });
+        let mut look_at = Track::new(
+            Interpolation::Linear,
+            0.,
+            Point3 {
+                x: 0.,
+                y: 0.,
+                z: -1.,
+            },
+        );
+        look_at.add_key(
+            1.,
+            Point3 {
+                x: 1.,
+                y: 0.,
+                z: 0.,
+            },
+        );
+        let mut vertical_fov = Track::new(Interpolation::Linear, 0., 90.);
+        vertical_fov.add_key(1., 10.);
+        let mut camera = Camera::new(1., 9);
+        camera.set_look_at(look_at);
+        camera.set_vertical_fov(vertical_fov);
+
+        camera.set_samples_per_pixel(4);
+        let aovs = render_aovs(&mut camera, &world);
+        assert_eq!(aovs.pixel(4, 4).depth, f64::INFINITY);
+
+        // Turned toward the sphere, which fills the narrow view
+        camera.set_shutter(1., 0.);
+        let aovs = render_aovs(&mut camera, &world);
+        assert!((aovs.pixel(4, 4).depth - 9.).abs() < 0.01);
+        assert!(aovs.pixel(4, 0).depth < 10.);
+
+        // Only the end of the turn sees the sphere, the outputs of blurred
+        // pixels come from the same moments as their colors
+        camera.set_samples_per_pixel(64);
+        camera.set_shutter(0., 1.);
+        let film = camera.render(&world, |_| {});
+        let sums = &film.pixel(4, 4).aov;
+        assert!(sums.hit_weight > 0. && sums.hit_weight < 0.5 * sums.weight);
+        let depth = camera.aovs(&film, &world).pixel(4, 4).depth;
+        assert!((9. ..10.).contains(&depth));
+    }
+
+    #[test]
+    fn test_looking_along_view_up() {
+        let mut camera = Camera::new(1., 9);
+        camera.set_look_from(Point3 {
+            x: 0.,
+            y: 10.,
+            z: 0.,
+        });
+        camera.set_look_at(Point3::default());
+        camera.initialize();
+        let mut sampler = IndependentSampler::new(0);
+        let (ray, _, _) = camera.camera_ray(4, 4, 0, &mut sampler);
+        let direction = ray.unwrap().dir.unit_vector();
+        assert!((direction.y + 1.).abs() < 0.01);
+
+        let (ray, _, _) = camera.camera_ray(0, 0, 0, &mut sampler);
+        let direction = ray.unwrap().dir;
+        assert!(direction.x.is_finite() && direction.z.is_finite());
+    }
+
+    #[test]
+    fn test_frame_file_names() {
+        assert_eq!(
+            FrameSequence::file_name("out/image.pfm", 12),
+            "out/image_0012.pfm"
+        );
+        assert_eq!(FrameSequence::file_name("image", 3), "image_0003");
+    }
+
+    #[test]
+    fn test_crop_and_threads_keep_pixels() {
+        let mut world = HittableList::new();
+        world.add(Sphere {
+            center: Point3 {
+                x: 0.,
+                y: 0.,
+                z: -1.,
+            },
+            radius: 0.5,
+            material: Arc::new(Lambertian {
+                albedo: Color {
+                    x: 0.5,
+                    y: 0.5,
+                    z: 0.5,
+                },
+            }),
+        });
+        let mut camera = Camera::new(1.5, 12);
+        camera.set_samples_per_pixel(4);
+        camera.set_threads(1);
+        let full = camera.render(&world, |_| {});
+
+        camera.set_threads(3);
+        camera.set_crop(Crop {
+            x: 2,
+            y: 3,
+            width: 5,
+            height: 4,
+        });
+        let cropped = camera.render(&world, |_| {});
+        assert_eq!((cropped.width, cropped.height), (5, 4));
+        for j in 0..4 {
+            for i in 0..5 {
+                assert_eq!(cropped.pixel(i, j), full.pixel(i + 2, j + 3));
+            }
         }
     }
 }
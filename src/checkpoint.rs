@@ -0,0 +1,370 @@
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+
+use crate::aov::AovSums;
+use crate::camera::Crop;
+use crate::film::{Film, Pixel};
+use crate::filter::Filter;
+use crate::sampler::SamplerKind;
+use crate::vec3::{Color, Vec3};
+
+const MAGIC: &[u8; 8] = b"RTCHECK3";
+// Seed, sampler, its sample count, spectral and auxiliary output flags,
+// path depths, filter, image size and rendered region
+const SETTINGS_SIZE: usize = 8 + 1 + 4 + 1 + 1 + 4 + 4 + 1 + 3 * 8 + 2 * 4 + 4 * 4;
+// Seven f64 sums, the u32 sample count and twelve f64 auxiliary output sums
+const PIXEL_SIZE: u64 = 7 * 8 + 4 + 12 * 8;
+
+/// Settings a render has to share with the checkpoint it resumes from to
+/// give the same image as an uninterrupted render
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RenderSettings {
+    pub seed: u64,
+    pub sampler: SamplerKind,
+    /// Samples per pixel the sampler was created for
+    pub sampler_samples: u32,
+    pub spectral: bool,
+    /// Whether the film holds the sums of the auxiliary outputs
+    pub aovs: bool,
+    pub max_depth: u32,
+    pub max_scattering_events: u32,
+    pub filter: Filter,
+    pub image_width: u32,
+    pub image_height: u32,
+    /// Part of the image the film holds
+    pub region: Crop,
+}
+
+impl RenderSettings {
+    /// Whether a render with these settings can continue one saved with
+    /// `saved`. Only the stratified sampler depends on the sample count
+    pub fn can_resume(&self, saved: &RenderSettings) -> bool {
+        let samples_match = self.sampler != SamplerKind::Stratified
+            || self.sampler_samples == saved.sampler_samples;
+        let others_match = RenderSettings {
+            sampler_samples: saved.sampler_samples,
+            ..*self
+        } == *saved;
+        samples_match && others_match
+    }
+}
+
+/// State of an interrupted render. The samplers derive their random numbers
+/// from the seed, the pixel and the sample index, so the film with its sample
+/// counts is enough to continue exactly where the render stopped
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    pub settings: RenderSettings,
+    pub film: Film,
+}
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+impl Checkpoint {
+    pub fn load(filename: &str) -> Result<Self> {
+        Self::decode(&fs::read(filename)?)
+    }
+
+    /// Writes the checkpoint to a temporary file first, so a render killed
+    /// while saving keeps the previous checkpoint
+    pub fn save(&self, filename: &str) -> Result<()> {
+        let temporary = format!("{filename}.tmp");
+        fs::write(&temporary, self.encode())?;
+        fs::rename(&temporary, filename)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let settings = &self.settings;
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(settings.seed.to_le_bytes());
+        bytes.push(match settings.sampler {
+            SamplerKind::Independent => 0,
+            SamplerKind::Stratified => 1,
+            SamplerKind::Halton => 2,
+            SamplerKind::Sobol => 3,
+        });
+        bytes.extend(settings.sampler_samples.to_le_bytes());
+        bytes.push(settings.spectral as u8);
+        bytes.push(settings.aovs as u8);
+        bytes.extend(settings.max_depth.to_le_bytes());
+        bytes.extend(settings.max_scattering_events.to_le_bytes());
+        // Filters are stored with their parameters padded to three
+        let (kind, parameters) = match settings.filter {
+            Filter::Box { radius } => (0, [radius, 0., 0.]),
+            Filter::Tent { radius } => (1, [radius, 0., 0.]),
+            Filter::Gaussian { radius, sigma } => (2, [radius, sigma, 0.]),
+            Filter::Mitchell { radius, b, c } => (3, [radius, b, c]),
+            Filter::Lanczos { radius, tau } => (4, [radius, tau, 0.]),
+        };
+        bytes.push(kind);
+        for parameter in parameters {
+            bytes.extend(parameter.to_le_bytes());
+        }
+        let region = settings.region;
+        for value in [
+            settings.image_width,
+            settings.image_height,
+            region.x,
+            region.y,
+            region.width,
+            region.height,
+        ] {
+            bytes.extend(value.to_le_bytes());
+        }
+        bytes.extend(self.film.width.to_le_bytes());
+        bytes.extend(self.film.height.to_le_bytes());
+        for pixel in &self.film.pixels {
+            for value in [
+                pixel.sum.x,
+                pixel.sum.y,
+                pixel.sum.z,
+                pixel.weight_sum,
+                pixel.abs_weight_sum,
+                pixel.luminance_sum,
+                pixel.luminance_squared_sum,
+            ] {
+                bytes.extend(value.to_le_bytes());
+            }
+            bytes.extend(pixel.samples.to_le_bytes());
+            let aov = &pixel.aov;
+            for value in [
+                aov.hit_weight,
+                aov.weight,
+                aov.depth,
+                aov.normal.x,
+                aov.normal.y,
+                aov.normal.z,
+                aov.albedo.x,
+                aov.albedo.y,
+                aov.albedo.z,
+                aov.position.x,
+                aov.position.y,
+                aov.position.z,
+            ] {
+                bytes.extend(value.to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let mut data = bytes
+            .strip_prefix(MAGIC)
+            .ok_or_else(|| invalid_data("Not a render checkpoint"))?;
+        let mut take = |count: usize| -> Result<&[u8]> {
+            if data.len() < count {
+                return Err(invalid_data("Unexpected end of the checkpoint"));
+            }
+            let (taken, rest) = data.split_at(count);
+            data = rest;
+            Ok(taken)
+        };
+
+        let seed = u64::from_le_bytes(take(8)?.try_into().unwrap());
+        let sampler = match take(1)?[0] {
+            0 => SamplerKind::Independent,
+            1 => SamplerKind::Stratified,
+            2 => SamplerKind::Halton,
+            3 => SamplerKind::Sobol,
+            _ => return Err(invalid_data("Unknown sampler in the checkpoint")),
+        };
+        let sampler_samples = u32::from_le_bytes(take(4)?.try_into().unwrap());
+        let spectral = take(1)?[0] != 0;
+        let aovs = take(1)?[0] != 0;
+        let max_depth = u32::from_le_bytes(take(4)?.try_into().unwrap());
+        let max_scattering_events = u32::from_le_bytes(take(4)?.try_into().unwrap());
+        let kind = take(1)?[0];
+        let mut parameters = [0.; 3];
+        for parameter in parameters.iter_mut() {
+            *parameter = f64::from_le_bytes(take(8)?.try_into().unwrap());
+        }
+        let [radius, p1, p2] = parameters;
+        let filter = match kind {
+            0 => Filter::Box { radius },
+            1 => Filter::Tent { radius },
+            2 => Filter::Gaussian { radius, sigma: p1 },
+            3 => Filter::Mitchell {
+                radius,
+                b: p1,
+                c: p2,
+            },
+            4 => Filter::Lanczos { radius, tau: p1 },
+            _ => return Err(invalid_data("Unknown filter in the checkpoint")),
+        };
+        let mut sizes = [0; 6];
+        for size in sizes.iter_mut() {
+            *size = u32::from_le_bytes(take(4)?.try_into().unwrap());
+        }
+        let [image_width, image_height, x, y, region_width, region_height] = sizes;
+        let settings = RenderSettings {
+            seed,
+            sampler,
+            sampler_samples,
+            spectral,
+            aovs,
+            max_depth,
+            max_scattering_events,
+            filter,
+            image_width,
+            image_height,
+            region: Crop {
+                x,
+                y,
+                width: region_width,
+                height: region_height,
+            },
+        };
+
+        let width = u32::from_le_bytes(take(4)?.try_into().unwrap());
+        let height = u32::from_le_bytes(take(4)?.try_into().unwrap());
+        let header_size = (MAGIC.len() + SETTINGS_SIZE + 4 + 4) as u64;
+        if header_size + width as u64 * height as u64 * PIXEL_SIZE != bytes.len() as u64 {
+            return Err(invalid_data(
+                "Checkpoint size does not match its resolution",
+            ));
+        }
+
+        let mut film = Film::new(width, height);
+        for pixel in film.pixels.iter_mut() {
+            let mut values = [0.; 7];
+            for value in values.iter_mut() {
+                *value = f64::from_le_bytes(take(8)?.try_into().unwrap());
+            }
+            let samples = u32::from_le_bytes(take(4)?.try_into().unwrap());
+            let mut sums = [0.; 12];
+            for value in sums.iter_mut() {
+                *value = f64::from_le_bytes(take(8)?.try_into().unwrap());
+            }
+            let vector = |i: usize| Vec3 {
+                x: sums[i],
+                y: sums[i + 1],
+                z: sums[i + 2],
+            };
+            *pixel = Pixel {
+                sum: Color {
+                    x: values[0],
+                    y: values[1],
+                    z: values[2],
+                },
+                weight_sum: values[3],
+                abs_weight_sum: values[4],
+                luminance_sum: values[5],
+                luminance_squared_sum: values[6],
+                samples,
+                aov: AovSums {
+                    hit_weight: sums[0],
+                    weight: sums[1],
+                    depth: sums[2],
+                    normal: vector(3),
+                    albedo: vector(6),
+                    position: vector(9),
+                },
+            };
+        }
+        Ok(Checkpoint { settings, film })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::checkpoint::*;
+
+    fn settings() -> RenderSettings {
+        RenderSettings {
+            seed: 1234,
+            sampler: SamplerKind::Sobol,
+            sampler_samples: 16,
+            spectral: true,
+            aovs: true,
+            max_depth: 8,
+            max_scattering_events: 64,
+            filter: Filter::Mitchell {
+                radius: 2.,
+                b: 0.2,
+                c: 0.4,
+            },
+            image_width: 6,
+            image_height: 4,
+            region: Crop {
+                x: 1,
+                y: 2,
+                width: 3,
+                height: 2,
+            },
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut film = Film::new(3, 2);
+        film.pixel_mut(2, 1).add(Color {
+            x: 0.1,
+            y: 0.2,
+            z: 1e300,
+        });
+        film.pixel_mut(0, 1).aov.position.y = -3.5;
+        let checkpoint = Checkpoint {
+            settings: settings(),
+            film,
+        };
+        assert_eq!(
+            Checkpoint::decode(&checkpoint.encode()).unwrap(),
+            checkpoint
+        );
+    }
+
+    #[test]
+    fn test_truncated_checkpoint_is_rejected() {
+        let checkpoint = Checkpoint {
+            settings: settings(),
+            film: Film::new(3, 2),
+        };
+        let bytes = checkpoint.encode();
+        assert!(Checkpoint::decode(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Checkpoint::decode(b"not a checkpoint").is_err());
+    }
+
+    #[test]
+    fn test_resume_needs_matching_settings() {
+        let saved = settings();
+        let more_samples = RenderSettings {
+            sampler_samples: 32,
+            ..saved
+        };
+        assert!(more_samples.can_resume(&saved));
+        let stratified = RenderSettings {
+            sampler: SamplerKind::Stratified,
+            ..saved
+        };
+        assert!(!RenderSettings {
+            sampler_samples: 32,
+            ..stratified
+        }
+        .can_resume(&stratified));
+        assert!(!RenderSettings {
+            spectral: false,
+            ..saved
+        }
+        .can_resume(&saved));
+        assert!(!RenderSettings {
+            filter: Filter::default(),
+            ..saved
+        }
+        .can_resume(&saved));
+        assert!(!RenderSettings {
+            image_width: 8,
+            ..saved
+        }
+        .can_resume(&saved));
+        assert!(!RenderSettings {
+            region: Crop {
+                x: 2,
+                ..saved.region
+            },
+            ..saved
+        }
+        .can_resume(&saved));
+    }
+}
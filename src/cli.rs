@@ -0,0 +1,411 @@
+use std::path::Path;
+
+use crate::camera::{Camera, Crop, FrameSequence};
+use crate::scene::BUILTIN_SCENES;
+
+// Extensions of the image files the renderer can write
+const FORMATS: [&str; 5] = ["png", "jpg", "jpeg", "gif", "pfm"];
+
+// Aspect ratio of the image when only its width is given
+const DEFAULT_ASPECT_RATIO: f64 = 16. / 9.;
+
+// Frame rate and shutter of animations when they are not given
+const DEFAULT_FRAMES_PER_SECOND: f64 = 24.;
+const DEFAULT_SHUTTER: f64 = 0.5;
+
+/// Where the scene to render comes from
+#[derive(Debug, Clone, PartialEq)]
+pub enum SceneSource {
+    /// Scene description file, see `Scene::parse`
+    File(String),
+    /// One of `BUILTIN_SCENES`
+    Builtin(String),
+}
+
+/// Render settings given on the command line
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    pub scene: SceneSource,
+    /// Image file, its extension giving the format
+    pub output: String,
+    pub width: u32,
+    /// Height of the image, 16:9 with the width without it
+    pub height: Option<u32>,
+    pub samples_per_pixel: u32,
+    pub max_depth: u32,
+    /// Number of rendering threads, all the processors without it
+    pub threads: Option<usize>,
+    pub seed: u64,
+    pub crop: Option<Crop>,
+    /// Frames of the animation to render, a still image at time 0 without it
+    pub frames: Option<FrameSequence>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            scene: SceneSource::Builtin(BUILTIN_SCENES[0].to_string()),
+            output: "image.png".to_string(),
+            width: 400,
+            height: None,
+            samples_per_pixel: 100,
+            max_depth: 50,
+            threads: None,
+            seed: 0,
+            crop: None,
+            frames: None,
+        }
+    }
+}
+
+/// What the command line asks for
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Help,
+    Render(Options),
+}
+
+/// Description of the options printed by `--help`
+pub fn usage() -> String {
+    format!(
+        "\
+Usage: raytrace [OPTIONS]
+
+Options:
+  -s, --scene <FILE>         Scene description file to render
+  -b, --builtin <NAME>       Built-in scene to render: {} (default: {})
+  -o, --output <FILE>        Output image (default: image.png)
+  -f, --format <FORMAT>      Output format, replacing the extension of the output:
+                             png, jpg, gif or pfm (linear colors)
+  -w, --width <PIXELS>       Image width (default: 400)
+      --height <PIXELS>      Image height (default: 16:9 with the width)
+  -n, --samples <COUNT>      Samples per pixel (default: 100)
+  -d, --max-depth <COUNT>    Maximum number of bounces (default: 50)
+  -t, --threads <COUNT>      Rendering threads (default: all the processors)
+      --seed <NUMBER>        Seed of the random numbers (default: 0)
+      --crop <X,Y,W,H>       Only renders the given part of the image, in pixels
+                             from the top-left corner
+      --frames <FIRST-LAST>  Renders these frames of the animation, numbering
+                             the output files as image_0001.png
+      --fps <RATE>           Frames per second of the animation (default: 24)
+      --shutter <FRACTION>   Fraction of a frame the shutter stays open for,
+                             0 for no motion blur (default: 0.5)
+  -h, --help                 Prints this help
+",
+        BUILTIN_SCENES.join(", "),
+        BUILTIN_SCENES[0]
+    )
+}
+
+impl Options {
+    /// Reads the command line arguments following the program name. Values
+    /// come after their option, or after an `=` sign as in `--width=800`
+    pub fn parse(arguments: impl IntoIterator<Item = String>) -> Result<Command, String> {
+        let mut options = Options::default();
+        let mut scene_file = None;
+        let mut builtin = None;
+        let mut format = None;
+        let mut frame_range = None;
+        let mut frames_per_second = None;
+        let mut shutter = None;
+
+        let mut arguments = arguments.into_iter();
+        while let Some(argument) = arguments.next() {
+            let (name, inline_value) = match argument.split_once('=') {
+                Some((name, value)) if name.starts_with("--") => {
+                    (name.to_string(), Some(value.to_string()))
+                }
+                _ => (argument, None),
+            };
+            if name == "-h" || name == "--help" {
+                return Ok(Command::Help);
+            }
+            if !name.starts_with('-') {
+                return Err(format!("Unexpected argument {name}"));
+            }
+            let value = inline_value
+                .or_else(|| arguments.next())
+                .ok_or_else(|| format!("{name} needs a value"))?;
+
+            match name.as_str() {
+                "-s" | "--scene" => scene_file = Some(value),
+                "-b" | "--builtin" => builtin = Some(value),
+                "-o" | "--output" => options.output = value,
+                "-f" | "--format" => format = Some(value.to_ascii_lowercase()),
+                "-w" | "--width" => options.width = positive(&name, &value)?,
+                "--height" => options.height = Some(positive(&name, &value)?),
+                "-n" | "--samples" => options.samples_per_pixel = positive(&name, &value)?,
+                "-d" | "--max-depth" => options.max_depth = number(&name, &value)?,
+                "-t" | "--threads" => options.threads = Some(positive(&name, &value)?),
+                "--seed" => options.seed = number(&name, &value)?,
+                "--crop" => options.crop = Some(crop(&value)?),
+                "--frames" => frame_range = Some(frames(&value)?),
+                "--fps" => frames_per_second = Some(number(&name, &value)?),
+                "--shutter" => shutter = Some(number(&name, &value)?),
+                _ => return Err(format!("Unknown option {name}")),
+            }
+        }
+
+        options.scene = match (scene_file, builtin) {
+            (Some(_), Some(_)) => {
+                return Err("--scene and --builtin can not be used together".to_string())
+            }
+            (Some(filename), None) => SceneSource::File(filename),
+            (None, Some(name)) if BUILTIN_SCENES.contains(&name.as_str()) => {
+                SceneSource::Builtin(name)
+            }
+            (None, Some(name)) => {
+                return Err(format!(
+                    "Unknown built-in scene {name}, available: {}",
+                    BUILTIN_SCENES.join(", ")
+                ))
+            }
+            (None, None) => options.scene,
+        };
+
+        if let Some(format) = format {
+            if !FORMATS.contains(&format.as_str()) {
+                return Err(format!("Unsupported output format {format}"));
+            }
+            let output = Path::new(&options.output).with_extension(format);
+            options.output = output.to_string_lossy().into_owned();
+        }
+        let extension = Path::new(&options.output)
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default();
+        if !FORMATS.contains(&extension.as_str()) {
+            return Err(format!(
+                "Unsupported output format for {}, use one of {}",
+                options.output,
+                FORMATS.join(", ")
+            ));
+        }
+
+        if let Some(crop) = options.crop {
+            let (width, height) = (options.width, options.image_height());
+            if crop.x >= width
+                || crop.width > width - crop.x
+                || crop.y >= height
+                || crop.height > height - crop.y
+            {
+                return Err(format!(
+                    "The crop region is outside the {width}x{height} image"
+                ));
+            }
+        }
+
+        options.frames = match frame_range {
+            Some((first_frame, last_frame)) => {
+                let frames_per_second = frames_per_second.unwrap_or(DEFAULT_FRAMES_PER_SECOND);
+                let shutter = shutter.unwrap_or(DEFAULT_SHUTTER);
+                if !(frames_per_second > 0. && frames_per_second.is_finite()) {
+                    return Err("--fps must be more than 0".to_string());
+                }
+                if !(0. ..=1.).contains(&shutter) {
+                    return Err("--shutter must be between 0 and 1".to_string());
+                }
+                Some(FrameSequence {
+                    first_frame,
+                    last_frame,
+                    frames_per_second,
+                    shutter,
+                })
+            }
+            None if frames_per_second.is_some() || shutter.is_some() => {
+                return Err("--fps and --shutter need --frames".to_string())
+            }
+            None => None,
+        };
+        Ok(Command::Render(options))
+    }
+
+    /// Height of the image in pixels
+    pub fn image_height(&self) -> u32 {
+        self.height
+            .unwrap_or_else(|| ((self.width as f64 / DEFAULT_ASPECT_RATIO).round() as u32).max(1))
+    }
+
+    /// Camera rendering the image with these settings
+    pub fn camera(&self) -> Camera {
+        // The camera rounds the height down, half a pixel more keeps the
+        // division from falling just below it
+        let aspect_ratio = self.width as f64 / (self.image_height() as f64 + 0.5);
+        let mut camera = Camera::new(aspect_ratio, self.width);
+        camera.set_samples_per_pixel(self.samples_per_pixel);
+        camera.set_max_depth(self.max_depth);
+        camera.set_seed(self.seed);
+        if let Some(threads) = self.threads {
+            camera.set_threads(threads);
+        }
+        if let Some(crop) = self.crop {
+            camera.set_crop(crop);
+        }
+        camera
+    }
+}
+
+fn number<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{name} expects a number, got {value}"))
+}
+
+fn positive<T: std::str::FromStr + Default + PartialEq>(
+    name: &str,
+    value: &str,
+) -> Result<T, String> {
+    let number = number(name, value)?;
+    if number == T::default() {
+        return Err(format!("{name} must be at least 1"));
+    }
+    Ok(number)
+}
+
+// Crop region written X,Y,WIDTH,HEIGHT
+fn crop(value: &str) -> Result<Crop, String> {
+    let invalid = || format!("--crop expects X,Y,WIDTH,HEIGHT, got {value}");
+    let values = value
+        .split(',')
+        .map(|number| number.trim().parse())
+        .collect::<Result<Vec<u32>, _>>()
+        .map_err(|_| invalid())?;
+    let [x, y, width, height] = values[..] else {
+        return Err(invalid());
+    };
+    if width == 0 || height == 0 {
+        return Err("The crop region is empty".to_string());
+    }
+    Ok(Crop {
+        x,
+        y,
+        width,
+        height,
+    })
+}
+
+// Frame range written FIRST-LAST, or a single frame
+fn frames(value: &str) -> Result<(u32, u32), String> {
+    let invalid = || format!("--frames expects FIRST-LAST, got {value}");
+    let (first, last) = value.split_once('-').unwrap_or((value, value));
+    let first: u32 = first.trim().parse().map_err(|_| invalid())?;
+    let last: u32 = last.trim().parse().map_err(|_| invalid())?;
+    if first > last {
+        return Err(format!("The frame range {value} is empty"));
+    }
+    Ok((first, last))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cli::*;
+
+    fn parse(arguments: &str) -> Result<Command, String> {
+        Options::parse(arguments.split_whitespace().map(str::to_string))
+    }
+
+    fn options(arguments: &str) -> Options {
+        match parse(arguments) {
+            Ok(Command::Render(options)) => options,
+            result => panic!("Unexpected result {result:?}"),
+        }
+    }
+
+    #[test]
+    fn test_defaults() {
+        assert_eq!(options(""), Options::default());
+        assert_eq!(Options::default().image_height(), 225);
+        assert_eq!(parse("--width 10 --help"), Ok(Command::Help));
+    }
+
+    #[test]
+    fn test_parse_options() {
+        let parsed = options(
+            "-s scene.txt -o out/render.png --format=pfm -w 640 --height=480 -n 16 \
+             -d 8 -t 3 --seed 42 --crop 10,20,100,50",
+        );
+        assert_eq!(
+            parsed,
+            Options {
+                scene: SceneSource::File("scene.txt".to_string()),
+                output: "out/render.pfm".to_string(),
+                width: 640,
+                height: Some(480),
+                samples_per_pixel: 16,
+                max_depth: 8,
+                threads: Some(3),
+                seed: 42,
+                crop: Some(Crop {
+                    x: 10,
+                    y: 20,
+                    width: 100,
+                    height: 50,
+                }),
+                frames: None,
+            }
+        );
+        assert_eq!(
+            options("--frames 1-48 --fps 12 --shutter=0").frames,
+            Some(FrameSequence {
+                first_frame: 1,
+                last_frame: 48,
+                frames_per_second: 12.,
+                shutter: 0.,
+            })
+        );
+        assert_eq!(
+            options("--frames 7").frames,
+            Some(FrameSequence {
+                first_frame: 7,
+                last_frame: 7,
+                frames_per_second: 24.,
+                shutter: 0.5,
+            })
+        );
+        assert_eq!(
+            options("--builtin cover").scene,
+            SceneSource::Builtin("cover".to_string())
+        );
+    }
+
+    #[test]
+    fn test_camera_keeps_the_height() {
+        let world = crate::geometry::HittableList::new();
+        for (width, height) in [(7, 3), (1, 93), (2, 93), (3, 10), (1, 1)] {
+            let mut camera = options(&format!("-w {width} --height {height} -n 1")).camera();
+            let film = camera.render(&world, |_| {});
+            assert_eq!((film.width, film.height), (width, height));
+        }
+    }
+
+    #[test]
+    fn test_validation_errors() {
+        let error = |arguments| parse(arguments).unwrap_err();
+        assert_eq!(error("--width"), "--width needs a value");
+        assert_eq!(error("--width wide"), "--width expects a number, got wide");
+        assert_eq!(error("-n 0"), "-n must be at least 1");
+        assert_eq!(error("--size 3"), "Unknown option --size");
+        assert_eq!(error("scene.txt"), "Unexpected argument scene.txt");
+        assert!(error("-s a.txt -b cover").contains("together"));
+        assert!(error("-b teapot").starts_with("Unknown built-in scene teapot"));
+        assert_eq!(error("-f tiff"), "Unsupported output format tiff");
+        assert!(error("-o image.bmp").starts_with("Unsupported output format"));
+        assert!(error("--crop 1,2,3").starts_with("--crop expects"));
+        assert_eq!(
+            error("-w 100 --crop 50,0,60,10"),
+            "The crop region is outside the 100x56 image"
+        );
+        assert_eq!(
+            error("--crop 4294967295,0,2,1"),
+            "The crop region is outside the 400x225 image"
+        );
+        assert!(error("--frames 1..4").starts_with("--frames expects"));
+        assert_eq!(error("--frames 5-2"), "The frame range 5-2 is empty");
+        assert_eq!(error("--frames 1-2 --fps 0"), "--fps must be more than 0");
+        assert_eq!(
+            error("--frames 1-2 --shutter 2"),
+            "--shutter must be between 0 and 1"
+        );
+        assert_eq!(error("--fps 30"), "--fps and --shutter need --frames");
+    }
+}
@@ -0,0 +1,253 @@
+use crate::aov::Aovs;
+use crate::vec3::*;
+
+// Weights of the B3 spline kernel along each axis
+const KERNEL: [f64; 5] = [1. / 16., 1. / 4., 3. / 8., 1. / 4., 1. / 16.];
+
+// Albedo below which a pixel is filtered as is instead of demodulated
+const MIN_ALBEDO: f64 = 0.01;
+
+// Squared widths of the edge stopping functions of the guides
+const NORMAL_SIGMA2: f64 = 0.1;
+const ALBEDO_SIGMA2: f64 = 0.01;
+const POSITION_SIGMA2: f64 = 0.001;
+
+/// Edge-avoiding à-trous wavelet filter (Dammertz et al. 2010) guided by the
+/// normal, albedo and position outputs. Textures are preserved by filtering
+/// the lighting, the color divided by the albedo
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Denoiser {
+    /// How much noise is removed, 0 keeps the image as is and higher values
+    /// blur more across lighting changes
+    pub strength: f64,
+    /// Number of passes, each one doubling the filter radius
+    pub iterations: u32,
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Denoiser {
+            strength: 1.,
+            iterations: 5,
+        }
+    }
+}
+
+impl Denoiser {
+    /// Filters `colors`, an image with the size and layout of `aovs`
+    pub fn denoise(&self, colors: &[Color], aovs: &Aovs) -> Vec<Color> {
+        if self.strength <= 0. {
+            return colors.to_vec();
+        }
+
+        let albedos: Vec<Color> = aovs
+            .pixels
+            .iter()
+            .map(|pixel| Color {
+                x: Self::demodulation(pixel.albedo.x),
+                y: Self::demodulation(pixel.albedo.y),
+                z: Self::demodulation(pixel.albedo.z),
+            })
+            .collect();
+        let mut lighting: Vec<Color> = colors
+            .iter()
+            .zip(&albedos)
+            .map(|(color, albedo)| Color {
+                x: color.x / albedo.x,
+                y: color.y / albedo.y,
+                z: color.z / albedo.z,
+            })
+            .collect();
+
+        // Scale of the scene, so the position weight does not depend on units
+        let scene_scale = aovs
+            .pixels
+            .iter()
+            .map(|pixel| pixel.depth)
+            .filter(|depth| depth.is_finite())
+            .fold(0., f64::max)
+            .max(1e-9);
+
+        for iteration in 0..self.iterations {
+            let step = 1 << iteration;
+            // The color weight gets stricter as the noise goes down
+            let color_sigma = self.strength / (1 << iteration) as f64;
+            lighting = self.filter_pass(&lighting, aovs, step, color_sigma, scene_scale);
+        }
+
+        lighting
+            .iter()
+            .zip(&albedos)
+            .map(|(light, albedo)| *light * *albedo)
+            .collect()
+    }
+
+    fn demodulation(albedo: f64) -> f64 {
+        if albedo < MIN_ALBEDO {
+            1.
+        } else {
+            albedo
+        }
+    }
+
+    fn filter_pass(
+        &self,
+        input: &[Color],
+        aovs: &Aovs,
+        step: i64,
+        color_sigma: f64,
+        scene_scale: f64,
+    ) -> Vec<Color> {
+        let (width, height) = (aovs.width as i64, aovs.height as i64);
+        let mut output = Vec::with_capacity(input.len());
+
+        for y in 0..height {
+            for x in 0..width {
+                let index = (y * width + x) as usize;
+                let center = &aovs.pixels[index];
+                let center_color = Self::perceptual(&input[index]);
+
+                let mut sum = Color::default();
+                let mut total_weight = 0.;
+                for (j, kernel_y) in KERNEL.iter().enumerate() {
+                    for (i, kernel_x) in KERNEL.iter().enumerate() {
+                        let qx = x + (i as i64 - 2) * step;
+                        let qy = y + (j as i64 - 2) * step;
+                        if qx < 0 || qy < 0 || qx >= width || qy >= height {
+                            continue;
+                        }
+                        let other_index = (qy * width + qx) as usize;
+                        let other = &aovs.pixels[other_index];
+
+                        // Background pixels are only mixed with each other
+                        if center.depth.is_finite() != other.depth.is_finite() {
+                            continue;
+                        }
+
+                        let color_distance =
+                            (Self::perceptual(&input[other_index]) - center_color).len_squared();
+                        let normal_distance = (other.normal - center.normal).len_squared();
+                        let albedo_distance = (other.albedo - center.albedo).len_squared();
+                        let position_distance = if center.depth.is_finite() {
+                            (other.position - center.position).len_squared()
+                                / (scene_scale * scene_scale)
+                        } else {
+                            0.
+                        };
+
+                        let weight = kernel_x
+                            * kernel_y
+                            * (-color_distance / (color_sigma * color_sigma)
+                                - normal_distance / NORMAL_SIGMA2
+                                - albedo_distance / ALBEDO_SIGMA2
+                                - position_distance / POSITION_SIGMA2)
+                                .exp();
+                        sum += weight * input[other_index];
+                        total_weight += weight;
+                    }
+                }
+
+                output.push(if total_weight > 0. {
+                    sum / total_weight
+                } else {
+                    input[index]
+                });
+            }
+        }
+        output
+    }
+
+    // Compresses bright values so they do not dominate the color distance
+    fn perceptual(color: &Color) -> Color {
+        Color {
+            x: color.x.max(0.).sqrt(),
+            y: color.y.max(0.).sqrt(),
+            z: color.z.max(0.).sqrt(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::aov::AovPixel;
+    use crate::denoise::*;
+
+    fn flat_aovs(width: u32, height: u32) -> Aovs {
+        let pixel = AovPixel {
+            depth: 1.,
+            normal: Vec3 {
+                x: 0.,
+                y: 0.,
+                z: 1.,
+            },
+            albedo: Color {
+                x: 0.5,
+                y: 0.5,
+                z: 0.5,
+            },
+            ..Default::default()
+        };
+        Aovs {
+            width,
+            height,
+            pixels: vec![pixel; (width * height) as usize],
+        }
+    }
+
+    fn gray(value: f64) -> Color {
+        Color {
+            x: value,
+            y: value,
+            z: value,
+        }
+    }
+
+    #[test]
+    fn test_noise_is_reduced() {
+        let aovs = flat_aovs(16, 16);
+        let colors: Vec<Color> = (0..256)
+            .map(|i| gray(if (i * 7919) % 5 < 2 { 0.6 } else { 0.4 }))
+            .collect();
+        let denoised = Denoiser::default().denoise(&colors, &aovs);
+
+        let variance = |colors: &[Color]| {
+            let mean = colors.iter().map(|color| color.x).sum::<f64>() / colors.len() as f64;
+            colors
+                .iter()
+                .map(|color| (color.x - mean).powi(2))
+                .sum::<f64>()
+                / colors.len() as f64
+        };
+        assert!(variance(&denoised) < 0.1 * variance(&colors));
+    }
+
+    #[test]
+    fn test_normal_edges_are_kept() {
+        let mut aovs = flat_aovs(8, 8);
+        let mut colors = vec![gray(0.2); 64];
+        for (index, pixel) in aovs.pixels.iter_mut().enumerate() {
+            if index % 8 >= 4 {
+                pixel.normal = Vec3 {
+                    x: 1.,
+                    y: 0.,
+                    z: 0.,
+                };
+                colors[index] = gray(0.8);
+            }
+        }
+        let denoised = Denoiser::default().denoise(&colors, &aovs);
+        assert!((denoised[3].x - 0.2).abs() < 0.01);
+        assert!((denoised[4].x - 0.8).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_zero_strength_keeps_image() {
+        let aovs = flat_aovs(2, 2);
+        let colors = vec![gray(0.1), gray(0.9), gray(0.3), gray(0.5)];
+        let denoiser = Denoiser {
+            strength: 0.,
+            iterations: 5,
+        };
+        assert_eq!(denoiser.denoise(&colors, &aovs), colors);
+    }
+}
@@ -0,0 +1,251 @@
+use std::f64::consts::PI;
+use std::io::{Error, ErrorKind, Result};
+
+use crate::hdr::HdrImage;
+use crate::sampler::Sampler;
+use crate::sampling::Distribution2D;
+use crate::vec3::*;
+
+/// Light arriving from infinitely far away when a ray leaves the scene
+pub trait Environment: Send + Sync {
+    fn color(&self, direction: &Vec3) -> Color;
+
+    /// Picks a direction towards the environment for direct lighting.
+    /// Returns the unit direction, the radiance along it and the solid angle
+    /// density of the choice. Environments without a sampling strategy return None
+    fn sample(&self, _sampler: &mut dyn Sampler) -> Option<(Vec3, Color, f64)> {
+        None
+    }
+
+    /// Solid angle density of `sample` choosing `direction`
+    fn pdf(&self, _direction: &Vec3) -> f64 {
+        0.
+    }
+}
+
+impl Default for Box<dyn Environment> {
+    fn default() -> Self {
+        Box::new(GradientEnvironment::default())
+    }
+}
+
+pub struct ConstantEnvironment {
+    pub color: Color,
+}
+
+impl Environment for ConstantEnvironment {
+    fn color(&self, _direction: &Vec3) -> Color {
+        self.color
+    }
+}
+
+/// Vertical blend between `bottom` and `top` colors
+pub struct GradientEnvironment {
+    pub bottom: Color,
+    pub top: Color,
+}
+
+impl Default for GradientEnvironment {
+    fn default() -> Self {
+        GradientEnvironment {
+            bottom: Color {
+                x: 1.0,
+                y: 1.0,
+                z: 1.0,
+            },
+            top: Color {
+                x: 0.5,
+                y: 0.7,
+                z: 1.0,
+            },
+        }
+    }
+}
+
+impl Environment for GradientEnvironment {
+    fn color(&self, direction: &Vec3) -> Color {
+        let unit_direction = direction.unit_vector();
+        let coeff = 0.5 * (unit_direction.y + 1.0);
+        (1.0 - coeff) * self.bottom + coeff * self.top
+    }
+}
+
+/// Equirectangular (latitude-longitude) environment map. The top row of the
+/// image is the +Y direction and the center of the image looks along -Z
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+    // Rotation around the Y axis in radians
+    rotation: f64,
+    intensity: f64,
+    distribution: Distribution2D,
+}
+
+impl EnvironmentMap {
+    /// `rotation` turns the map around the vertical axis (in degrees) and
+    /// `intensity` scales the radiance of every pixel. Fails on empty images
+    pub fn new(image: HdrImage, rotation: f64, intensity: f64) -> Result<Self> {
+        let HdrImage {
+            width,
+            height,
+            pixels,
+        } = image;
+        if width == 0 || height == 0 || pixels.len() != width * height {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Invalid environment map size",
+            ));
+        }
+
+        // Sample proportionally to the brightness, the sine compensates for
+        // rows near the poles covering a smaller solid angle
+        let mut func = Vec::with_capacity(width * height);
+        for j in 0..height {
+            let sin_theta = (PI * (j as f64 + 0.5) / height as f64).sin();
+            for i in 0..width {
+                func.push(pixels[j * width + i].luminance() * sin_theta);
+            }
+        }
+
+        Ok(EnvironmentMap {
+            width,
+            height,
+            pixels,
+            rotation: rotation.to_radians(),
+            intensity,
+            distribution: Distribution2D::new(&func, width, height),
+        })
+    }
+
+    pub fn load(filename: &str, rotation: f64, intensity: f64) -> Result<Self> {
+        Self::new(HdrImage::load(filename)?, rotation, intensity)
+    }
+
+    fn direction_to_uv(&self, direction: &Vec3) -> (f64, f64) {
+        let unit_direction = direction.unit_vector();
+        let theta = unit_direction.y.clamp(-1., 1.).acos();
+        let phi = unit_direction.z.atan2(unit_direction.x) - self.rotation;
+        let u = (phi / (2. * PI) + 0.75).rem_euclid(1.);
+        (u, theta / PI)
+    }
+
+    fn uv_to_direction(&self, u: f64, v: f64) -> Vec3 {
+        let theta = v * PI;
+        let phi = (u - 0.75) * 2. * PI + self.rotation;
+        Vec3 {
+            x: theta.sin() * phi.cos(),
+            y: theta.cos(),
+            z: theta.sin() * phi.sin(),
+        }
+    }
+
+    fn lookup(&self, u: f64, v: f64) -> Color {
+        let i = ((u * self.width as f64) as usize).min(self.width - 1);
+        let j = ((v * self.height as f64) as usize).min(self.height - 1);
+        self.intensity * self.pixels[j * self.width + i]
+    }
+}
+
+impl Environment for EnvironmentMap {
+    fn color(&self, direction: &Vec3) -> Color {
+        let (u, v) = self.direction_to_uv(direction);
+        self.lookup(u, v)
+    }
+
+    fn sample(&self, sampler: &mut dyn Sampler) -> Option<(Vec3, Color, f64)> {
+        let (u0, u1) = sampler.get_2d();
+        let ((u, v), pdf_uv) = self.distribution.sample_continuous(u0, u1);
+        let sin_theta = (v * PI).sin();
+        if pdf_uv == 0. || sin_theta == 0. {
+            return None;
+        }
+
+        // Convert the density from the image plane to solid angle
+        let pdf = pdf_uv / (2. * PI * PI * sin_theta);
+        Some((self.uv_to_direction(u, v), self.lookup(u, v), pdf))
+    }
+
+    fn pdf(&self, direction: &Vec3) -> f64 {
+        let (u, v) = self.direction_to_uv(direction);
+        let sin_theta = (v * PI).sin();
+        if sin_theta == 0. {
+            return 0.;
+        }
+        self.distribution.pdf(u, v) / (2. * PI * PI * sin_theta)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::environment::{Environment, EnvironmentMap};
+    use crate::hdr::HdrImage;
+    use crate::sampler::IndependentSampler;
+    use crate::vec3::{Color, Vec3};
+
+    fn single_bright_pixel_map(rotation: f64) -> EnvironmentMap {
+        let mut pixels = vec![Color::default(); 8 * 4];
+        pixels[8 + 2] = Color {
+            x: 10.,
+            y: 10.,
+            z: 10.,
+        };
+        let image = HdrImage {
+            width: 8,
+            height: 4,
+            pixels,
+        };
+        EnvironmentMap::new(image, rotation, 2.).unwrap()
+    }
+
+    #[test]
+    fn test_center_looks_forward() {
+        let map = EnvironmentMap::new(
+            HdrImage {
+                width: 1,
+                height: 1,
+                pixels: vec![Color::default()],
+            },
+            0.,
+            1.,
+        )
+        .unwrap();
+        let (u, v) = map.direction_to_uv(&Vec3 {
+            x: 0.,
+            y: 0.,
+            z: -1.,
+        });
+        assert!((u - 0.5).abs() < 1e-12);
+        assert!((v - 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_rejects_empty_map() {
+        let empty = HdrImage {
+            width: 0,
+            height: 0,
+            pixels: Vec::new(),
+        };
+        assert!(EnvironmentMap::new(empty, 0., 1.).is_err());
+    }
+
+    #[test]
+    fn test_uv_direction_round_trip() {
+        let map = single_bright_pixel_map(30.);
+        let (u, v) = map.direction_to_uv(&map.uv_to_direction(0.3, 0.7));
+        assert!((u - 0.3).abs() < 1e-9);
+        assert!((v - 0.7).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_samples_bright_pixel() {
+        let mut sampler = IndependentSampler::new(0);
+        let map = single_bright_pixel_map(45.);
+        for _ in 0..16 {
+            let (direction, color, pdf) = map.sample(&mut sampler).unwrap();
+            assert_eq!(color.x, 20.);
+            assert!((map.pdf(&direction) - pdf).abs() < 1e-9 * pdf);
+            assert_eq!(map.color(&direction), color);
+        }
+    }
+}
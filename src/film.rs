@@ -0,0 +1,259 @@
+use std::fs;
+use std::io::Result;
+
+use crate::aov::AovSums;
+use crate::vec3::*;
+
+// Smallest sum of the weights of a pixel, as a fraction of the sum of their
+// absolute values. Filters with negative lobes stay above it once converged,
+// Lanczos with a radius of 3 being at about 0.54
+const MIN_WEIGHT_FRACTION: f64 = 0.25;
+
+/// Samples accumulated in a pixel
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Pixel {
+    /// Sum of the samples weighted by the reconstruction filter
+    pub sum: Color,
+    pub weight_sum: f64,
+    pub abs_weight_sum: f64,
+    // Running sums of the luminance of the samples, used to estimate the noise
+    pub luminance_sum: f64,
+    pub luminance_squared_sum: f64,
+    pub samples: u32,
+    /// First hits of the samples, only accumulated when the camera renders
+    /// auxiliary outputs
+    pub aov: AovSums,
+}
+
+impl Pixel {
+    pub fn add(&mut self, color: Color) {
+        self.add_weighted(color, 1.);
+    }
+
+    /// Adds a sample with its reconstruction filter weight, which may be
+    /// negative
+    pub fn add_weighted(&mut self, color: Color, weight: f64) {
+        let luminance = color.luminance();
+        self.sum += weight * color;
+        self.weight_sum += weight;
+        self.abs_weight_sum += weight.abs();
+        self.luminance_sum += luminance;
+        self.luminance_squared_sum += luminance * luminance;
+        self.samples += 1;
+    }
+
+    /// Weighted average of the samples, black without any. With few samples
+    /// negative weights can cancel out the positive ones, the sum of the
+    /// weights is then bounded and the negative colors clamped
+    pub fn color(&self) -> Color {
+        let weight_sum = self
+            .weight_sum
+            .max(MIN_WEIGHT_FRACTION * self.abs_weight_sum);
+        if weight_sum == 0. {
+            return Color::default();
+        }
+        let color = self.sum / weight_sum;
+        Color {
+            x: color.x.max(0.),
+            y: color.y.max(0.),
+            z: color.z.max(0.),
+        }
+    }
+
+    /// Standard error of the mean luminance relative to the mean, which is
+    /// clamped to `min_luminance`. Infinite with fewer than two samples
+    pub fn relative_error(&self, min_luminance: f64) -> f64 {
+        if self.samples < 2 {
+            return f64::INFINITY;
+        }
+        let n = self.samples as f64;
+        let mean = self.luminance_sum / n;
+        let variance = (self.luminance_squared_sum / n - mean * mean).max(0.) * n / (n - 1.);
+        (variance / n).sqrt() / mean.max(min_luminance)
+    }
+}
+
+/// Accumulation buffer of an image, stored row by row from the top-left corner
+#[derive(Debug, Clone, PartialEq)]
+pub struct Film {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<Pixel>,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32) -> Self {
+        Film {
+            width,
+            height,
+            pixels: vec![Pixel::default(); (width * height) as usize],
+        }
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> &Pixel {
+        &self.pixels[(y * self.width + x) as usize]
+    }
+
+    pub fn pixel_mut(&mut self, x: u32, y: u32) -> &mut Pixel {
+        &mut self.pixels[(y * self.width + x) as usize]
+    }
+
+    /// Weighted averages of the pixels
+    pub fn colors(&self) -> Vec<Color> {
+        self.pixels.iter().map(Pixel::color).collect()
+    }
+
+    /// Largest number of samples taken in a pixel
+    pub fn max_samples(&self) -> u32 {
+        self.pixels
+            .iter()
+            .map(|pixel| pixel.samples)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// Replaces the pixels whose luminance is more than `ratio` times the one of
+/// their brightest neighbor by the average of their neighbors. Such isolated
+/// bright pixels are fireflies from rare high energy paths
+pub fn reject_outliers(colors: &mut [Color], width: u32, height: u32, ratio: f64) {
+    let input = colors.to_vec();
+    let (width, height) = (width as i64, height as i64);
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = Color::default();
+            let mut count = 0;
+            let mut brightest: f64 = 0.;
+            for (dx, dy) in (-1..=1).flat_map(|dy| (-1..=1).map(move |dx| (dx, dy))) {
+                let (nx, ny) = (x + dx, y + dy);
+                if (dx, dy) == (0, 0) || nx < 0 || ny < 0 || nx >= width || ny >= height {
+                    continue;
+                }
+                let neighbor = input[(ny * width + nx) as usize];
+                sum += neighbor;
+                count += 1;
+                brightest = brightest.max(neighbor.luminance());
+            }
+
+            let index = (y * width + x) as usize;
+            if count > 0 && input[index].luminance() > ratio * brightest {
+                colors[index] = sum / count as f64;
+            }
+        }
+    }
+}
+
+/// Writes linear colors, stored row by row from the top-left corner, as a
+/// portable float map: a small header followed by little-endian floats, rows
+/// going from the bottom to the top of the image
+pub fn save_pfm(width: u32, height: u32, colors: &[Color], filename: &str) -> Result<()> {
+    let mut bytes = format!("PF\n{width} {height}\n-1.0\n").into_bytes();
+    for row in colors.chunks(width as usize).take(height as usize).rev() {
+        for color in row {
+            for value in [color.x, color.y, color.z] {
+                bytes.extend((value as f32).to_le_bytes());
+            }
+        }
+    }
+    fs::write(filename, bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::film::*;
+
+    #[test]
+    fn test_pixel_average() {
+        let mut pixel = Pixel::default();
+        assert_eq!(pixel.color(), Color::default());
+        pixel.add(Color {
+            x: 1.,
+            y: 0.,
+            z: 0.,
+        });
+        pixel.add(Color {
+            x: 0.,
+            y: 0.,
+            z: 1.,
+        });
+        assert_eq!(
+            pixel.color(),
+            Color {
+                x: 0.5,
+                y: 0.,
+                z: 0.5,
+            }
+        );
+    }
+
+    #[test]
+    fn test_weighted_average() {
+        let mut pixel = Pixel::default();
+        pixel.add_weighted(
+            Color {
+                x: 1.,
+                y: 1.,
+                z: 1.,
+            },
+            3.,
+        );
+        pixel.add_weighted(Color::default(), 1.);
+        assert_eq!(pixel.color().x, 0.75);
+        assert_eq!(pixel.samples, 2);
+    }
+
+    #[test]
+    fn test_negative_weights_stay_bounded() {
+        let bright = Color {
+            x: 10.,
+            y: 10.,
+            z: 10.,
+        };
+        // A sample in a negative lobe cancelling out the weight of another
+        let mut pixel = Pixel::default();
+        pixel.add_weighted(Color::default(), 0.1);
+        pixel.add_weighted(bright, -0.1);
+        assert_eq!(pixel.color(), Color::default());
+
+        let mut pixel = Pixel::default();
+        pixel.add_weighted(bright, 0.6);
+        pixel.add_weighted(Color::default(), -0.4);
+        assert_eq!(pixel.color().x, 24.);
+    }
+
+    #[test]
+    fn test_outliers_are_replaced() {
+        let gray = |value: f64| Color {
+            x: value,
+            y: value,
+            z: value,
+        };
+        let mut colors = vec![gray(0.5); 9];
+        colors[4] = gray(50.);
+        colors[0] = gray(0.9);
+        reject_outliers(&mut colors, 3, 3, 4.);
+        assert!((colors[4].x - 0.55).abs() < 1e-12);
+        // Brighter pixels next to similar ones are kept
+        assert_eq!(colors[0], gray(0.9));
+    }
+
+    #[test]
+    fn test_relative_error() {
+        let gray = |value: f64| Color {
+            x: value,
+            y: value,
+            z: value,
+        };
+        let mut pixel = Pixel::default();
+        pixel.add(gray(1.));
+        assert_eq!(pixel.relative_error(0.01), f64::INFINITY);
+        pixel.add(gray(1.));
+        assert!(pixel.relative_error(0.01) < 1e-6);
+
+        // Two samples 0 and 2: standard deviation √2, error of the mean 1
+        let mut noisy = Pixel::default();
+        noisy.add(gray(0.));
+        noisy.add(gray(2.));
+        assert!((noisy.relative_error(0.01) - 1.).abs() < 1e-9);
+    }
+}
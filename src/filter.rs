@@ -0,0 +1,255 @@
+use std::f64::consts::PI;
+
+use crate::sampling::Distribution1D;
+
+// Number of values of a filter tabulated along each axis for sampling
+const FILTER_TABLE_SIZE: usize = 64;
+
+/// Pixel reconstruction filter. Each pixel takes its samples over the
+/// support of the filter around its center and weights them by the filter,
+/// wider filters giving smoother edges and narrower ones sharper images
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    /// Plain average of the samples, radius 0.5 covers exactly the pixel
+    Box { radius: f64 },
+    /// Weights falling linearly to zero at the radius
+    Tent { radius: f64 },
+    /// Gaussian shifted down to reach zero at the radius
+    Gaussian { radius: f64, sigma: f64 },
+    /// Mitchell-Netravali cubic, b = c = 1/3 being the usual compromise
+    /// between blurring and ringing
+    Mitchell { radius: f64, b: f64, c: f64 },
+    /// Sinc windowed by a wider sinc, sharp with slight ringing
+    Lanczos { radius: f64, tau: f64 },
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter::Box { radius: 0.5 }
+    }
+}
+
+impl Filter {
+    pub fn mitchell(radius: f64) -> Self {
+        Filter::Mitchell {
+            radius,
+            b: 1. / 3.,
+            c: 1. / 3.,
+        }
+    }
+
+    pub fn lanczos(radius: f64) -> Self {
+        Filter::Lanczos { radius, tau: 3. }
+    }
+
+    /// Half width of the square support, in pixels
+    pub fn radius(&self) -> f64 {
+        match *self {
+            Filter::Box { radius }
+            | Filter::Tent { radius }
+            | Filter::Gaussian { radius, .. }
+            | Filter::Mitchell { radius, .. }
+            | Filter::Lanczos { radius, .. } => radius,
+        }
+    }
+
+    /// Weight of a sample at the offset (x, y) in pixels from the pixel
+    /// center. Mitchell and Lanczos have negative lobes
+    pub fn evaluate(&self, x: f64, y: f64) -> f64 {
+        self.evaluate_1d(x) * self.evaluate_1d(y)
+    }
+
+    fn evaluate_1d(&self, x: f64) -> f64 {
+        let radius = self.radius();
+        if x.abs() > radius {
+            return 0.;
+        }
+        match *self {
+            Filter::Box { .. } => 1.,
+            Filter::Tent { .. } => 1. - x.abs() / radius,
+            Filter::Gaussian { sigma, .. } => {
+                let gaussian = |x: f64| (-x * x / (2. * sigma * sigma)).exp();
+                (gaussian(x) - gaussian(radius)).max(0.)
+            }
+            Filter::Mitchell { b, c, .. } => {
+                // The cubic is defined over [-2, 2]
+                let x = (2. * x / radius).abs();
+                if x > 1. {
+                    ((-b - 6. * c) * x.powi(3)
+                        + (6. * b + 30. * c) * x.powi(2)
+                        + (-12. * b - 48. * c) * x
+                        + (8. * b + 24. * c))
+                        / 6.
+                } else {
+                    ((12. - 9. * b - 6. * c) * x.powi(3)
+                        + (-18. + 12. * b + 6. * c) * x.powi(2)
+                        + (6. - 2. * b))
+                        / 6.
+                }
+            }
+            Filter::Lanczos { tau, .. } => sinc(x) * sinc(x / tau),
+        }
+    }
+}
+
+/// Places samples around the pixel center with a density following the
+/// absolute value of a filter, tabulated along each axis. Samples then have
+/// weights of the same magnitude, so few samples in negative lobes can not
+/// cancel out the others
+pub struct FilterSampler {
+    pub filter: Filter,
+    // Filter values over [-radius, radius] and the density following them
+    values: Vec<f64>,
+    distribution: Distribution1D,
+}
+
+impl FilterSampler {
+    /// Panics when the radius, the sigma of a Gaussian or the tau of Lanczos
+    /// is not positive and finite, as the filter would weigh every sample 0
+    /// or NaN
+    pub fn new(filter: Filter) -> Self {
+        let radius = filter.radius();
+        let positive = |value: f64| value > 0. && value.is_finite();
+        let valid = positive(radius)
+            && match filter {
+                Filter::Gaussian { sigma, .. } => positive(sigma),
+                Filter::Mitchell { b, c, .. } => b.is_finite() && c.is_finite(),
+                Filter::Lanczos { tau, .. } => positive(tau),
+                Filter::Box { .. } | Filter::Tent { .. } => true,
+            };
+        if !valid {
+            panic!("Invalid filter {filter:?}, its radius, sigma and tau must be positive");
+        }
+        let values: Vec<f64> = (0..FILTER_TABLE_SIZE)
+            .map(|i| {
+                let x = ((i as f64 + 0.5) / FILTER_TABLE_SIZE as f64 * 2. - 1.) * radius;
+                filter.evaluate_1d(x)
+            })
+            .collect();
+        FilterSampler {
+            filter,
+            distribution: Distribution1D::new(&values),
+            values,
+        }
+    }
+
+    /// Offset in pixels from the pixel center for the uniform sample `u`, and
+    /// the weight of the sample: the tabulated filter value over the density
+    pub fn sample(&self, u: (f64, f64)) -> (f64, f64, f64) {
+        let radius = self.filter.radius();
+        let axis = |u: f64| {
+            let (t, pdf, bin) = self.distribution.sample_continuous(u);
+            ((2. * t - 1.) * radius, self.values[bin] * 2. * radius / pdf)
+        };
+        let (x, weight_x) = axis(u.0);
+        let (y, weight_y) = axis(u.1);
+        (x, y, weight_x * weight_y)
+    }
+}
+
+impl Default for FilterSampler {
+    fn default() -> Self {
+        Self::new(Filter::default())
+    }
+}
+
+// Normalized sinc, sin(πx) / (πx)
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-5 {
+        1.
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::panic;
+
+    use crate::filter::*;
+
+    #[test]
+    fn test_invalid_filters_are_rejected() {
+        for filter in [
+            Filter::Box { radius: 0. },
+            Filter::Tent { radius: f64::NAN },
+            Filter::Gaussian {
+                radius: 1.5,
+                sigma: 0.,
+            },
+            Filter::Lanczos {
+                radius: 2.,
+                tau: -1.,
+            },
+            Filter::mitchell(f64::INFINITY),
+        ] {
+            assert!(
+                panic::catch_unwind(|| FilterSampler::new(filter)).is_err(),
+                "{filter:?}"
+            );
+        }
+        FilterSampler::new(Filter::mitchell(2.));
+    }
+
+    #[test]
+    fn test_weights_vanish_outside_radius() {
+        for filter in [
+            Filter::Box { radius: 0.5 },
+            Filter::Tent { radius: 1. },
+            Filter::Gaussian {
+                radius: 1.5,
+                sigma: 0.5,
+            },
+            Filter::mitchell(2.),
+            Filter::lanczos(3.),
+        ] {
+            let radius = filter.radius();
+            assert!(filter.evaluate(0., 0.) > 0.);
+            assert_eq!(filter.evaluate(radius + 0.01, 0.), 0.);
+            assert_eq!(filter.evaluate(0., -radius - 0.01), 0.);
+            // Continuous filters reach zero at the edge
+            if !matches!(filter, Filter::Box { .. }) {
+                assert!(filter.evaluate(radius, 0.).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn test_known_values() {
+        let tent = Filter::Tent { radius: 2. };
+        assert!((tent.evaluate(1., 0.) - 0.5).abs() < 1e-12);
+        assert!((tent.evaluate(1., 1.) - 0.25).abs() < 1e-12);
+
+        // Mitchell-Netravali with b = c = 1/3 is 8/9 at the center along each
+        // axis and has a negative lobe past half the radius
+        let mitchell = Filter::mitchell(2.);
+        assert!((mitchell.evaluate(0., 0.) - 64. / 81.).abs() < 1e-12);
+        assert!(mitchell.evaluate(1.5, 0.) < 0.);
+
+        // Lanczos vanishes at the integers
+        let lanczos = Filter::lanczos(3.);
+        assert!(lanczos.evaluate(1., 0.).abs() < 1e-12);
+        assert!(lanczos.evaluate(1.5, 0.) < 0.);
+    }
+
+    #[test]
+    fn test_sampled_weights_are_even() {
+        let sampler = FilterSampler::new(Filter::mitchell(2.));
+        let n = 400;
+        let mut total = 0.;
+        let mut negative = 0;
+        for i in 0..n {
+            for j in 0..n {
+                let u = ((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64);
+                let (x, y, weight) = sampler.sample(u);
+                assert!(x.abs() <= 2. && y.abs() <= 2.);
+                assert!((weight.abs() - sampler.sample((0.5, 0.5)).2).abs() < 1e-9);
+                total += weight;
+                negative += (weight < 0.) as u32;
+            }
+        }
+        // The filter integrates to 1, and its negative lobes are small
+        assert!((total / (n * n) as f64 - 1.).abs() < 0.02);
+        assert!(negative < n * n / 10);
+    }
+}
@@ -1,15 +1,26 @@
 use crate::interval::Interval;
 use crate::material::Material;
+use crate::onb::Onb;
 use crate::ray::Ray;
 use crate::vec3::*;
-use std::rc::Rc;
+use std::sync::Arc;
 
+#[derive(Clone)]
 pub struct HitRecord {
     pub point: Point3,
     pub normal: Vec3,
-    pub material: Rc<dyn Material>,
+    pub material: Arc<dyn Material>,
     pub t: f64,
+    // Surface coordinates used for texture lookups
+    pub u: f64,
+    pub v: f64,
+    // Unit vectors along the directions of growing `u` and `v`, perpendicular
+    // to the outward normal. They are not flipped with the normal
+    pub tangent: Vec3,
+    pub bitangent: Vec3,
     pub front_face: bool,
+    // Position of the hit object in the scene list
+    pub object_id: usize,
 }
 
 impl HitRecord {
@@ -23,18 +34,45 @@ impl HitRecord {
         };
     }
 
-    fn make_default(material: Rc<dyn Material>) -> Self {
+    /// Normal pointing out of the surface, regardless of the side it was hit from
+    pub fn outward_normal(&self) -> Vec3 {
+        if self.front_face {
+            self.normal
+        } else {
+            -self.normal
+        }
+    }
+
+    // Builds the tangent frame from the derivative of the position along `u`.
+    // outward_normal must be unit length
+    fn set_tangent_frame(&mut self, outward_normal: &Vec3, dpdu: &Vec3) {
+        let tangent = *dpdu - outward_normal.dot(dpdu) * *outward_normal;
+        self.tangent = if tangent.near_zero() {
+            // Degenerate parametrization, any perpendicular direction will do
+            Onb::new(outward_normal).u
+        } else {
+            tangent.unit_vector()
+        };
+        self.bitangent = outward_normal.cross(&self.tangent);
+    }
+
+    fn make_default(material: Arc<dyn Material>) -> Self {
         HitRecord {
             point: Point3::default(),
             normal: Vec3::default(),
             material,
             t: 0.,
+            u: 0.,
+            v: 0.,
+            tangent: Vec3::default(),
+            bitangent: Vec3::default(),
             front_face: false,
+            object_id: 0,
         }
     }
 }
 
-pub trait Hittable {
+pub trait Hittable: Send + Sync {
     fn hit(&self, ray: &mut Ray, ray_t: &Interval) -> Option<HitRecord>;
 }
 
@@ -62,16 +100,24 @@ impl<'a> HittableList<'a> {
         let mut closest_so_far = ray_t.max;
         let mut result: Option<HitRecord> = None;
 
-        for object in self.objects.iter() {
-            if let Some(record) = object.hit(
+        for (object_id, object) in self.objects.iter().enumerate() {
+            let mut min = ray_t.min;
+            while let Some(mut record) = object.hit(
                 ray,
                 &Interval {
-                    min: ray_t.min,
+                    min,
                     max: closest_so_far,
                 },
             ) {
+                // Look for the next hit behind cut out parts of the surface
+                if !record.material.is_visible(ray, &record) {
+                    min = record.t;
+                    continue;
+                }
                 closest_so_far = record.t;
+                record.object_id = object_id;
                 result = Some(record);
+                break;
             }
         }
 
@@ -82,7 +128,20 @@ impl<'a> HittableList<'a> {
 pub struct Sphere {
     pub center: Point3,
     pub radius: f64,
-    pub material: Rc<dyn Material>,
+    pub material: Arc<dyn Material>,
+}
+
+impl Sphere {
+    // `point` is a point on the unit sphere. `u` goes around the Y axis
+    // starting from -X and `v` from the bottom to the top
+    fn uv(point: &Point3) -> (f64, f64) {
+        let theta = (-point.y).clamp(-1., 1.).acos();
+        let phi = (-point.z).atan2(point.x) + std::f64::consts::PI;
+        (
+            phi / (2. * std::f64::consts::PI),
+            theta / std::f64::consts::PI,
+        )
+    }
 }
 
 impl Hittable for Sphere {
@@ -111,7 +170,219 @@ impl Hittable for Sphere {
         record.point = ray.at(record.t);
         let outward_normal = (record.point - self.center) / self.radius;
         record.set_face_normal(ray, &outward_normal);
+        (record.u, record.v) = Self::uv(&outward_normal);
+        // Moving along `u` turns the point around the Y axis
+        let dpdu = Vec3 {
+            x: outward_normal.z,
+            y: 0.,
+            z: -outward_normal.x,
+        };
+        record.set_tangent_frame(&outward_normal, &dpdu);
+
+        Some(record)
+    }
+}
+
+pub struct Triangle {
+    pub vertices: [Point3; 3],
+    // Texture coordinates of each vertex
+    pub uvs: [(f64, f64); 3],
+    pub material: Arc<dyn Material>,
+}
+
+impl Triangle {
+    /// Triangle with the texture coordinates (0, 0), (1, 0) and (0, 1)
+    pub fn new(vertices: [Point3; 3], material: Arc<dyn Material>) -> Self {
+        Triangle {
+            vertices,
+            uvs: [(0., 0.), (1., 0.), (0., 1.)],
+            material,
+        }
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, ray: &mut Ray, ray_t: &Interval) -> Option<HitRecord> {
+        // Moller-Trumbore intersection
+        let [p0, p1, p2] = self.vertices;
+        let edge1 = p1 - p0;
+        let edge2 = p2 - p0;
+        let p = ray.dir.cross(&edge2);
+        let determinant = edge1.dot(&p);
+        if determinant.abs() < 1e-12 {
+            return None;
+        }
+        let inverse_determinant = 1. / determinant;
+
+        let s = ray.orig - p0;
+        let b1 = s.dot(&p) * inverse_determinant;
+        if !(0. ..=1.).contains(&b1) {
+            return None;
+        }
+        let q = s.cross(&edge1);
+        let b2 = ray.dir.dot(&q) * inverse_determinant;
+        if b2 < 0. || b1 + b2 > 1. {
+            return None;
+        }
+        let t = edge2.dot(&q) * inverse_determinant;
+        if !ray_t.surrounds(t) {
+            return None;
+        }
+
+        let mut record = HitRecord::make_default(self.material.clone());
+        record.t = t;
+        record.point = ray.at(t);
+        let outward_normal = edge1.cross(&edge2).unit_vector();
+        record.set_face_normal(ray, &outward_normal);
+
+        let [(u0, v0), (u1, v1), (u2, v2)] = self.uvs;
+        let b0 = 1. - b1 - b2;
+        record.u = b0 * u0 + b1 * u1 + b2 * u2;
+        record.v = b0 * v0 + b1 * v1 + b2 * v2;
+
+        // Solve for the derivative of the position along `u` from the edges
+        let (du1, dv1) = (u1 - u0, v1 - v0);
+        let (du2, dv2) = (u2 - u0, v2 - v0);
+        let uv_determinant = du1 * dv2 - du2 * dv1;
+        let dpdu = if uv_determinant.abs() < 1e-12 {
+            Vec3::default()
+        } else {
+            (dv2 * edge1 - dv1 * edge2) / uv_determinant
+        };
+        record.set_tangent_frame(&outward_normal, &dpdu);
+        // Keep the bitangent along growing `v` even for mirrored texture coordinates
+        if uv_determinant < 0. {
+            record.bitangent = -record.bitangent;
+        }
 
         Some(record)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::geometry::*;
+    use crate::material::{AlphaMask, AlphaMode, Lambertian};
+    use crate::texture::SolidColor;
+
+    fn material() -> Arc<dyn Material> {
+        Arc::new(Lambertian {
+            albedo: Color::default(),
+        })
+    }
+
+    #[test]
+    fn test_triangle_hit() {
+        let triangle = Triangle::new(
+            [
+                Point3 {
+                    x: 0.,
+                    y: 0.,
+                    z: -1.,
+                },
+                Point3 {
+                    x: 1.,
+                    y: 0.,
+                    z: -1.,
+                },
+                Point3 {
+                    x: 0.,
+                    y: 1.,
+                    z: -1.,
+                },
+            ],
+            material(),
+        );
+        let mut ray = Ray::new(
+            Point3 {
+                x: 0.25,
+                y: 0.5,
+                z: 0.,
+            },
+            Vec3 {
+                x: 0.,
+                y: 0.,
+                z: -1.,
+            },
+        );
+        let record = triangle.hit(&mut ray, &Interval::new(0., 10.)).unwrap();
+        assert!((record.t - 1.).abs() < 1e-12);
+        assert!((record.u - 0.25).abs() < 1e-12);
+        assert!((record.v - 0.5).abs() < 1e-12);
+        assert!(record.front_face);
+        assert!((record.tangent.x - 1.).abs() < 1e-12);
+        assert!((record.bitangent.y - 1.).abs() < 1e-12);
+
+        ray.orig.x = 0.75;
+        assert!(triangle.hit(&mut ray, &Interval::new(0., 10.)).is_none());
+    }
+
+    #[test]
+    fn test_sphere_tangent_frame() {
+        let sphere = Sphere {
+            center: Point3::default(),
+            radius: 2.,
+            material: material(),
+        };
+        let mut ray = Ray::new(
+            Point3 {
+                x: 0.3,
+                y: 0.4,
+                z: 5.,
+            },
+            Vec3 {
+                x: 0.,
+                y: 0.,
+                z: -1.,
+            },
+        );
+        let record = sphere.hit(&mut ray, &Interval::new(0., 10.)).unwrap();
+        assert!(record.tangent.dot(&record.normal).abs() < 1e-12);
+        assert!(record.bitangent.dot(&record.normal).abs() < 1e-12);
+        assert!((record.tangent.cross(&record.bitangent) - record.normal).len() < 1e-12);
+        // `v` grows towards the top of the sphere
+        assert!(record.bitangent.y > 0.);
+    }
+
+    #[test]
+    fn test_list_skips_cut_out_surfaces() {
+        let mut world = HittableList::new();
+        world.add(Sphere {
+            center: Point3::default(),
+            radius: 1.,
+            material: Arc::new(AlphaMask {
+                material: material(),
+                opacity: Arc::new(SolidColor::gray(0.)),
+                mode: AlphaMode::Threshold(0.5),
+            }),
+        });
+        world.add(Sphere {
+            center: Point3 {
+                x: 0.,
+                y: 0.,
+                z: -5.,
+            },
+            radius: 1.,
+            material: material(),
+        });
+
+        let mut ray = Ray::new(
+            Point3 {
+                x: 0.,
+                y: 0.,
+                z: 5.,
+            },
+            Vec3 {
+                x: 0.,
+                y: 0.,
+                z: -1.,
+            },
+        );
+        let record = world
+            .hit(&mut ray, &Interval::new(0.001, f64::INFINITY))
+            .unwrap();
+        assert!((record.t - 9.).abs() < 1e-12);
+    }
+}
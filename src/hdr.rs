@@ -0,0 +1,225 @@
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+
+use crate::vec3::Color;
+
+/// Floating point image loaded from a Radiance `.hdr` (RGBE) file.
+/// Pixels are stored row by row starting from the top-left corner
+pub struct HdrImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Color>,
+}
+
+// Largest width or height accepted, far above real environment maps, so a
+// corrupted header can not request a huge allocation
+const MAX_DIMENSION: usize = 1 << 16;
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+impl HdrImage {
+    pub fn load(filename: &str) -> Result<Self> {
+        Self::decode(&fs::read(filename)?)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let mut position = 0;
+        let mut read_line = || -> Result<String> {
+            let end = bytes[position..]
+                .iter()
+                .position(|&byte| byte == b'\n')
+                .ok_or_else(|| invalid_data("Unexpected end of the HDR header"))?;
+            let line = String::from_utf8_lossy(&bytes[position..position + end]).into_owned();
+            position += end + 1;
+            Ok(line)
+        };
+
+        let magic = read_line()?;
+        if !magic.starts_with("#?") {
+            return Err(invalid_data("Not a Radiance HDR file"));
+        }
+
+        // Header variables are terminated by an empty line
+        loop {
+            let line = read_line()?;
+            if line.is_empty() {
+                break;
+            }
+            if let Some(format) = line.strip_prefix("FORMAT=") {
+                if format.trim() != "32-bit_rle_rgbe" {
+                    return Err(invalid_data("Only the RGBE pixel format is supported"));
+                }
+            }
+        }
+
+        // Only the standard orientation is supported: rows from top to bottom,
+        // pixels from left to right
+        let resolution = read_line()?;
+        let (height, width) = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+            ["-Y", height, "+X", width] => (height.parse::<usize>(), width.parse::<usize>()),
+            _ => return Err(invalid_data("Unsupported HDR image orientation")),
+        };
+        let (height, width) = match (height, width) {
+            (Ok(height), Ok(width))
+                if (1..=MAX_DIMENSION).contains(&height)
+                    && (1..=MAX_DIMENSION).contains(&width) =>
+            {
+                (height, width)
+            }
+            _ => return Err(invalid_data("Invalid HDR image resolution")),
+        };
+
+        let mut data = &bytes[position..];
+        // Run-length encoding packs at most 127 pixels in 2 bytes per channel
+        let mut pixels = Vec::with_capacity((width * height).min(data.len() * 64));
+        let mut scanline = vec![[0u8; 4]; width];
+        for _ in 0..height {
+            data = Self::read_scanline(data, &mut scanline)?;
+            pixels.extend(scanline.iter().map(Self::rgbe_to_color));
+        }
+
+        Ok(HdrImage {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    fn rgbe_to_color(rgbe: &[u8; 4]) -> Color {
+        if rgbe[3] == 0 {
+            return Color::default();
+        }
+        let scale = 2f64.powi(rgbe[3] as i32 - (128 + 8));
+        Color {
+            x: (rgbe[0] as f64 + 0.5) * scale,
+            y: (rgbe[1] as f64 + 0.5) * scale,
+            z: (rgbe[2] as f64 + 0.5) * scale,
+        }
+    }
+
+    // Reads one scanline into `scanline` and returns the rest of the data
+    fn read_scanline<'a>(data: &'a [u8], scanline: &mut [[u8; 4]]) -> Result<&'a [u8]> {
+        let width = scanline.len();
+        let truncated = || invalid_data("Unexpected end of the HDR pixel data");
+
+        let is_run_length_encoded = (8..0x8000).contains(&width)
+            && data.len() >= 4
+            && data[0] == 2
+            && data[1] == 2
+            && ((data[2] as usize) << 8 | data[3] as usize) == width;
+
+        if !is_run_length_encoded {
+            if data.len() >= 4 && data[0] == 1 && data[1] == 1 && data[2] == 1 {
+                return Err(invalid_data(
+                    "Old-style HDR run-length encoding is not supported",
+                ));
+            }
+            if data.len() < width * 4 {
+                return Err(truncated());
+            }
+            for (pixel, rgbe) in scanline.iter_mut().zip(data.chunks_exact(4)) {
+                pixel.copy_from_slice(rgbe);
+            }
+            return Ok(&data[width * 4..]);
+        }
+
+        // Each channel is encoded separately as a sequence of runs and literals
+        let mut data = &data[4..];
+        for channel in 0..4 {
+            let mut x = 0;
+            while x < width {
+                let (&count, rest) = data.split_first().ok_or_else(truncated)?;
+                data = rest;
+                if count > 128 {
+                    let count = (count - 128) as usize;
+                    let (&value, rest) = data.split_first().ok_or_else(truncated)?;
+                    data = rest;
+                    if x + count > width {
+                        return Err(invalid_data("HDR run exceeds the scanline"));
+                    }
+                    for pixel in scanline[x..x + count].iter_mut() {
+                        pixel[channel] = value;
+                    }
+                    x += count;
+                } else {
+                    let count = count as usize;
+                    if count == 0 || x + count > width {
+                        return Err(invalid_data("Invalid HDR literal run"));
+                    }
+                    if data.len() < count {
+                        return Err(truncated());
+                    }
+                    for (pixel, &value) in scanline[x..x + count].iter_mut().zip(data) {
+                        pixel[channel] = value;
+                    }
+                    data = &data[count..];
+                    x += count;
+                }
+            }
+        }
+
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::ErrorKind;
+
+    use crate::hdr::HdrImage;
+
+    const HEADER: &[u8] = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n";
+
+    #[test]
+    fn test_decode_flat() {
+        let mut bytes = HEADER.to_vec();
+        bytes.extend_from_slice(b"-Y 1 +X 2\n");
+        bytes.extend_from_slice(&[127, 0, 0, 129, 0, 0, 0, 0]);
+
+        let image = HdrImage::decode(&bytes).unwrap();
+        assert_eq!((image.width, image.height), (2, 1));
+        assert_eq!(image.pixels[0].x, 127.5 / 128.);
+        assert_eq!(image.pixels[0].y, 0.5 / 128.);
+        assert_eq!(image.pixels[1].x, 0.);
+    }
+
+    #[test]
+    fn test_decode_run_length_encoded() {
+        let mut bytes = HEADER.to_vec();
+        bytes.extend_from_slice(b"-Y 1 +X 8\n");
+        bytes.extend_from_slice(&[2, 2, 0, 8]);
+        // Red: a run of eight values
+        bytes.extend_from_slice(&[128 + 8, 63]);
+        // Green: four literals and a run of four
+        bytes.extend_from_slice(&[4, 0, 1, 2, 3, 128 + 4, 0]);
+        // Blue and exponent: runs
+        bytes.extend_from_slice(&[128 + 8, 0, 128 + 8, 129]);
+
+        let image = HdrImage::decode(&bytes).unwrap();
+        assert_eq!(image.pixels.len(), 8);
+        assert_eq!(image.pixels[7].x, 63.5 / 128.);
+        assert_eq!(image.pixels[3].y, 3.5 / 128.);
+    }
+
+    #[test]
+    fn test_decode_rejects_garbage() {
+        assert!(HdrImage::decode(b"P6\n1 1\n255\n").is_err());
+        let mut truncated = HEADER.to_vec();
+        truncated.extend_from_slice(b"-Y 2 +X 2\n");
+        truncated.extend_from_slice(&[1, 2, 3]);
+        assert!(HdrImage::decode(&truncated).is_err());
+
+        for resolution in [
+            "-Y 0 +X 0\n",
+            "-Y 1 +X 0\n",
+            "-Y 4000000000 +X 4000000000\n",
+        ] {
+            let mut bytes = HEADER.to_vec();
+            bytes.extend_from_slice(resolution.as_bytes());
+            let error = HdrImage::decode(&bytes).err().unwrap();
+            assert_eq!(error.kind(), ErrorKind::InvalidData);
+        }
+    }
+}
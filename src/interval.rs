@@ -48,14 +48,14 @@ mod test {
     #[test]
     fn test_contains() {
         let interval = Interval { min: -1., max: 1. };
-        assert_eq!(interval.contains(-0.5), true);
-        assert_eq!(interval.contains(-1.5), false);
+        assert!(interval.contains(-0.5));
+        assert!(!interval.contains(-1.5));
     }
 
     #[test]
     fn test_surrounds() {
         let interval = Interval { min: -1., max: 1. };
-        assert_eq!(interval.surrounds(-0.5), true);
-        assert_eq!(interval.surrounds(-1.), false);
+        assert!(interval.surrounds(-0.5));
+        assert!(!interval.surrounds(-1.));
     }
 }
@@ -0,0 +1,182 @@
+use std::f64::consts::PI;
+
+use crate::vec3::*;
+
+/// Opening of a thin lens, giving depth of field. Out of focus highlights
+/// take the shape of the aperture
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aperture {
+    /// Radius of the circle around the aperture, in world units
+    pub radius: f64,
+    /// Distance in front of the camera of the plane in focus
+    pub focus_distance: f64,
+    /// Number of diaphragm blades, the aperture is round below 3
+    pub blades: u32,
+    /// Rotation of the blades in degrees
+    pub rotation: f64,
+}
+
+impl Aperture {
+    /// Uniformly distributed point of the aperture, centered on the origin
+    pub fn sample(&self, (u, v): (f64, f64)) -> (f64, f64) {
+        if self.blades < 3 {
+            let radius = self.radius * u.sqrt();
+            let angle = 2. * PI * v;
+            return (radius * angle.cos(), radius * angle.sin());
+        }
+
+        // Pick one of the triangles joining the center to the sides of the
+        // polygon, all of the same area, then a point inside it
+        let blades = self.blades as f64;
+        let blade = (u * blades).floor().min(blades - 1.);
+        let u = u * blades - blade;
+        let vertex = |index: f64| {
+            let angle = self.rotation.to_radians() + 2. * PI * index / blades;
+            (self.radius * angle.cos(), self.radius * angle.sin())
+        };
+        let (first, second) = (vertex(blade), vertex(blade + 1.));
+        let scale = u.sqrt();
+        (
+            scale * ((1. - v) * first.0 + v * second.0),
+            scale * ((1. - v) * first.1 + v * second.1),
+        )
+    }
+
+    /// Moves a camera space ray to the point (x, y) of the lens, keeping
+    /// where it crosses the plane in focus. Rays not going forward, from
+    /// wide fisheyes and panoramas, are focused at the focus distance along
+    /// the ray
+    pub fn defocus(&self, origin: Point3, direction: Vec3, (x, y): (f64, f64)) -> (Point3, Vec3) {
+        let distance = if direction.z < 0. {
+            self.focus_distance / -direction.z
+        } else {
+            self.focus_distance / direction.len()
+        };
+        let focus = origin + distance * direction;
+        let lens_origin = origin + Vec3 { x, y, z: 0. };
+        (lens_origin, focus - lens_origin)
+    }
+}
+
+/// Imperfections of a photographic lens. All are off by default
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct LensEffects {
+    /// Radial distortion, positive values bending straight lines outward
+    /// (barrel) and negative ones inward (pincushion)
+    pub distortion: f64,
+    /// Darkening toward the edges, 1 following the cos⁴ law of natural
+    /// vignetting
+    pub vignetting: f64,
+    /// Lateral chromatic aberration, the difference of magnification of the
+    /// red and blue channels relative to green
+    pub chromatic_aberration: f64,
+    pub aperture: Option<Aperture>,
+}
+
+impl LensEffects {
+    /// Point of the viewport seen through the point (x, y) of the image, the
+    /// center of the viewport being the optical axis. `channel` is the color
+    /// channel the ray is traced for, when tracing them separately
+    pub fn distort(&self, x: f64, y: f64, channel: Option<usize>) -> (f64, f64) {
+        let magnification = match channel {
+            Some(channel) => 1. + self.chromatic_aberration * (channel as f64 - 1.),
+            None => 1.,
+        };
+        let scale = magnification * (1. + self.distortion * (x * x + y * y));
+        (scale * x, scale * y)
+    }
+
+    /// Fraction of the light of a camera space ray reaching the sensor
+    pub fn vignetting(&self, direction: &Vec3) -> f64 {
+        let cos_theta = (-direction.unit_vector().z).max(0.);
+        1. - self.vignetting * (1. - cos_theta.powi(4))
+    }
+
+    /// Whether chromatic aberration needs a separate ray per channel
+    pub fn splits_channels(&self) -> bool {
+        self.chromatic_aberration != 0.
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lens::*;
+
+    #[test]
+    fn test_aperture_samples_stay_inside() {
+        for blades in [0, 5, 6] {
+            let aperture = Aperture {
+                radius: 2.,
+                focus_distance: 1.,
+                blades,
+                rotation: 18.,
+            };
+            for i in 0..20 {
+                for j in 0..20 {
+                    let (x, y) = aperture.sample((i as f64 / 20., j as f64 / 20.));
+                    assert!((x * x + y * y).sqrt() <= 2. + 1e-9);
+                }
+            }
+        }
+
+        // The sides of a square aperture are at radius cos(45°)
+        let square = Aperture {
+            radius: 1.,
+            focus_distance: 1.,
+            blades: 4,
+            rotation: 45.,
+        };
+        for i in 0..=10 {
+            let (x, y) = square.sample((0.999, i as f64 / 10.));
+            assert!(x.abs().max(y.abs()) <= 0.5f64.sqrt() + 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_defocus_keeps_focus_plane() {
+        let aperture = Aperture {
+            radius: 0.1,
+            focus_distance: 3.,
+            blades: 0,
+            rotation: 0.,
+        };
+        let direction = Vec3 {
+            x: 0.2,
+            y: 0.1,
+            z: -1.,
+        };
+        let (origin, defocused) = aperture.defocus(Point3::default(), direction, (0.05, -0.02));
+        let focus = origin + (3. / -defocused.z) * defocused;
+        assert!((focus - 3. * direction).len() < 1e-9);
+    }
+
+    #[test]
+    fn test_distortion_and_vignetting() {
+        let lens = LensEffects {
+            distortion: 0.1,
+            vignetting: 1.,
+            chromatic_aberration: 0.01,
+            aperture: None,
+        };
+        assert_eq!(lens.distort(0., 0., None), (0., 0.));
+        let (x, _) = lens.distort(1., 0., None);
+        assert!((x - 1.1).abs() < 1e-12);
+        let (red, _) = lens.distort(1., 0., Some(0));
+        let (blue, _) = lens.distort(1., 0., Some(2));
+        assert!(red < x && x < blue);
+
+        let forward = Vec3 {
+            x: 0.,
+            y: 0.,
+            z: -1.,
+        };
+        assert_eq!(lens.vignetting(&forward), 1.);
+        // 45° off axis, cos⁴ = 1/4
+        let diagonal = Vec3 {
+            x: 1.,
+            y: 0.,
+            z: -1.,
+        };
+        assert!((lens.vignetting(&diagonal) - 0.25).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,399 @@
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+
+use crate::vec3::*;
+
+// Lens prescriptions are in millimeters, scenes in meters
+const MILLIMETERS_PER_UNIT: f64 = 1000.;
+
+/// Double Gauss 50mm f/2 (US patent 2,673,491, scaled from 100mm), a
+/// classic normal lens for 35mm film
+pub const DOUBLE_GAUSS_50MM: &str = "\
+# radius  thickness  ior    aperture
+29.475    3.76       1.67   25.2
+84.83     0.12       1      25.2
+19.275    4.025      1.67   23
+40.77     3.275      1.699  23
+12.75     5.705      1      18
+0         4.5        0      17.1
+-14.495   1.18       1.603  17
+40.77     6.065      1.658  20
+-20.385   0.19       1      20
+437.065   3.22       1.717  20
+-39.73    0          1      20
+";
+
+/// One surface of a lens, listed from the front (scene side) to the film.
+/// Lengths are in millimeters
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LensElement {
+    /// Radius of the spherical surface, positive when it bulges toward the
+    /// scene. 0 is the aperture stop
+    pub curvature_radius: f64,
+    /// Distance along the axis to the next surface, or to the film for the
+    /// last one
+    pub thickness: f64,
+    /// Index of refraction of the glass after the surface, 1 for air
+    pub ior: f64,
+    pub aperture_radius: f64,
+}
+
+impl LensElement {
+    fn is_stop(&self) -> bool {
+        self.curvature_radius == 0.
+    }
+}
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Reads a lens prescription table: one surface per line with its radius,
+/// thickness, index of refraction and aperture diameter, in millimeters.
+/// Lines starting with `#` are comments and an index of 0 means air, as in
+/// the tables of pbrt
+pub fn parse_prescription(table: &str) -> Result<Vec<LensElement>> {
+    let mut elements = Vec::new();
+    for line in table.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let values = line
+            .split_whitespace()
+            .map(str::parse)
+            .collect::<std::result::Result<Vec<f64>, _>>()
+            .map_err(|_| invalid_data(&format!("Invalid lens surface: {line}")))?;
+        let [curvature_radius, thickness, ior, aperture] = values[..] else {
+            return Err(invalid_data(&format!(
+                "A lens surface needs 4 values: {line}"
+            )));
+        };
+        elements.push(LensElement {
+            curvature_radius,
+            thickness,
+            ior: if ior == 0. { 1. } else { ior },
+            aperture_radius: aperture / 2.,
+        });
+    }
+    if elements.is_empty() {
+        return Err(invalid_data("The lens has no surfaces"));
+    }
+    Ok(elements)
+}
+
+pub fn load_prescription(filename: &str) -> Result<Vec<LensElement>> {
+    parse_prescription(&fs::read_to_string(filename)?)
+}
+
+// Ray in lens space, where the film is at z = 0 and the lens and the scene
+// lie toward -z
+#[derive(Debug, Clone, Copy)]
+struct LensRay {
+    origin: Point3,
+    direction: Vec3,
+}
+
+impl LensRay {
+    fn at(&self, t: f64) -> Point3 {
+        self.origin + t * self.direction
+    }
+}
+
+/// Camera lens made of spherical elements, traced like a real lens. Focusing
+/// moves the lens away from the film, which changes the field of view like
+/// real lenses do
+#[derive(Debug, Clone, PartialEq)]
+pub struct LensSystem {
+    elements: Vec<LensElement>,
+    film_diagonal: f64,
+}
+
+impl LensSystem {
+    /// Lens in front of a film with the given diagonal in millimeters (43.3
+    /// for 35mm film), focused on the plane `focus_distance` in front of the
+    /// film in scene units
+    pub fn new(elements: Vec<LensElement>, film_diagonal: f64, focus_distance: f64) -> Self {
+        let mut lens = LensSystem {
+            elements,
+            film_diagonal,
+        };
+        lens.focus(focus_distance);
+        lens
+    }
+
+    /// Moves the lens so the plane at `focus_distance` from the film is in
+    /// focus, using the thick lens approximation of the elements
+    pub fn focus(&mut self, focus_distance: f64) {
+        let Some((front_principal, rear_principal, focal_length)) = self.cardinal_points() else {
+            return;
+        };
+        // Solves 1 / s + 1 / s' = 1 / f for the distance δ the lens moves
+        // toward the scene, s and s' being the distances of the focus plane
+        // and the film to the principal planes
+        let a = front_principal + focus_distance * MILLIMETERS_PER_UNIT;
+        let b = -rear_principal;
+        let c = (a + b) * (a + b - 4. * focal_length);
+        if c < 0. {
+            return;
+        }
+        let delta = ((a - b) - c.sqrt()) / 2.;
+        if let Some(last) = self.elements.last_mut() {
+            last.thickness += delta;
+        }
+    }
+
+    /// Origin, in scene units, and direction in camera space of the ray
+    /// through the point (x, y) of the viewport, as in `Projection::ray`.
+    /// `sample` picks the point of the rear element the ray goes through,
+    /// its center without. None when the lens blocks the ray
+    pub fn ray(
+        &self,
+        x: f64,
+        y: f64,
+        half_width: f64,
+        sample: Option<(f64, f64)>,
+    ) -> Option<(Point3, Vec3)> {
+        // The lens flips the image on the film
+        let half_height = self.film_diagonal / 2. / (half_width * half_width + 1.).sqrt();
+        let film_point = Point3 {
+            x: -x * half_height,
+            y: -y * half_height,
+            z: 0.,
+        };
+
+        let rear = self.elements.last()?;
+        let (rear_x, rear_y) = match sample {
+            Some((u, v)) => {
+                let radius = rear.aperture_radius * u.sqrt();
+                let angle = 2. * std::f64::consts::PI * v;
+                (radius * angle.cos(), radius * angle.sin())
+            }
+            None => (0., 0.),
+        };
+        let rear_point = Point3 {
+            x: rear_x,
+            y: rear_y,
+            z: -rear.thickness,
+        };
+
+        let ray = self.trace_from_film(LensRay {
+            origin: film_point,
+            direction: rear_point - film_point,
+        })?;
+        Some((ray.origin / MILLIMETERS_PER_UNIT, ray.direction))
+    }
+
+    // Position along the axis of the first surface
+    fn front_z(&self) -> f64 {
+        -self
+            .elements
+            .iter()
+            .map(|element| element.thickness)
+            .sum::<f64>()
+    }
+
+    fn trace_from_film(&self, mut ray: LensRay) -> Option<LensRay> {
+        let mut z = 0.;
+        for (i, element) in self.elements.iter().enumerate().rev() {
+            z -= element.thickness;
+            let outside_ior = if i > 0 { self.elements[i - 1].ior } else { 1. };
+            ray = Self::cross_surface(ray, element, z, element.ior, outside_ior)?;
+        }
+        Some(ray)
+    }
+
+    fn trace_from_scene(&self, mut ray: LensRay) -> Option<LensRay> {
+        let mut z = self.front_z();
+        for (i, element) in self.elements.iter().enumerate() {
+            let outside_ior = if i > 0 { self.elements[i - 1].ior } else { 1. };
+            ray = Self::cross_surface(ray, element, z, outside_ior, element.ior)?;
+            z += element.thickness;
+        }
+        Some(ray)
+    }
+
+    // Intersects the surface whose vertex is at `z` and refracts the ray from
+    // the medium of index `ior_in` into the one of index `ior_out`
+    fn cross_surface(
+        ray: LensRay,
+        element: &LensElement,
+        z: f64,
+        ior_in: f64,
+        ior_out: f64,
+    ) -> Option<LensRay> {
+        let (t, normal) = if element.is_stop() {
+            let t = (z - ray.origin.z) / ray.direction.z;
+            (t, None)
+        } else {
+            let radius = element.curvature_radius;
+            let center = Point3 {
+                x: 0.,
+                y: 0.,
+                z: z + radius,
+            };
+            let oc = ray.origin - center;
+            let a = ray.direction.len_squared();
+            let half_b = oc.dot(&ray.direction);
+            let c = oc.len_squared() - radius * radius;
+            let discriminant = half_b * half_b - a * c;
+            if discriminant < 0. {
+                return None;
+            }
+            // The surface is the half of the sphere around the vertex
+            let closer = (ray.direction.z > 0.) != (radius < 0.);
+            let t = if closer {
+                (-half_b - discriminant.sqrt()) / a
+            } else {
+                (-half_b + discriminant.sqrt()) / a
+            };
+            (t, Some((ray.at(t) - center) / radius.abs()))
+        };
+        if t <= 0. {
+            return None;
+        }
+
+        let point = ray.at(t);
+        if point.x * point.x + point.y * point.y > element.aperture_radius * element.aperture_radius
+        {
+            return None;
+        }
+        let Some(normal) = normal else {
+            return Some(LensRay {
+                origin: point,
+                direction: ray.direction,
+            });
+        };
+
+        let direction = ray.direction.unit_vector();
+        let normal = if normal.dot(&direction) > 0. {
+            -normal
+        } else {
+            normal
+        };
+        let eta = ior_in / ior_out;
+        let cos_in = -normal.dot(&direction);
+        let sin2_out = eta * eta * (1. - cos_in * cos_in);
+        if sin2_out > 1. {
+            return None;
+        }
+        Some(LensRay {
+            origin: point,
+            direction: eta * direction + (eta * cos_in - (1. - sin2_out).sqrt()) * normal,
+        })
+    }
+
+    // Positions of the front and rear principal planes and the focal length,
+    // from rays parallel to the axis entering each side of the lens
+    fn cardinal_points(&self) -> Option<(f64, f64, f64)> {
+        let height = 0.001 * self.elements.first()?.aperture_radius;
+
+        let from_scene = LensRay {
+            origin: Point3 {
+                x: height,
+                y: 0.,
+                z: self.front_z() - 1.,
+            },
+            direction: Vec3 {
+                x: 0.,
+                y: 0.,
+                z: 1.,
+            },
+        };
+        let (rear_principal, rear_focal) =
+            Self::principal_and_focal(&from_scene, &self.trace_from_scene(from_scene)?);
+
+        let from_film = LensRay {
+            origin: Point3 {
+                x: height,
+                y: 0.,
+                z: 1.,
+            },
+            direction: Vec3 {
+                x: 0.,
+                y: 0.,
+                z: -1.,
+            },
+        };
+        let (front_principal, _) =
+            Self::principal_and_focal(&from_film, &self.trace_from_film(from_film)?);
+
+        Some((front_principal, rear_principal, rear_focal - rear_principal))
+    }
+
+    // Where the extension of the exiting ray crosses the height of the
+    // parallel entering ray, and where it crosses the axis
+    fn principal_and_focal(entering: &LensRay, exiting: &LensRay) -> (f64, f64) {
+        let focal = exiting.at(-exiting.origin.x / exiting.direction.x).z;
+        let principal = exiting
+            .at((entering.origin.x - exiting.origin.x) / exiting.direction.x)
+            .z;
+        (principal, focal)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lens_system::*;
+
+    fn double_gauss(focus_distance: f64) -> LensSystem {
+        LensSystem::new(
+            parse_prescription(DOUBLE_GAUSS_50MM).unwrap(),
+            43.3,
+            focus_distance,
+        )
+    }
+
+    #[test]
+    fn test_parse_prescription() {
+        let elements = parse_prescription(DOUBLE_GAUSS_50MM).unwrap();
+        assert_eq!(elements.len(), 11);
+        assert!(elements[5].is_stop());
+        assert_eq!(elements[5].ior, 1.);
+        assert_eq!(elements[5].aperture_radius, 8.55);
+
+        assert!(parse_prescription("# only a comment").is_err());
+        assert!(parse_prescription("1 2 3").is_err());
+        assert!(parse_prescription("1 2 glass 4").is_err());
+    }
+
+    #[test]
+    fn test_focused_rays_converge() {
+        for focus_distance in [0.5, 2.] {
+            let lens = double_gauss(focus_distance);
+            // Near axis rays from the film center meet at the focus distance
+            let crossing = |sample| {
+                let (origin, direction) = lens.ray(0., 0., 1.5, Some(sample)).unwrap();
+                let t = -origin.x / direction.x;
+                -(origin + t * direction).z
+            };
+            for sample in [(0.001, 0.), (0.002, 0.5)] {
+                let distance = crossing(sample);
+                assert!((distance - focus_distance).abs() < 0.01 * focus_distance);
+            }
+        }
+    }
+
+    #[test]
+    fn test_focus_breathing() {
+        // Focusing closer moves the lens away from the film and narrows the
+        // field of view
+        let field_angle = |lens: &LensSystem| {
+            let (_, direction) = lens.ray(0., 0.5, 1.5, None).unwrap();
+            (direction.y / -direction.z).atan()
+        };
+        let far = double_gauss(100.);
+        let near = double_gauss(0.5);
+        assert!(near.elements.last().unwrap().thickness > far.elements.last().unwrap().thickness);
+        assert!(field_angle(&near) < field_angle(&far));
+        // The image is flipped on the film, so the top of the image looks up
+        assert!(field_angle(&far) > 0.);
+    }
+
+    #[test]
+    fn test_rays_outside_the_lens_are_blocked() {
+        let lens = double_gauss(1.);
+        assert!(lens.ray(0., 0., 1.5, None).is_some());
+        // Far outside the image circle of the lens
+        assert!(lens.ray(5., 5., 1.5, None).is_none());
+    }
+}
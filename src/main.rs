@@ -1,97 +1,67 @@
+pub mod animation;
+pub mod aov;
 pub mod camera;
+pub mod checkpoint;
+pub mod cli;
+pub mod denoise;
+pub mod environment;
+pub mod film;
+pub mod filter;
 pub mod geometry;
+pub mod hdr;
 pub mod interval;
+pub mod lens;
+pub mod lens_system;
 pub mod material;
+pub mod medium;
+pub mod microfacet;
+pub mod onb;
+pub mod projection;
 pub mod ray;
+pub mod sampler;
+pub mod sampling;
+pub mod scene;
+pub mod sky;
+pub mod spectrum;
+pub mod stereo;
+pub mod texture;
+pub mod thinfilm;
 pub mod vec3;
 
-use std::rc::Rc;
+use std::{env, process};
 
-use camera::Camera;
-use material::*;
+use cli::{Command, Options, SceneSource};
+use scene::Scene;
 use vec3::*;
 
-use geometry::*;
-
 fn main() {
-    let mut world = HittableList::new();
-
-    // Prepare materials
-    let material_ground = Lambertian {
-        albedo: Color {
-            x: 0.8,
-            y: 0.8,
-            z: 0.,
-        },
+    let options = match Options::parse(env::args().skip(1)) {
+        Ok(Command::Render(options)) => options,
+        Ok(Command::Help) => {
+            print!("{}", cli::usage());
+            return;
+        }
+        Err(message) => {
+            eprintln!("Error: {message}");
+            eprintln!("Run `raytrace --help` for the list of options");
+            process::exit(2);
+        }
     };
 
-    let material_center = Lambertian {
-        albedo: Color {
-            x: 0.1,
-            y: 0.2,
-            z: 0.5,
-        },
+    let scene = match &options.scene {
+        SceneSource::File(filename) => Scene::load(filename).unwrap_or_else(|error| {
+            eprintln!("Error loading the scene {filename}: {error}");
+            process::exit(1);
+        }),
+        SceneSource::Builtin(name) => {
+            Scene::builtin(name).expect("The built-in scene names are checked when parsing")
+        }
     };
 
-    let material_left = Metal::new(
-        Color {
-            x: 0.8,
-            y: 0.8,
-            z: 0.8,
-        },
-        0.3,
-    );
-
-    let material_right = Metal::new(
-        Color {
-            x: 0.8,
-            y: 0.6,
-            z: 0.2,
-        },
-        1.0,
-    );
-
-    // Lets start with copies
-    world.add(Sphere {
-        center: Point3 {
-            x: 0.,
-            y: 0.,
-            z: -1.,
-        },
-        radius: 0.5,
-        material: Rc::new(material_center),
-    });
-    world.add(Sphere {
-        center: Point3 {
-            x: 0.,
-            y: -100.5,
-            z: -1.,
-        },
-        radius: 100.,
-        material: Rc::new(material_ground),
-    });
-    world.add(Sphere {
-        center: Point3 {
-            x: -1.,
-            y: 0.,
-            z: -1.,
-        },
-        radius: 0.5,
-        material: Rc::new(material_left),
-    });
-    world.add(Sphere {
-        center: Point3 {
-            x: 1.,
-            y: 0.,
-            z: -1.,
-        },
-        radius: 0.5,
-        material: Rc::new(material_right),
-    });
-
-    let aspect_ratio = 16. / 9.;
-    let image_width = 400;
-    let mut camera = Camera::new(aspect_ratio, image_width);
-
-    camera.render_to_file("image.png", &world);
+    let mut camera = options.camera();
+    scene.set_up_camera(&mut camera);
+    match options.frames {
+        Some(frames) => camera.render_frames(&options.output, &scene.world, frames),
+        None => camera.render_to_file(&options.output, &scene.world),
+    }
 }
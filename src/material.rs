@@ -1,14 +1,50 @@
+use std::f64::consts::PI;
+use std::sync::Arc;
+
 use crate::vec3::Color;
 
 use crate::geometry::HitRecord;
 use crate::interval::Interval;
+use crate::medium::Medium;
+use crate::microfacet::*;
+use crate::onb::Onb;
 use crate::ray::Ray;
+use crate::sampler::*;
+use crate::spectrum::{Dispersion, Wavelengths};
+use crate::texture::{ChannelTexture, SolidColor, Texture};
+use crate::thinfilm::{thin_film_reflectance, thin_film_reflectance_rgb, Complex};
 use crate::Vec3;
 
-pub trait Material {
-    fn scatter(&self, _ray: &Ray, _record: &HitRecord) -> Option<(Color, Ray)> {
+pub trait Material: Send + Sync {
+    fn scatter(
+        &self,
+        _ray: &Ray,
+        _record: &HitRecord,
+        _sampler: &mut dyn Sampler,
+    ) -> Option<(Color, Ray)> {
+        None
+    }
+
+    /// Evaluates the scattering of light arriving from `direction` towards the
+    /// incoming ray. Returns the BSDF multiplied by the cosine term and the
+    /// density with which `scatter` would pick `direction`. Materials that can
+    /// not be evaluated (mirror-like ones) return None and are skipped by
+    /// direct light sampling
+    fn eval(&self, _ray: &Ray, _record: &HitRecord, _direction: &Vec3) -> Option<(Color, f64)> {
         None
     }
+
+    /// Overall color of the surface, as seen by the albedo output and the
+    /// denoiser
+    fn albedo(&self, _record: &HitRecord) -> Color {
+        Color::default()
+    }
+
+    /// Whether the hit is kept. Rays continue through invisible parts of
+    /// surfaces as if they were not there
+    fn is_visible(&self, _ray: &Ray, _record: &HitRecord) -> bool {
+        true
+    }
 }
 
 pub struct Lambertian {
@@ -29,37 +65,1661 @@ impl Metal {
     }
 }
 
+/// Metal described by its complex index of refraction `eta + i k` with
+/// GGX microfacet roughness
+pub struct Conductor {
+    pub eta: Color,
+    pub k: Color,
+    distribution: TrowbridgeReitz,
+}
+
+impl Conductor {
+    pub fn new(eta: Color, k: Color, roughness: f64) -> Conductor {
+        Conductor {
+            eta,
+            k,
+            distribution: TrowbridgeReitz::from_roughness(roughness),
+        }
+    }
+
+    // Indices of refraction measured at 650, 550 and 450 nm
+
+    pub fn gold(roughness: f64) -> Conductor {
+        Self::new(
+            Color {
+                x: 0.143,
+                y: 0.374,
+                z: 1.442,
+            },
+            Color {
+                x: 3.983,
+                y: 2.385,
+                z: 1.603,
+            },
+            roughness,
+        )
+    }
+
+    pub fn copper(roughness: f64) -> Conductor {
+        Self::new(
+            Color {
+                x: 0.200,
+                y: 0.924,
+                z: 1.102,
+            },
+            Color {
+                x: 3.912,
+                y: 2.452,
+                z: 2.142,
+            },
+            roughness,
+        )
+    }
+
+    pub fn aluminium(roughness: f64) -> Conductor {
+        Self::new(
+            Color {
+                x: 1.657,
+                y: 0.880,
+                z: 0.521,
+            },
+            Color {
+                x: 9.224,
+                y: 6.270,
+                z: 4.837,
+            },
+            roughness,
+        )
+    }
+
+    pub fn silver(roughness: f64) -> Conductor {
+        Self::new(
+            Color {
+                x: 0.155,
+                y: 0.117,
+                z: 0.138,
+            },
+            Color {
+                x: 4.828,
+                y: 3.122,
+                z: 2.147,
+            },
+            roughness,
+        )
+    }
+
+    // Fresnel reflectance carried by the ray, evaluated from the index of
+    // refraction at each wavelength when rendering spectrally
+    fn fresnel(&self, ray: &Ray, cos_theta_i: f64) -> Color {
+        match &ray.wavelengths {
+            Some(wavelengths) => fresnel_conductor(
+                cos_theta_i,
+                &wavelengths.interpolate_measurements(&self.eta),
+                &wavelengths.interpolate_measurements(&self.k),
+            ),
+            None => fresnel_conductor(cos_theta_i, &self.eta, &self.k),
+        }
+    }
+}
+
+/// Glass-like material. A non-zero roughness gives frosted glass
+pub struct Dielectric {
+    pub ior: f64,
+    // Splits light into its wavelengths when rendering spectrally, `ior` is
+    // used otherwise
+    pub dispersion: Option<Dispersion>,
+    distribution: TrowbridgeReitz,
+}
+
+// Wavelength of the sodium D line (nm), where glasses are usually characterized
+const SODIUM_D_LINE: f64 = 589.3;
+
+impl Dielectric {
+    pub fn new(ior: f64, roughness: f64) -> Dielectric {
+        Dielectric {
+            ior,
+            dispersion: None,
+            distribution: TrowbridgeReitz::from_roughness(roughness),
+        }
+    }
+
+    pub fn dispersive(dispersion: Dispersion, roughness: f64) -> Dielectric {
+        Dielectric {
+            ior: dispersion.ior(SODIUM_D_LINE),
+            dispersion: Some(dispersion),
+            distribution: TrowbridgeReitz::from_roughness(roughness),
+        }
+    }
+
+    // Wavelengths the ray is split into, if any
+    fn dispersed_wavelengths(&self, ray: &Ray) -> Option<Wavelengths> {
+        match (&self.dispersion, ray.wavelengths) {
+            (Some(_), Some(wavelengths)) => Some(wavelengths),
+            _ => None,
+        }
+    }
+
+    // Index of refraction on the other side of the surface relative to the
+    // side the ray comes from. Dispersion follows the hero wavelength
+    fn relative_ior(&self, ray: &Ray, record: &HitRecord) -> f64 {
+        let ior = match (&self.dispersion, &ray.wavelengths) {
+            (Some(dispersion), Some(wavelengths)) => dispersion.ior(wavelengths.lambda[0]),
+            _ => self.ior,
+        };
+        if record.front_face {
+            ior
+        } else {
+            1. / ior
+        }
+    }
+}
+
+// Lobes of the principled material are kept slightly rough so every one of
+// them can be evaluated for light sampling
+const PRINCIPLED_MIN_ROUGHNESS: f64 = 0.05;
+
+/// Principled (Disney-style) uber material combining diffuse with sheen,
+/// metallic and dielectric specular, clearcoat and transmission. Every
+/// parameter is a texture, scalar ones read its first channel
+pub struct Principled {
+    pub base_color: Arc<dyn Texture>,
+    pub metallic: Arc<dyn Texture>,
+    pub roughness: Arc<dyn Texture>,
+    // Reflectance of the dielectric specular, 0.5 corresponds to 4%
+    pub specular: Arc<dyn Texture>,
+    pub sheen: Arc<dyn Texture>,
+    // Blend of the sheen color from white to the base color
+    pub sheen_tint: Arc<dyn Texture>,
+    pub clearcoat: Arc<dyn Texture>,
+    pub clearcoat_roughness: Arc<dyn Texture>,
+    pub transmission: Arc<dyn Texture>,
+    // Index of refraction used by the transmission
+    pub ior: f64,
+}
+
+impl Default for Principled {
+    fn default() -> Self {
+        Principled {
+            base_color: Arc::new(SolidColor::gray(0.8)),
+            metallic: Arc::new(SolidColor::gray(0.)),
+            roughness: Arc::new(SolidColor::gray(0.5)),
+            specular: Arc::new(SolidColor::gray(0.5)),
+            sheen: Arc::new(SolidColor::gray(0.)),
+            sheen_tint: Arc::new(SolidColor::gray(0.5)),
+            clearcoat: Arc::new(SolidColor::gray(0.)),
+            clearcoat_roughness: Arc::new(SolidColor::gray(0.1)),
+            transmission: Arc::new(SolidColor::gray(0.)),
+            ior: 1.5,
+        }
+    }
+}
+
+impl Principled {
+    /// Material following the glTF metallic-roughness model, where the green
+    /// channel of `metallic_roughness` holds the roughness and the blue one
+    /// the metalness
+    pub fn metallic_roughness(
+        base_color: Arc<dyn Texture>,
+        metallic_roughness: Arc<dyn Texture>,
+    ) -> Principled {
+        Principled {
+            base_color,
+            metallic: Arc::new(ChannelTexture {
+                texture: metallic_roughness.clone(),
+                channel: 2,
+            }),
+            roughness: Arc::new(ChannelTexture {
+                texture: metallic_roughness,
+                channel: 1,
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn lobes(&self, record: &HitRecord) -> PrincipledLobes {
+        let value = |texture: &Arc<dyn Texture>| texture.value(record.u, record.v, &record.point);
+        let scalar = |texture: &Arc<dyn Texture>| value(texture).x.clamp(0., 1.);
+
+        let base_color = value(&self.base_color);
+        let metallic = scalar(&self.metallic);
+        let roughness = scalar(&self.roughness).max(PRINCIPLED_MIN_ROUGHNESS);
+        let transmission = scalar(&self.transmission);
+        let white = Color {
+            x: 1.,
+            y: 1.,
+            z: 1.,
+        };
+
+        let sheen_tint = scalar(&self.sheen_tint);
+        let dielectric_f0 = 0.08 * scalar(&self.specular);
+
+        PrincipledLobes {
+            base_color,
+            sheen_color: scalar(&self.sheen)
+                * ((1. - sheen_tint) * white + sheen_tint * base_color),
+            specular_f0: (1. - metallic) * dielectric_f0 * white + metallic * base_color,
+            roughness,
+            distribution: TrowbridgeReitz::from_roughness(roughness),
+            clearcoat_distribution: TrowbridgeReitz::from_roughness(
+                scalar(&self.clearcoat_roughness).max(PRINCIPLED_MIN_ROUGHNESS),
+            ),
+            diffuse_weight: (1. - metallic) * (1. - transmission),
+            glass_weight: (1. - metallic) * transmission,
+            clearcoat: scalar(&self.clearcoat),
+            eta: if record.front_face {
+                self.ior
+            } else {
+                1. / self.ior
+            },
+        }
+    }
+}
+
+// Parameters of the principled material evaluated at a surface point. All
+// directions are in the local shading frame
+struct PrincipledLobes {
+    base_color: Color,
+    sheen_color: Color,
+    specular_f0: Color,
+    roughness: f64,
+    distribution: TrowbridgeReitz,
+    clearcoat_distribution: TrowbridgeReitz,
+    diffuse_weight: f64,
+    glass_weight: f64,
+    clearcoat: f64,
+    eta: f64,
+}
+
+// Reflectance of the clearcoat layer at normal incidence (IOR of 1.5)
+const CLEARCOAT_F0: f64 = 0.04;
+
+impl PrincipledLobes {
+    // Probabilities of sampling the diffuse, specular, clearcoat and glass
+    // lobes, roughly proportional to their contribution
+    fn probabilities(&self, wo: &Vec3) -> [f64; 4] {
+        let weights = [
+            self.diffuse_weight * (self.base_color.luminance() + self.sheen_color.luminance()),
+            (1. - self.glass_weight) * fresnel_schlick(&self.specular_f0, wo.z).luminance(),
+            self.clearcoat * Self::clearcoat_fresnel(wo.z),
+            self.glass_weight,
+        ];
+        let total: f64 = weights.iter().sum();
+        if total <= 0. {
+            return [0.; 4];
+        }
+        weights.map(|weight| weight / total)
+    }
+
+    fn clearcoat_fresnel(cos_theta: f64) -> f64 {
+        let f0 = Color {
+            x: CLEARCOAT_F0,
+            y: CLEARCOAT_F0,
+            z: CLEARCOAT_F0,
+        };
+        fresnel_schlick(&f0, cos_theta).x
+    }
+
+    fn sample(&self, wo: &Vec3, sampler: &mut dyn Sampler) -> Option<Vec3> {
+        let probabilities = self.probabilities(wo);
+        let mut choice = sampler.get_1d();
+        let lobe = probabilities
+            .iter()
+            .position(|&probability| {
+                choice -= probability;
+                choice < 0.
+            })
+            .unwrap_or(3);
+
+        let wi = match lobe {
+            0 => {
+                let up = Vec3 {
+                    x: 0.,
+                    y: 0.,
+                    z: 1.,
+                };
+                let direction = up + Vec3::random_unit_vector(sampler);
+                if direction.near_zero() {
+                    up
+                } else {
+                    direction.unit_vector()
+                }
+            }
+            1 | 2 => {
+                let distribution = if lobe == 1 {
+                    &self.distribution
+                } else {
+                    &self.clearcoat_distribution
+                };
+                let wm = distribution.sample_visible_normal(wo, sampler.get_2d());
+                reflect(wo, &wm)
+            }
+            _ => sample_dielectric(&self.distribution, wo, self.eta, sampler)?,
+        };
+
+        if wi.z == 0. {
+            None
+        } else {
+            Some(wi)
+        }
+    }
+
+    // BSDF multiplied by the cosine term and the combined density of `sample`
+    fn eval(&self, wo: &Vec3, wi: &Vec3) -> (Color, f64) {
+        let probabilities = self.probabilities(wo);
+        let mut value = Color::default();
+        let mut pdf = 0.;
+
+        if let Some((glass, glass_pdf)) = eval_dielectric(&self.distribution, wo, wi, self.eta) {
+            // Light passing through is tinted by the base color
+            let tint = if wi.z < 0. {
+                self.base_color
+            } else {
+                Color {
+                    x: 1.,
+                    y: 1.,
+                    z: 1.,
+                }
+            };
+            value += (self.glass_weight * glass) * tint;
+            pdf += probabilities[3] * glass_pdf;
+        }
+
+        if wi.z <= 0. || wo.z <= 0. {
+            return (value, pdf);
+        }
+
+        let wm = (*wo + *wi).unit_vector();
+        let cos_d = wi.dot(&wm);
+
+        // Diffuse with retro-reflection at grazing angles and sheen
+        let fd90 = 0.5 + 2. * self.roughness * cos_d * cos_d;
+        let retro =
+            (1. + (fd90 - 1.) * (1. - wi.z).powi(5)) * (1. + (fd90 - 1.) * (1. - wo.z).powi(5));
+        let diffuse = (retro / PI) * self.base_color + (1. - cos_d).powi(5) * self.sheen_color;
+        let base_layer = self.diffuse_weight * wi.z * diffuse;
+
+        // Specular reflection of metals and opaque dielectrics
+        let specular = fresnel_schlick(&self.specular_f0, cos_d)
+            * ((1. - self.glass_weight) * self.distribution.d(&wm) * self.distribution.g(wo, wi)
+                / (4. * wo.z));
+        let specular_pdf = self.distribution.d_visible(wo, &wm) / (4. * wo.dot(&wm));
+
+        // The clearcoat reflects part of the light before it reaches the base
+        let clearcoat = self.clearcoat
+            * Self::clearcoat_fresnel(cos_d)
+            * self.clearcoat_distribution.d(&wm)
+            * self.clearcoat_distribution.g(wo, wi)
+            / (4. * wo.z);
+        let clearcoat_pdf = self.clearcoat_distribution.d_visible(wo, &wm) / (4. * wo.dot(&wm));
+        let coat_transmittance = 1. - self.clearcoat * Self::clearcoat_fresnel(wo.z);
+
+        value = coat_transmittance * (value + base_layer + specular)
+            + Color {
+                x: clearcoat,
+                y: clearcoat,
+                z: clearcoat,
+            };
+        pdf += probabilities[0] * wi.z / PI
+            + probabilities[1] * specular_pdf
+            + probabilities[2] * clearcoat_pdf;
+        (value, pdf)
+    }
+}
+
+/// Adds surface detail to `material` from a tangent-space normal map. The map
+/// has to be loaded as linear data, `strength` scales the tilt of the normals
+pub struct NormalMap {
+    pub material: Arc<dyn Material>,
+    pub texture: Arc<dyn Texture>,
+    pub strength: f64,
+}
+
+/// Adds surface detail to `material` from a height map. `scale` converts the
+/// height differences to the slope of the surface
+pub struct BumpMap {
+    pub material: Arc<dyn Material>,
+    pub height: Arc<dyn Texture>,
+    pub scale: f64,
+}
+
+pub enum AlphaMode {
+    /// Surfaces with opacity below the threshold are cut out completely
+    Threshold(f64),
+    /// Surfaces are hit with a probability equal to their opacity, which
+    /// converges to partial transparency. The choice hashes the hit and the
+    /// camera sample of the ray so the intersection tests need no sampler
+    Stochastic,
+}
+
+/// Cuts holes into `material` using the first channel of the `opacity` texture
+/// (leaves, fences, decals)
+pub struct AlphaMask {
+    pub material: Arc<dyn Material>,
+    pub opacity: Arc<dyn Texture>,
+    pub mode: AlphaMode,
+}
+
+/// Shades only the front side of `material`, the back side absorbs all light
+pub struct OneSided {
+    pub material: Arc<dyn Material>,
+}
+
+/// Chooses between two materials at random, `weight` (first channel of the
+/// texture) is the share of `second`. Light sampling is only used when both
+/// materials can be evaluated
+pub struct MixMaterial {
+    pub first: Arc<dyn Material>,
+    pub second: Arc<dyn Material>,
+    pub weight: Arc<dyn Texture>,
+}
+
+/// Clear dielectric coating layered over `base` (car paint, varnished wood).
+/// `color` tints the light passing through the coating
+pub struct Coated {
+    pub base: Arc<dyn Material>,
+    pub ior: f64,
+    pub color: Color,
+    distribution: TrowbridgeReitz,
+}
+
+impl Coated {
+    pub fn new(base: Arc<dyn Material>, ior: f64, roughness: f64, color: Color) -> Coated {
+        Coated {
+            base,
+            ior,
+            color,
+            distribution: TrowbridgeReitz::from_roughness(roughness),
+        }
+    }
+}
+
+pub enum ThinFilmBase {
+    /// Transparent base with the given index of refraction, 1 for soap bubbles
+    Dielectric(f64),
+    /// Metal with the complex index of refraction `eta + i k`
+    Conductor { eta: Color, k: Color },
+}
+
+/// Thin transparent film over a dielectric or metal base, showing interference
+/// colors (soap bubbles, oil slicks, lens coatings). The first channel of
+/// `thickness` holds the film thickness in nanometers. Light sampling is only
+/// used with a rough metal base
+pub struct ThinFilm {
+    pub base: ThinFilmBase,
+    pub thickness: Arc<dyn Texture>,
+    pub film_ior: f64,
+    distribution: TrowbridgeReitz,
+}
+
+impl ThinFilm {
+    pub fn new(
+        base: ThinFilmBase,
+        thickness: Arc<dyn Texture>,
+        film_ior: f64,
+        roughness: f64,
+    ) -> ThinFilm {
+        ThinFilm {
+            base,
+            thickness,
+            film_ior,
+            distribution: TrowbridgeReitz::from_roughness(roughness),
+        }
+    }
+
+    // Returns the reflectance of the film and, for a transparent base, the
+    // index of refraction of the base relative to the side of the ray
+    fn reflectance(&self, ray: &Ray, record: &HitRecord, cos_theta: f64) -> (Color, Option<f64>) {
+        let thickness = self
+            .thickness
+            .value(record.u, record.v, &record.point)
+            .x
+            .max(0.);
+        // Spectral rendering evaluates the interference at the wavelengths of
+        // the ray instead of averaging over color bands
+        let film = |outside: f64, substrate: [Complex; 3]| match &ray.wavelengths {
+            Some(wavelengths) => {
+                let lane = |i: usize| {
+                    thin_film_reflectance(
+                        cos_theta,
+                        thickness,
+                        outside,
+                        self.film_ior,
+                        substrate[i],
+                        wavelengths.lambda[i],
+                    )
+                };
+                Color {
+                    x: lane(0),
+                    y: lane(1),
+                    z: lane(2),
+                }
+            }
+            None => {
+                thin_film_reflectance_rgb(cos_theta, thickness, outside, self.film_ior, substrate)
+            }
+        };
+
+        match self.base {
+            ThinFilmBase::Dielectric(ior) => {
+                // Seen from inside the film lies between the base and the air
+                let (outside, substrate) = if record.front_face {
+                    (1., ior)
+                } else {
+                    (ior, 1.)
+                };
+                let reflectance = film(outside, [Complex::real(substrate); 3]);
+                (reflectance, Some(substrate / outside))
+            }
+            ThinFilmBase::Conductor { eta, k } => {
+                let (eta, k) = match &ray.wavelengths {
+                    Some(wavelengths) => (
+                        wavelengths.interpolate_measurements(&eta),
+                        wavelengths.interpolate_measurements(&k),
+                    ),
+                    None => (eta, k),
+                };
+                let substrate = [
+                    Complex::new(eta.x, k.x),
+                    Complex::new(eta.y, k.y),
+                    Complex::new(eta.z, k.z),
+                ];
+                (film(1., substrate), None)
+            }
+        }
+    }
+}
+
+/// Translucent material (skin, wax, marble, milk) where light refracted into
+/// the object performs a random walk through a medium until it leaves through
+/// the boundary. The object has to be closed. Light sampling is not used
+pub struct Subsurface {
+    pub medium: Medium,
+    pub ior: f64,
+    distribution: TrowbridgeReitz,
+}
+
+impl Subsurface {
+    pub fn new(mean_free_path: Color, albedo: Color, ior: f64, roughness: f64) -> Subsurface {
+        Subsurface {
+            medium: Medium::new(mean_free_path, albedo),
+            ior,
+            distribution: TrowbridgeReitz::from_roughness(roughness),
+        }
+    }
+}
+
+// Step in texture coordinates used to compute the slope of a height map
+const BUMP_DELTA: f64 = 1. / 2048.;
+
+// Copy of `record` with the shading normal tilted towards `outward_normal`
+fn perturb_normal(ray: &Ray, record: &HitRecord, outward_normal: Vec3) -> HitRecord {
+    let mut perturbed = record.clone();
+    let normal = if record.front_face {
+        outward_normal
+    } else {
+        -outward_normal
+    };
+    // A normal facing away from the ray would hide the surface from it, keep
+    // the geometric one in that case
+    if !normal.near_zero() && ray.dir.dot(&normal) < 0. {
+        perturbed.normal = normal.unit_vector();
+    }
+    perturbed
+}
+
+impl NormalMap {
+    fn perturb(&self, ray: &Ray, record: &HitRecord) -> HitRecord {
+        let color = self.texture.value(record.u, record.v, &record.point);
+        let x = (2. * color.x - 1.) * self.strength;
+        let y = (2. * color.y - 1.) * self.strength;
+        let z = 2. * color.z - 1.;
+
+        let outward_normal =
+            x * record.tangent + y * record.bitangent + z * record.outward_normal();
+        perturb_normal(ray, record, outward_normal)
+    }
+}
+
+impl BumpMap {
+    fn perturb(&self, ray: &Ray, record: &HitRecord) -> HitRecord {
+        let height = |u: f64, v: f64| self.height.value(u, v, &record.point).x;
+        let center = height(record.u, record.v);
+        let slope_u = (height(record.u + BUMP_DELTA, record.v) - center) / BUMP_DELTA;
+        let slope_v = (height(record.u, record.v + BUMP_DELTA) - center) / BUMP_DELTA;
+
+        let outward_normal = record.outward_normal()
+            - self.scale * (slope_u * record.tangent + slope_v * record.bitangent);
+        perturb_normal(ray, record, outward_normal)
+    }
+}
+
 impl Material for Lambertian {
-    fn scatter(&self, _ray: &Ray, record: &HitRecord) -> Option<(Color, Ray)> {
-        let mut scatter_direction = record.normal + Vec3::random_unit_vector();
+    fn scatter(
+        &self,
+        ray: &Ray,
+        record: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Color, Ray)> {
+        let mut scatter_direction = record.normal + Vec3::random_unit_vector(sampler);
 
         // Catch degenerate scatter direction
         if scatter_direction.near_zero() {
             scatter_direction = record.normal;
         }
 
-        let scattered = Ray {
-            orig: record.point,
-            dir: scatter_direction,
-        };
-        let attenuation = self.albedo;
+        let scattered = ray.spawn(record.point, scatter_direction);
+        let attenuation = ray.sample_color(&self.albedo);
         Some((attenuation, scattered))
     }
+
+    fn eval(&self, ray: &Ray, record: &HitRecord, direction: &Vec3) -> Option<(Color, f64)> {
+        let cosine = record.normal.dot(&direction.unit_vector());
+        if cosine <= 0. {
+            return None;
+        }
+        // Scattering around the normal above is cosine distributed
+        let pdf = cosine / PI;
+        Some((pdf * ray.sample_color(&self.albedo), pdf))
+    }
+
+    fn albedo(&self, _record: &HitRecord) -> Color {
+        self.albedo
+    }
 }
 
 impl Material for Metal {
-    fn scatter(&self, ray: &Ray, record: &HitRecord) -> Option<(Color, Ray)> {
+    fn scatter(
+        &self,
+        ray: &Ray,
+        record: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Color, Ray)> {
         let reflected = Vec3::reflect(&ray.dir, &record.normal).unit_vector()
-            + self.fuzz * Vec3::random_unit_vector();
-        let scattered = Ray {
-            orig: record.point,
-            dir: reflected,
-        };
-        let attenuation = self.albedo;
+            + self.fuzz * Vec3::random_unit_vector(sampler);
+        let scattered = ray.spawn(record.point, reflected);
+        let attenuation = ray.sample_color(&self.albedo);
         if scattered.dir.dot(&record.normal) > 0. {
             Some((attenuation, scattered))
         } else {
             None
         }
     }
+
+    fn albedo(&self, _record: &HitRecord) -> Color {
+        self.albedo
+    }
+}
+
+impl Material for Conductor {
+    fn scatter(
+        &self,
+        ray: &Ray,
+        record: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Color, Ray)> {
+        let frame = Onb::new(&record.normal);
+        let wo = frame.to_local(&-ray.dir.unit_vector());
+        if wo.z <= 0. {
+            return None;
+        }
+
+        let (wi, attenuation) = if self.distribution.is_smooth() {
+            let wi = Vec3 {
+                x: -wo.x,
+                y: -wo.y,
+                z: wo.z,
+            };
+            (wi, self.fresnel(ray, wo.z))
+        } else {
+            let wm = self
+                .distribution
+                .sample_visible_normal(&wo, sampler.get_2d());
+            let wi = reflect(&wo, &wm);
+            if wi.z <= 0. {
+                return None;
+            }
+            // The microfacet density cancels out with the sampling density
+            let masking = self.distribution.g(&wo, &wi) / self.distribution.g1(&wo);
+            (wi, masking * self.fresnel(ray, wo.dot(&wm)))
+        };
+
+        let scattered = ray.spawn(record.point, frame.local(&wi));
+        Some((attenuation, scattered))
+    }
+
+    fn eval(&self, ray: &Ray, record: &HitRecord, direction: &Vec3) -> Option<(Color, f64)> {
+        if self.distribution.is_smooth() {
+            return None;
+        }
+
+        let frame = Onb::new(&record.normal);
+        let wo = frame.to_local(&-ray.dir.unit_vector());
+        let wi = frame.to_local(&direction.unit_vector());
+        if wo.z <= 0. || wi.z <= 0. {
+            return None;
+        }
+
+        let wm = (wo + wi).unit_vector();
+        let fresnel = self.fresnel(ray, wo.dot(&wm));
+        let bsdf_cos = self.distribution.d(&wm) * self.distribution.g(&wo, &wi) / (4. * wo.z);
+        let pdf = self.distribution.d_visible(&wo, &wm) / (4. * wo.dot(&wm));
+        Some((bsdf_cos * fresnel, pdf))
+    }
+
+    fn albedo(&self, _record: &HitRecord) -> Color {
+        fresnel_conductor(1., &self.eta, &self.k)
+    }
+}
+
+impl Material for Dielectric {
+    fn scatter(
+        &self,
+        ray: &Ray,
+        record: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Color, Ray)> {
+        let frame = Onb::new(&record.normal);
+        let wo = frame.to_local(&-ray.dir.unit_vector());
+        if wo.z <= 0. {
+            return None;
+        }
+
+        let wi = sample_dielectric(
+            &self.distribution,
+            &wo,
+            self.relative_ior(ray, record),
+            sampler,
+        )?;
+        // Choosing between reflection and refraction proportionally to the
+        // Fresnel term cancels it out of the weight
+        let attenuation = if self.distribution.is_smooth() {
+            1.
+        } else {
+            self.distribution.g(&wo, &wi) / self.distribution.g1(&wo)
+        };
+        let mut scattered = ray.spawn(record.point, frame.local(&wi));
+
+        if let Some(mut wavelengths) = self.dispersed_wavelengths(ray) {
+            // The direction is only valid for the hero wavelength, which from
+            // now on carries the contribution of the whole path
+            if !wavelengths.secondary_terminated {
+                wavelengths.secondary_terminated = true;
+                scattered.wavelengths = Some(wavelengths);
+                return Some((
+                    Color {
+                        x: 3. * attenuation,
+                        y: 0.,
+                        z: 0.,
+                    },
+                    scattered,
+                ));
+            }
+        }
+
+        Some((
+            Color {
+                x: attenuation,
+                y: attenuation,
+                z: attenuation,
+            },
+            scattered,
+        ))
+    }
+
+    fn eval(&self, ray: &Ray, record: &HitRecord, direction: &Vec3) -> Option<(Color, f64)> {
+        if self.distribution.is_smooth() || self.dispersed_wavelengths(ray).is_some() {
+            return None;
+        }
+
+        let frame = Onb::new(&record.normal);
+        let wo = frame.to_local(&-ray.dir.unit_vector());
+        let wi = frame.to_local(&direction.unit_vector());
+        let (value, pdf) =
+            eval_dielectric(&self.distribution, &wo, &wi, self.relative_ior(ray, record))?;
+        Some((
+            Color {
+                x: value,
+                y: value,
+                z: value,
+            },
+            pdf,
+        ))
+    }
+
+    fn albedo(&self, _record: &HitRecord) -> Color {
+        Color {
+            x: 1.,
+            y: 1.,
+            z: 1.,
+        }
+    }
+}
+
+impl Material for Principled {
+    fn scatter(
+        &self,
+        ray: &Ray,
+        record: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Color, Ray)> {
+        let frame = Onb::new(&record.normal);
+        let wo = frame.to_local(&-ray.dir.unit_vector());
+        if wo.z <= 0. {
+            return None;
+        }
+
+        let lobes = self.lobes(record);
+        let wi = lobes.sample(&wo, sampler)?;
+        let (value, pdf) = lobes.eval(&wo, &wi);
+        if pdf <= 0. {
+            return None;
+        }
+
+        let scattered = ray.spawn(record.point, frame.local(&wi));
+        Some((ray.sample_color(&(value / pdf)), scattered))
+    }
+
+    fn eval(&self, ray: &Ray, record: &HitRecord, direction: &Vec3) -> Option<(Color, f64)> {
+        let frame = Onb::new(&record.normal);
+        let wo = frame.to_local(&-ray.dir.unit_vector());
+        let wi = frame.to_local(&direction.unit_vector());
+        if wo.z <= 0. {
+            return None;
+        }
+
+        let (value, pdf) = self.lobes(record).eval(&wo, &wi);
+        if pdf > 0. {
+            Some((ray.sample_color(&value), pdf))
+        } else {
+            None
+        }
+    }
+
+    fn albedo(&self, record: &HitRecord) -> Color {
+        self.base_color.value(record.u, record.v, &record.point)
+    }
+}
+
+impl Material for NormalMap {
+    fn scatter(
+        &self,
+        ray: &Ray,
+        record: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Color, Ray)> {
+        self.material
+            .scatter(ray, &self.perturb(ray, record), sampler)
+    }
+
+    fn eval(&self, ray: &Ray, record: &HitRecord, direction: &Vec3) -> Option<(Color, f64)> {
+        self.material
+            .eval(ray, &self.perturb(ray, record), direction)
+    }
+
+    fn is_visible(&self, ray: &Ray, record: &HitRecord) -> bool {
+        self.material.is_visible(ray, record)
+    }
+
+    fn albedo(&self, record: &HitRecord) -> Color {
+        self.material.albedo(record)
+    }
+}
+
+impl Material for BumpMap {
+    fn scatter(
+        &self,
+        ray: &Ray,
+        record: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Color, Ray)> {
+        self.material
+            .scatter(ray, &self.perturb(ray, record), sampler)
+    }
+
+    fn eval(&self, ray: &Ray, record: &HitRecord, direction: &Vec3) -> Option<(Color, f64)> {
+        self.material
+            .eval(ray, &self.perturb(ray, record), direction)
+    }
+
+    fn is_visible(&self, ray: &Ray, record: &HitRecord) -> bool {
+        self.material.is_visible(ray, record)
+    }
+
+    fn albedo(&self, record: &HitRecord) -> Color {
+        self.material.albedo(record)
+    }
+}
+
+impl Material for AlphaMask {
+    fn scatter(
+        &self,
+        ray: &Ray,
+        record: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Color, Ray)> {
+        self.material.scatter(ray, record, sampler)
+    }
+
+    fn eval(&self, ray: &Ray, record: &HitRecord, direction: &Vec3) -> Option<(Color, f64)> {
+        self.material.eval(ray, record, direction)
+    }
+
+    fn is_visible(&self, ray: &Ray, record: &HitRecord) -> bool {
+        let opacity = self.opacity.value(record.u, record.v, &record.point).x;
+        let visible = match self.mode {
+            AlphaMode::Threshold(threshold) => opacity >= threshold,
+            AlphaMode::Stochastic => {
+                // Different for every camera sample so the noise averages out
+                let point = record.point;
+                let [x, y, z, t] = [point.x, point.y, point.z, record.t].map(f64::to_bits);
+                to_unit(hash(&[x, y, z, t, ray.sample_id])) < opacity
+            }
+        };
+        visible && self.material.is_visible(ray, record)
+    }
+
+    fn albedo(&self, record: &HitRecord) -> Color {
+        self.material.albedo(record)
+    }
+}
+
+impl Material for OneSided {
+    fn scatter(
+        &self,
+        ray: &Ray,
+        record: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Color, Ray)> {
+        if record.front_face {
+            self.material.scatter(ray, record, sampler)
+        } else {
+            None
+        }
+    }
+
+    fn eval(&self, ray: &Ray, record: &HitRecord, direction: &Vec3) -> Option<(Color, f64)> {
+        if record.front_face {
+            self.material.eval(ray, record, direction)
+        } else {
+            None
+        }
+    }
+
+    fn is_visible(&self, ray: &Ray, record: &HitRecord) -> bool {
+        self.material.is_visible(ray, record)
+    }
+
+    fn albedo(&self, record: &HitRecord) -> Color {
+        self.material.albedo(record)
+    }
+}
+
+impl MixMaterial {
+    fn weight(&self, record: &HitRecord) -> f64 {
+        self.weight
+            .value(record.u, record.v, &record.point)
+            .x
+            .clamp(0., 1.)
+    }
+}
+
+impl Material for MixMaterial {
+    fn scatter(
+        &self,
+        ray: &Ray,
+        record: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Color, Ray)> {
+        // The probability of the choice cancels out with the weight
+        if sampler.get_1d() < self.weight(record) {
+            self.second.scatter(ray, record, sampler)
+        } else {
+            self.first.scatter(ray, record, sampler)
+        }
+    }
+
+    fn eval(&self, ray: &Ray, record: &HitRecord, direction: &Vec3) -> Option<(Color, f64)> {
+        let weight = self.weight(record);
+        if weight == 0. {
+            return self.first.eval(ray, record, direction);
+        }
+        if weight == 1. {
+            return self.second.eval(ray, record, direction);
+        }
+
+        let (first, first_pdf) = self.first.eval(ray, record, direction)?;
+        let (second, second_pdf) = self.second.eval(ray, record, direction)?;
+        Some((
+            (1. - weight) * first + weight * second,
+            (1. - weight) * first_pdf + weight * second_pdf,
+        ))
+    }
+
+    fn is_visible(&self, ray: &Ray, record: &HitRecord) -> bool {
+        self.first.is_visible(ray, record) || self.second.is_visible(ray, record)
+    }
+
+    fn albedo(&self, record: &HitRecord) -> Color {
+        let weight = self.weight(record);
+        (1. - weight) * self.first.albedo(record) + weight * self.second.albedo(record)
+    }
+}
+
+impl Coated {
+    // Direction of the reflection off a smooth coating, in the local frame
+    fn mirror(wo: &Vec3) -> Vec3 {
+        Vec3 {
+            x: -wo.x,
+            y: -wo.y,
+            z: wo.z,
+        }
+    }
+}
+
+impl Material for Coated {
+    fn scatter(
+        &self,
+        ray: &Ray,
+        record: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Color, Ray)> {
+        let frame = Onb::new(&record.normal);
+        let wo = frame.to_local(&-ray.dir.unit_vector());
+        if wo.z <= 0. || !record.front_face {
+            return self.base.scatter(ray, record, sampler);
+        }
+
+        // Reflect off the coating proportionally to its reflectance, otherwise
+        // enter it and let the base scatter the light
+        let reflectance = fresnel_dielectric(wo.z, self.ior);
+        if sampler.get_1d() < reflectance {
+            let (wi, attenuation) = if self.distribution.is_smooth() {
+                (Self::mirror(&wo), 1.)
+            } else {
+                let wm = self
+                    .distribution
+                    .sample_visible_normal(&wo, sampler.get_2d());
+                let wi = reflect(&wo, &wm);
+                if wi.z <= 0. {
+                    return None;
+                }
+                let masking = self.distribution.g(&wo, &wi) / self.distribution.g1(&wo);
+                (
+                    wi,
+                    masking * fresnel_dielectric(wo.dot(&wm), self.ior) / reflectance,
+                )
+            };
+
+            let scattered = ray.spawn(record.point, frame.local(&wi));
+            return Some((
+                Color {
+                    x: attenuation,
+                    y: attenuation,
+                    z: attenuation,
+                },
+                scattered,
+            ));
+        }
+
+        let (attenuation, scattered) = self.base.scatter(ray, record, sampler)?;
+        // Part of the light leaving the base is reflected back by the coating
+        let cos_out = scattered.dir.unit_vector().dot(&record.normal).abs();
+        let transmittance = 1. - fresnel_dielectric(cos_out, self.ior);
+        Some((
+            transmittance * ray.sample_color(&self.color) * attenuation,
+            scattered,
+        ))
+    }
+
+    fn eval(&self, ray: &Ray, record: &HitRecord, direction: &Vec3) -> Option<(Color, f64)> {
+        let frame = Onb::new(&record.normal);
+        let wo = frame.to_local(&-ray.dir.unit_vector());
+        if wo.z <= 0. || !record.front_face {
+            return self.base.eval(ray, record, direction);
+        }
+        let wi = frame.to_local(&direction.unit_vector());
+        // The mirror reflection of a smooth coating is only picked by scatter
+        if self.distribution.is_smooth() && (wi - Self::mirror(&wo)).near_zero() {
+            return None;
+        }
+
+        // The base is seen through the coating, the light crossing it twice
+        let (base, base_pdf) = self.base.eval(ray, record, direction)?;
+        let reflectance = fresnel_dielectric(wo.z, self.ior);
+        let transmittance = (1. - reflectance) * (1. - fresnel_dielectric(wi.z.abs(), self.ior));
+        let mut value = transmittance * ray.sample_color(&self.color) * base;
+        let mut pdf = (1. - reflectance) * base_pdf;
+
+        if wi.z > 0. && !self.distribution.is_smooth() {
+            let wm = (wo + wi).unit_vector();
+            let coat = self.distribution.d(&wm)
+                * self.distribution.g(&wo, &wi)
+                * fresnel_dielectric(wo.dot(&wm), self.ior)
+                / (4. * wo.z);
+            value += Color {
+                x: coat,
+                y: coat,
+                z: coat,
+            };
+            pdf += reflectance * self.distribution.d_visible(&wo, &wm) / (4. * wo.dot(&wm));
+        }
+        Some((value, pdf))
+    }
+
+    fn is_visible(&self, ray: &Ray, record: &HitRecord) -> bool {
+        self.base.is_visible(ray, record)
+    }
+
+    fn albedo(&self, record: &HitRecord) -> Color {
+        self.color * self.base.albedo(record)
+    }
+}
+
+impl Material for ThinFilm {
+    fn scatter(
+        &self,
+        ray: &Ray,
+        record: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Color, Ray)> {
+        let frame = Onb::new(&record.normal);
+        let wo = frame.to_local(&-ray.dir.unit_vector());
+        if wo.z <= 0. {
+            return None;
+        }
+
+        let wm = if self.distribution.is_smooth() {
+            Vec3 {
+                x: 0.,
+                y: 0.,
+                z: 1.,
+            }
+        } else {
+            self.distribution
+                .sample_visible_normal(&wo, sampler.get_2d())
+        };
+        let (reflectance, eta) = self.reflectance(ray, record, wo.dot(&wm));
+
+        // Choose between reflection and transmission by the average
+        // reflectance, each channel is then weighted by its own one
+        let (wi, weight) = match eta {
+            None => (reflect(&wo, &wm), reflectance),
+            Some(eta) => {
+                let probability = (reflectance.x + reflectance.y + reflectance.z) / 3.;
+                let refracted = refract(&wo, &wm, eta);
+                match refracted {
+                    Some(refracted) if sampler.get_1d() >= probability => {
+                        let transmittance = Color {
+                            x: 1. - reflectance.x,
+                            y: 1. - reflectance.y,
+                            z: 1. - reflectance.z,
+                        };
+                        (refracted, transmittance / (1. - probability))
+                    }
+                    Some(_) => (reflect(&wo, &wm), reflectance / probability),
+                    // Total internal reflection
+                    None => (
+                        reflect(&wo, &wm),
+                        Color {
+                            x: 1.,
+                            y: 1.,
+                            z: 1.,
+                        },
+                    ),
+                }
+            }
+        };
+
+        let is_reflection = wi.z > 0.;
+        if is_reflection != (wo.dot(&wm) * wi.dot(&wm) > 0.) || wi.z == 0. {
+            return None;
+        }
+
+        let masking = if self.distribution.is_smooth() {
+            1.
+        } else {
+            self.distribution.g(&wo, &wi) / self.distribution.g1(&wo)
+        };
+        let scattered = ray.spawn(record.point, frame.local(&wi));
+        Some((masking * weight, scattered))
+    }
+
+    fn eval(&self, ray: &Ray, record: &HitRecord, direction: &Vec3) -> Option<(Color, f64)> {
+        if self.distribution.is_smooth() || matches!(self.base, ThinFilmBase::Dielectric(_)) {
+            return None;
+        }
+
+        let frame = Onb::new(&record.normal);
+        let wo = frame.to_local(&-ray.dir.unit_vector());
+        let wi = frame.to_local(&direction.unit_vector());
+        if wo.z <= 0. || wi.z <= 0. {
+            return None;
+        }
+
+        let wm = (wo + wi).unit_vector();
+        let (reflectance, _) = self.reflectance(ray, record, wo.dot(&wm));
+        let bsdf_cos = self.distribution.d(&wm) * self.distribution.g(&wo, &wi) / (4. * wo.z);
+        let pdf = self.distribution.d_visible(&wo, &wm) / (4. * wo.dot(&wm));
+        Some((bsdf_cos * reflectance, pdf))
+    }
+
+    fn albedo(&self, _record: &HitRecord) -> Color {
+        match self.base {
+            ThinFilmBase::Dielectric(_) => Color {
+                x: 1.,
+                y: 1.,
+                z: 1.,
+            },
+            ThinFilmBase::Conductor { eta, k } => fresnel_conductor(1., &eta, &k),
+        }
+    }
+}
+
+impl Material for Subsurface {
+    fn scatter(
+        &self,
+        ray: &Ray,
+        record: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Color, Ray)> {
+        let frame = Onb::new(&record.normal);
+        let wo = frame.to_local(&-ray.dir.unit_vector());
+        if wo.z <= 0. {
+            return None;
+        }
+
+        let eta = if record.front_face {
+            self.ior
+        } else {
+            1. / self.ior
+        };
+        let wi = sample_dielectric(&self.distribution, &wo, eta, sampler)?;
+        let attenuation = if self.distribution.is_smooth() {
+            1.
+        } else {
+            self.distribution.g(&wo, &wi) / self.distribution.g1(&wo)
+        };
+
+        let mut scattered = ray.spawn(record.point, frame.local(&wi));
+        // Refracting through the boundary enters or leaves the medium
+        if wi.z < 0. {
+            scattered.medium = if record.front_face {
+                Some(self.medium)
+            } else {
+                None
+            };
+        }
+        Some((
+            Color {
+                x: attenuation,
+                y: attenuation,
+                z: attenuation,
+            },
+            scattered,
+        ))
+    }
+
+    fn albedo(&self, _record: &HitRecord) -> Color {
+        self.medium.albedo
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::geometry::HitRecord;
+    use crate::material::*;
+
+    fn flat_record() -> HitRecord {
+        HitRecord {
+            point: Vec3::default(),
+            normal: Vec3 {
+                x: 0.,
+                y: 0.,
+                z: 1.,
+            },
+            material: Arc::new(Lambertian {
+                albedo: Color::default(),
+            }),
+            t: 1.,
+            u: 0.5,
+            v: 0.5,
+            tangent: Vec3 {
+                x: 1.,
+                y: 0.,
+                z: 0.,
+            },
+            bitangent: Vec3 {
+                x: 0.,
+                y: 1.,
+                z: 0.,
+            },
+            front_face: true,
+            object_id: 0,
+        }
+    }
+
+    fn down_ray() -> Ray {
+        Ray::new(
+            Vec3 {
+                x: 0.,
+                y: 0.,
+                z: 1.,
+            },
+            Vec3 {
+                x: 0.,
+                y: 0.,
+                z: -1.,
+            },
+        )
+    }
+
+    #[test]
+    fn test_flat_normal_map_keeps_normal() {
+        let normal_map = NormalMap {
+            material: Arc::new(Lambertian {
+                albedo: Color::default(),
+            }),
+            texture: Arc::new(SolidColor::new(Color {
+                x: 0.5,
+                y: 0.5,
+                z: 1.,
+            })),
+            strength: 1.,
+        };
+        let record = normal_map.perturb(&down_ray(), &flat_record());
+        assert!((record.normal.z - 1.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_normal_map_tilts_along_tangent() {
+        let normal_map = NormalMap {
+            material: Arc::new(Lambertian {
+                albedo: Color::default(),
+            }),
+            texture: Arc::new(SolidColor::new(Color {
+                x: 1.,
+                y: 0.5,
+                z: 1.,
+            })),
+            strength: 1.,
+        };
+        let record = normal_map.perturb(&down_ray(), &flat_record());
+        assert!((record.normal.x - record.normal.z).abs() < 1e-12);
+        assert!(record.normal.y.abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_constant_bump_map_keeps_normal() {
+        let bump_map = BumpMap {
+            material: Arc::new(Lambertian {
+                albedo: Color::default(),
+            }),
+            height: Arc::new(SolidColor::gray(0.3)),
+            scale: 1.,
+        };
+        let record = bump_map.perturb(&down_ray(), &flat_record());
+        assert_eq!(record.normal.z, 1.);
+    }
+
+    #[test]
+    fn test_mix_eval_blends_materials() {
+        let dark = Arc::new(Lambertian {
+            albedo: Color::default(),
+        });
+        let white = Arc::new(Lambertian {
+            albedo: Color {
+                x: 1.,
+                y: 1.,
+                z: 1.,
+            },
+        });
+        let mix = MixMaterial {
+            first: dark,
+            second: white.clone(),
+            weight: Arc::new(SolidColor::gray(0.25)),
+        };
+        let direction = Vec3 {
+            x: 0.,
+            y: 0.6,
+            z: 0.8,
+        };
+        let (value, pdf) = mix.eval(&down_ray(), &flat_record(), &direction).unwrap();
+        let (white_value, white_pdf) = white.eval(&down_ray(), &flat_record(), &direction).unwrap();
+        assert!((value.x - 0.25 * white_value.x).abs() < 1e-12);
+        assert!((pdf - white_pdf).abs() < 1e-12);
+
+        // A mirror-like part can not be evaluated
+        let mix = MixMaterial {
+            first: white,
+            second: Arc::new(Metal::new(Color::default(), 0.)),
+            weight: Arc::new(SolidColor::gray(0.5)),
+        };
+        assert!(mix.eval(&down_ray(), &flat_record(), &direction).is_none());
+    }
+
+    #[test]
+    fn test_coating_conserves_energy() {
+        let mut sampler = IndependentSampler::new(0);
+        let white = Arc::new(Lambertian {
+            albedo: Color {
+                x: 1.,
+                y: 1.,
+                z: 1.,
+            },
+        });
+        let coated = Coated::new(
+            white,
+            1.5,
+            0.3,
+            Color {
+                x: 1.,
+                y: 1.,
+                z: 1.,
+            },
+        );
+        let samples = 10000;
+        let mut total = Color::default();
+        for _ in 0..samples {
+            if let Some((attenuation, _)) =
+                coated.scatter(&down_ray(), &flat_record(), &mut sampler)
+            {
+                total += attenuation;
+            }
+        }
+        let albedo = total.x / samples as f64;
+        assert!(albedo > 0.5 && albedo < 1.02);
+    }
+
+    #[test]
+    fn test_smooth_coating_evaluates_the_base() {
+        let white = Arc::new(Lambertian {
+            albedo: Color {
+                x: 1.,
+                y: 1.,
+                z: 1.,
+            },
+        });
+        let coated = Coated::new(
+            white.clone(),
+            1.5,
+            0.,
+            Color {
+                x: 1.,
+                y: 1.,
+                z: 1.,
+            },
+        );
+        let direction = Vec3 {
+            x: 0.,
+            y: 0.6,
+            z: 0.8,
+        };
+        let (value, pdf) = coated
+            .eval(&down_ray(), &flat_record(), &direction)
+            .unwrap();
+        let (base, base_pdf) = white.eval(&down_ray(), &flat_record(), &direction).unwrap();
+        let entering = 1. - fresnel_dielectric(1., 1.5);
+        let leaving = 1. - fresnel_dielectric(0.8, 1.5);
+        assert!((value.x - entering * leaving * base.x).abs() < 1e-12);
+        assert!((pdf - entering * base_pdf).abs() < 1e-12);
+
+        // The mirror reflection is left to scatter
+        let mirror = Vec3 {
+            x: 0.,
+            y: 0.,
+            z: 1.,
+        };
+        assert!(coated.eval(&down_ray(), &flat_record(), &mirror).is_none());
+    }
+
+    #[test]
+    fn test_soap_bubble_is_mostly_transparent() {
+        let mut sampler = IndependentSampler::new(0);
+        let bubble = ThinFilm::new(
+            ThinFilmBase::Dielectric(1.),
+            Arc::new(SolidColor::gray(400.)),
+            1.33,
+            0.,
+        );
+        let mut transmitted = 0;
+        for _ in 0..1000 {
+            let (_, scattered) = bubble
+                .scatter(&down_ray(), &flat_record(), &mut sampler)
+                .unwrap();
+            if scattered.dir.z < 0. {
+                transmitted += 1;
+                // Both sides are air, the light passes straight through
+                assert!((scattered.dir.unit_vector().z + 1.).abs() < 1e-9);
+            }
+        }
+        assert!(transmitted > 800);
+    }
+
+    #[test]
+    fn test_alpha_threshold() {
+        let mask = |opacity: f64| AlphaMask {
+            material: Arc::new(Lambertian {
+                albedo: Color::default(),
+            }),
+            opacity: Arc::new(SolidColor::gray(opacity)),
+            mode: AlphaMode::Threshold(0.5),
+        };
+        let ray = Ray::new(Vec3::default(), Vec3::default());
+        assert!(mask(0.7).is_visible(&ray, &flat_record()));
+        assert!(!mask(0.2).is_visible(&ray, &flat_record()));
+    }
+
+    #[test]
+    fn test_stochastic_alpha_changes_with_sample() {
+        let mask = AlphaMask {
+            material: Arc::new(Lambertian {
+                albedo: Color::default(),
+            }),
+            opacity: Arc::new(SolidColor::gray(0.3)),
+            mode: AlphaMode::Stochastic,
+        };
+        let mut ray = Ray::new(Vec3::default(), Vec3::default());
+        let visible = (0..1000)
+            .filter(|&sample_id| {
+                ray.sample_id = sample_id;
+                mask.is_visible(&ray, &flat_record())
+            })
+            .count();
+        assert!((250..350).contains(&visible));
+    }
+
+    #[test]
+    fn test_one_sided_back_absorbs() {
+        let mut sampler = IndependentSampler::new(0);
+        let material = OneSided {
+            material: Arc::new(Lambertian {
+                albedo: Color::default(),
+            }),
+        };
+        let mut record = flat_record();
+        assert!(material
+            .scatter(&down_ray(), &record, &mut sampler)
+            .is_some());
+        record.front_face = false;
+        assert!(material
+            .scatter(&down_ray(), &record, &mut sampler)
+            .is_none());
+    }
+
+    #[test]
+    fn test_dispersion_splits_wavelengths() {
+        let mut sampler = IndependentSampler::new(0);
+        let glass = Dielectric::dispersive(Dispersion::sf11(), 0.);
+        let mut refracted = |lambda: f64| {
+            let mut ray = Ray::new(
+                Vec3 {
+                    x: -1.,
+                    y: 0.,
+                    z: 1.,
+                },
+                Vec3 {
+                    x: 1.,
+                    y: 0.,
+                    z: -1.,
+                },
+            );
+            let mut wavelengths = Wavelengths::sample(0.);
+            wavelengths.lambda[0] = lambda;
+            ray.wavelengths = Some(wavelengths);
+            loop {
+                let (attenuation, scattered) =
+                    glass.scatter(&ray, &flat_record(), &mut sampler).unwrap();
+                if scattered.dir.z < 0. {
+                    assert_eq!(attenuation.x, 3.);
+                    assert_eq!(attenuation.y, 0.);
+                    assert!(scattered.wavelengths.unwrap().secondary_terminated);
+                    return scattered.dir.unit_vector();
+                }
+            }
+        };
+        // Blue light bends more towards the normal than red light
+        assert!(refracted(420.).x < refracted(680.).x);
+    }
+
+    #[test]
+    fn test_spectral_conductor_uses_measured_index() {
+        let mut sampler = IndependentSampler::new(0);
+        let gold = Conductor::gold(0.);
+        let (rgb, _) = gold
+            .scatter(&down_ray(), &flat_record(), &mut sampler)
+            .unwrap();
+
+        // At the measured wavelengths the reflectance matches the color channels
+        let mut ray = down_ray();
+        ray.wavelengths = Some(Wavelengths {
+            lambda: [650., 550., 450.],
+            secondary_terminated: false,
+        });
+        let (spectral, _) = gold.scatter(&ray, &flat_record(), &mut sampler).unwrap();
+        assert!((spectral - rgb).len() < 1e-12);
+    }
+
+    #[test]
+    fn test_subsurface_enters_and_leaves_medium() {
+        let mut sampler = IndependentSampler::new(0);
+        let gray = Color {
+            x: 0.5,
+            y: 0.5,
+            z: 0.5,
+        };
+        // Matched index of refraction, light always goes through the boundary
+        let material = Subsurface::new(gray, gray, 1., 0.);
+        let mut record = flat_record();
+        let (_, inside) = material
+            .scatter(&down_ray(), &record, &mut sampler)
+            .unwrap();
+        assert_eq!(inside.medium, Some(material.medium));
+
+        // Leaving through the bottom of the object, the normal still faces the ray
+        record.front_face = false;
+        let (_, outside) = material.scatter(&inside, &record, &mut sampler).unwrap();
+        assert_eq!(outside.medium, None);
+    }
 }
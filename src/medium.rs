@@ -0,0 +1,145 @@
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::vec3::*;
+
+/// Homogeneous participating medium scattering light isotropically. Both
+/// parameters are per color channel
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Medium {
+    /// Average distance light travels between two interactions
+    pub mean_free_path: Color,
+    /// Fraction of the interactions scattering light instead of absorbing it
+    pub albedo: Color,
+}
+
+impl Medium {
+    pub fn new(mean_free_path: Color, albedo: Color) -> Self {
+        Medium {
+            mean_free_path,
+            albedo,
+        }
+    }
+
+    /// Samples the distance to the next interaction along `ray`, up to the
+    /// parameter `t_max` of the next surface. Returns the parameter of the
+    /// scattering point, None when the surface is reached first, and the
+    /// weight of the path
+    pub fn sample(&self, ray: &Ray, t_max: f64, sampler: &mut dyn Sampler) -> (Option<f64>, Color) {
+        let albedo = ray.sample_color(&self.albedo);
+        let sigma_t = self.sigma_t(ray);
+
+        // Sample the distance with the density of a random channel, the
+        // weight then uses the average of the densities of all channels
+        let (u_channel, u_distance) = sampler.get_2d();
+        let channel = ((3. * u_channel) as usize).min(2);
+        let distance = -(1. - u_distance).ln() / sigma_t[channel];
+        let length = ray.dir.len();
+        let surface_distance = t_max * length;
+
+        let transmittance = |distance: f64| sigma_t.map(|sigma| (-sigma * distance).exp());
+        if distance < surface_distance {
+            let tr = transmittance(distance);
+            let pdf = (0..3).map(|i| sigma_t[i] * tr[i]).sum::<f64>() / 3.;
+            let weight = Color {
+                x: albedo.x * sigma_t[0] * tr[0],
+                y: albedo.y * sigma_t[1] * tr[1],
+                z: albedo.z * sigma_t[2] * tr[2],
+            } / pdf;
+            (Some(distance / length), weight)
+        } else {
+            let tr = transmittance(surface_distance);
+            let pdf = tr.iter().sum::<f64>() / 3.;
+            let weight = Color {
+                x: tr[0],
+                y: tr[1],
+                z: tr[2],
+            } / pdf;
+            (None, weight)
+        }
+    }
+
+    /// Fraction of the light going through the medium along `ray` up to the
+    /// parameter `t_max`
+    pub fn transmittance(&self, ray: &Ray, t_max: f64) -> Color {
+        let distance = t_max * ray.dir.len();
+        let [x, y, z] = self.sigma_t(ray).map(|sigma| (-sigma * distance).exp());
+        Color { x, y, z }
+    }
+
+    // Extinction coefficients at the channels of the ray
+    fn sigma_t(&self, ray: &Ray) -> [f64; 3] {
+        let mean_free_path = ray.sample_color(&self.mean_free_path);
+        [
+            1. / mean_free_path.x.max(1e-9),
+            1. / mean_free_path.y.max(1e-9),
+            1. / mean_free_path.z.max(1e-9),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::medium::*;
+    use crate::sampler::IndependentSampler;
+
+    #[test]
+    fn test_transmittance_is_unbiased() {
+        let mut sampler = IndependentSampler::new(0);
+        let medium = Medium::new(
+            Color {
+                x: 1.,
+                y: 0.5,
+                z: 2.,
+            },
+            Color {
+                x: 1.,
+                y: 1.,
+                z: 1.,
+            },
+        );
+        let ray = Ray::new(
+            Point3::default(),
+            Vec3 {
+                x: 0.,
+                y: 0.,
+                z: 2.,
+            },
+        );
+
+        // Reaching the surface at a distance of 1
+        let samples = 40000;
+        let mut total = Color::default();
+        for _ in 0..samples {
+            if let (None, weight) = medium.sample(&ray, 0.5, &mut sampler) {
+                total += weight;
+            }
+        }
+        let average = total / samples as f64;
+        let expected = medium.transmittance(&ray, 0.5);
+        assert!((expected.x - (-1f64).exp()).abs() < 1e-12);
+        assert!((average.x - expected.x).abs() < 0.02);
+        assert!((average.y - expected.y).abs() < 0.02);
+        assert!((average.z - expected.z).abs() < 0.02);
+    }
+
+    #[test]
+    fn test_gray_medium_weights() {
+        let mut sampler = IndependentSampler::new(0);
+        let gray = |value: f64| Color {
+            x: value,
+            y: value,
+            z: value,
+        };
+        let medium = Medium::new(gray(0.3), gray(0.8));
+        let ray = Ray::new(Point3::default(), gray(1.));
+        for _ in 0..100 {
+            match medium.sample(&ray, 0.2, &mut sampler) {
+                (Some(t), weight) => {
+                    assert!(t < 0.2);
+                    assert!((weight.x - 0.8).abs() < 1e-9);
+                }
+                (None, weight) => assert!((weight.x - 1.).abs() < 1e-9),
+            }
+        }
+    }
+}
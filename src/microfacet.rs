@@ -0,0 +1,341 @@
+use std::f64::consts::PI;
+
+use crate::sampler::Sampler;
+use crate::vec3::*;
+
+// All directions here are unit vectors in the local shading frame where the
+// normal is +Z, pointing away from the surface
+
+// Below this roughness the surface is treated as perfectly smooth
+const SMOOTH_ALPHA: f64 = 1e-3;
+
+/// Isotropic Trowbridge-Reitz (GGX) microfacet distribution with the Smith
+/// height-correlated masking-shadowing function
+pub struct TrowbridgeReitz {
+    alpha: f64,
+}
+
+impl TrowbridgeReitz {
+    /// `roughness` is the perceptual roughness in [0, 1], squared to get alpha
+    pub fn from_roughness(roughness: f64) -> Self {
+        let roughness = roughness.clamp(0., 1.);
+        TrowbridgeReitz {
+            alpha: roughness * roughness,
+        }
+    }
+
+    pub fn alpha(&self) -> f64 {
+        self.alpha
+    }
+
+    pub fn is_smooth(&self) -> bool {
+        self.alpha < SMOOTH_ALPHA
+    }
+
+    /// Density of microfacet normals
+    pub fn d(&self, wm: &Vec3) -> f64 {
+        if wm.z <= 0. {
+            return 0.;
+        }
+        let alpha2 = self.alpha * self.alpha;
+        let denominator = wm.z * wm.z * (alpha2 - 1.) + 1.;
+        alpha2 / (PI * denominator * denominator)
+    }
+
+    fn lambda(&self, w: &Vec3) -> f64 {
+        let cos2 = w.z * w.z;
+        if cos2 == 0. {
+            return f64::INFINITY;
+        }
+        let tan2 = (1. - cos2).max(0.) / cos2;
+        ((1. + self.alpha * self.alpha * tan2).sqrt() - 1.) / 2.
+    }
+
+    /// Fraction of microfacets visible from `w`
+    pub fn g1(&self, w: &Vec3) -> f64 {
+        1. / (1. + self.lambda(w))
+    }
+
+    /// Fraction of microfacets visible from both directions
+    pub fn g(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        1. / (1. + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Density of normals visible from `w`
+    pub fn d_visible(&self, w: &Vec3, wm: &Vec3) -> f64 {
+        if w.z == 0. {
+            return 0.;
+        }
+        self.g1(w) / w.z.abs() * self.d(wm) * w.dot(wm).abs()
+    }
+
+    /// Samples a microfacet normal visible from `w` (Heitz 2018)
+    pub fn sample_visible_normal(&self, w: &Vec3, (u1, u2): (f64, f64)) -> Vec3 {
+        // Stretch the view direction so the distribution becomes a hemisphere
+        let wh = Vec3 {
+            x: self.alpha * w.x,
+            y: self.alpha * w.y,
+            z: w.z,
+        }
+        .unit_vector();
+
+        let length2 = wh.x * wh.x + wh.y * wh.y;
+        let t1 = if length2 > 0. {
+            Vec3 {
+                x: -wh.y,
+                y: wh.x,
+                z: 0.,
+            } * (1. / length2.sqrt())
+        } else {
+            Vec3 {
+                x: 1.,
+                y: 0.,
+                z: 0.,
+            }
+        };
+        let t2 = wh.cross(&t1);
+
+        // Uniform point on the disk, warped to the projected visible hemisphere
+        let r = u1.sqrt();
+        let phi = 2. * PI * u2;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1. + wh.z);
+        let p2 = (1. - s) * (1. - p1 * p1).max(0.).sqrt() + s * r * phi.sin();
+        let nh = p1 * t1 + p2 * t2 + (1. - p1 * p1 - p2 * p2).max(0.).sqrt() * wh;
+
+        Vec3 {
+            x: self.alpha * nh.x,
+            y: self.alpha * nh.y,
+            z: nh.z.max(1e-6),
+        }
+        .unit_vector()
+    }
+}
+
+/// Mirrors `w` around the normal `n`
+pub fn reflect(w: &Vec3, n: &Vec3) -> Vec3 {
+    -*w + 2. * w.dot(n) * *n
+}
+
+/// Refracts `w` through a surface with normal `n` on the same side as `w`.
+/// `eta` is the ratio of the index of refraction on the other side to the one
+/// on the side of `w`. Returns None on total internal reflection
+pub fn refract(w: &Vec3, n: &Vec3, eta: f64) -> Option<Vec3> {
+    let cos_i = w.dot(n);
+    let sin2_i = (1. - cos_i * cos_i).max(0.);
+    let sin2_t = sin2_i / (eta * eta);
+    if sin2_t >= 1. {
+        return None;
+    }
+    let cos_t = (1. - sin2_t).sqrt();
+    Some(-*w / eta + (cos_i / eta - cos_t) * *n)
+}
+
+/// Samples reflection or refraction through a dielectric interface with the
+/// relative index of refraction `eta`, choosing proportionally to the Fresnel
+/// term. Returns None when the sampled direction is not valid
+pub fn sample_dielectric(
+    distribution: &TrowbridgeReitz,
+    wo: &Vec3,
+    eta: f64,
+    sampler: &mut dyn Sampler,
+) -> Option<Vec3> {
+    let wm = if distribution.is_smooth() {
+        Vec3 {
+            x: 0.,
+            y: 0.,
+            z: 1.,
+        }
+    } else {
+        distribution.sample_visible_normal(wo, sampler.get_2d())
+    };
+
+    let reflectance = fresnel_dielectric(wo.dot(&wm), eta);
+    let wi = match refract(wo, &wm, eta) {
+        Some(refracted) if sampler.get_1d() >= reflectance => refracted,
+        _ => reflect(wo, &wm),
+    };
+
+    // A reflection has to stay above and a refraction below the surface
+    let is_reflection = wi.z > 0.;
+    if is_reflection != (wo.dot(&wm) * wi.dot(&wm) > 0.) || wi.z == 0. {
+        return None;
+    }
+    Some(wi)
+}
+
+/// Evaluates a rough dielectric interface for the directions `wo` and `wi`.
+/// Returns the BSDF multiplied by the cosine term and the density of
+/// `sample_dielectric` choosing `wi`
+pub fn eval_dielectric(
+    distribution: &TrowbridgeReitz,
+    wo: &Vec3,
+    wi: &Vec3,
+    eta: f64,
+) -> Option<(f64, f64)> {
+    if wo.z <= 0. || wi.z == 0. {
+        return None;
+    }
+
+    let is_reflection = wi.z > 0.;
+    let wm = if is_reflection {
+        (*wo + *wi).unit_vector()
+    } else {
+        // Generalized half vector of the refraction, flipped to the side of `wo`
+        let wm = (*wo + eta * *wi).unit_vector();
+        if wm.z < 0. {
+            -wm
+        } else {
+            wm
+        }
+    };
+    // Discard back-facing microfacets
+    if wo.dot(&wm) <= 0. || (wi.dot(&wm) > 0.) != is_reflection {
+        return None;
+    }
+
+    let reflectance = fresnel_dielectric(wo.dot(&wm), eta);
+    let d = distribution.d(&wm);
+    let g = distribution.g(wo, wi);
+    if is_reflection {
+        let value = d * g * reflectance / (4. * wo.z);
+        let pdf = distribution.d_visible(wo, &wm) / (4. * wo.dot(&wm)) * reflectance;
+        Some((value, pdf))
+    } else {
+        let denominator = (wi.dot(&wm) + wo.dot(&wm) / eta).powi(2);
+        let jacobian = wi.dot(&wm).abs() / denominator;
+        let value = d * g * (1. - reflectance) * wo.dot(&wm) * jacobian / wo.z;
+        let pdf = distribution.d_visible(wo, &wm) * jacobian * (1. - reflectance);
+        Some((value, pdf))
+    }
+}
+
+/// Unpolarized Fresnel reflectance of a dielectric interface. `eta` is the
+/// relative index of refraction of the transmitted side
+pub fn fresnel_dielectric(cos_theta_i: f64, eta: f64) -> f64 {
+    let cos_i = cos_theta_i.clamp(0., 1.);
+    let sin2_t = (1. - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1. {
+        return 1.;
+    }
+    let cos_t = (1. - sin2_t).sqrt();
+
+    let r_parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    (r_parallel * r_parallel + r_perpendicular * r_perpendicular) / 2.
+}
+
+/// Schlick's approximation of the Fresnel reflectance with the reflectance
+/// `f0` at normal incidence
+pub fn fresnel_schlick(f0: &Color, cos_theta_i: f64) -> Color {
+    let weight = (1. - cos_theta_i.clamp(0., 1.)).powi(5);
+    *f0 + weight
+        * Color {
+            x: 1. - f0.x,
+            y: 1. - f0.y,
+            z: 1. - f0.z,
+        }
+}
+
+fn fresnel_complex(cos_theta_i: f64, eta: f64, k: f64) -> f64 {
+    let cos2 = cos_theta_i * cos_theta_i;
+    let sin2 = 1. - cos2;
+    let eta2 = eta * eta;
+    let k2 = k * k;
+
+    let t0 = eta2 - k2 - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4. * eta2 * k2).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.).sqrt();
+    let t2 = 2. * cos_theta_i * a;
+    let r_s = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let r_p = r_s * (t3 - t4) / (t3 + t4);
+    (r_p + r_s) / 2.
+}
+
+/// Unpolarized Fresnel reflectance of a conductor with the complex index of
+/// refraction `eta + i k`, evaluated per color channel
+pub fn fresnel_conductor(cos_theta_i: f64, eta: &Color, k: &Color) -> Color {
+    let cos_i = cos_theta_i.clamp(0., 1.);
+    Color {
+        x: fresnel_complex(cos_i, eta.x, k.x),
+        y: fresnel_complex(cos_i, eta.y, k.y),
+        z: fresnel_complex(cos_i, eta.z, k.z),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::f64::consts::PI;
+
+    use crate::microfacet::*;
+
+    #[test]
+    fn test_fresnel_dielectric() {
+        assert!((fresnel_dielectric(1., 1.5) - 0.04).abs() < 1e-12);
+        assert_eq!(fresnel_dielectric(0.1, 1. / 1.5), 1.);
+        assert_eq!(fresnel_dielectric(1., 1.), 0.);
+    }
+
+    #[test]
+    fn test_fresnel_conductor_without_absorption_is_dielectric() {
+        let eta = Color {
+            x: 1.5,
+            y: 1.5,
+            z: 1.5,
+        };
+        let reflectance = fresnel_conductor(0.6, &eta, &Color::default());
+        assert!((reflectance.x - fresnel_dielectric(0.6, 1.5)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_refract_straight_through() {
+        let n = Vec3 {
+            x: 0.,
+            y: 0.,
+            z: 1.,
+        };
+        let transmitted = refract(&n, &n, 1.5).unwrap();
+        assert!((transmitted + n).len() < 1e-12);
+    }
+
+    #[test]
+    fn test_distribution_is_normalized() {
+        // The projected area of the microfacets equals the macro surface area
+        let distribution = TrowbridgeReitz::from_roughness(0.5);
+        let steps = 2000;
+        let mut integral = 0.;
+        for i in 0..steps {
+            let theta = (i as f64 + 0.5) / steps as f64 * PI / 2.;
+            let wm = Vec3 {
+                x: theta.sin(),
+                y: 0.,
+                z: theta.cos(),
+            };
+            integral += distribution.d(&wm) * theta.cos() * theta.sin() * 2. * PI;
+        }
+        integral *= PI / 2. / steps as f64;
+        assert!((integral - 1.).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_visible_normals_face_the_viewer() {
+        let distribution = TrowbridgeReitz::from_roughness(0.8);
+        let w = Vec3 {
+            x: 0.8,
+            y: 0.,
+            z: 0.6,
+        };
+        for i in 0..10 {
+            for j in 0..10 {
+                let wm = distribution.sample_visible_normal(&w, (i as f64 / 10., j as f64 / 10.));
+                assert!(wm.z > 0.);
+                assert!(w.dot(&wm) >= -1e-9);
+                assert!((wm.len() - 1.).abs() < 1e-9);
+            }
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use crate::vec3::Vec3;
+
+/// Orthonormal basis with `w` along a given direction, used to build samples
+/// in a local frame (around a normal or a light direction)
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Onb {
+    pub fn new(direction: &Vec3) -> Self {
+        let w = direction.unit_vector();
+        let a = if w.x.abs() > 0.9 {
+            Vec3 {
+                x: 0.,
+                y: 1.,
+                z: 0.,
+            }
+        } else {
+            Vec3 {
+                x: 1.,
+                y: 0.,
+                z: 0.,
+            }
+        };
+        let v = w.cross(&a).unit_vector();
+        let u = w.cross(&v);
+        Onb { u, v, w }
+    }
+
+    /// Converts local coordinates to world space
+    pub fn local(&self, a: &Vec3) -> Vec3 {
+        a.x * self.u + a.y * self.v + a.z * self.w
+    }
+
+    /// Converts a world space vector to local coordinates
+    pub fn to_local(&self, a: &Vec3) -> Vec3 {
+        Vec3 {
+            x: a.dot(&self.u),
+            y: a.dot(&self.v),
+            z: a.dot(&self.w),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::onb::Onb;
+    use crate::vec3::Vec3;
+
+    #[test]
+    fn test_round_trip() {
+        let onb = Onb::new(&Vec3 {
+            x: 1.,
+            y: 2.,
+            z: 3.,
+        });
+        assert!((onb.u.dot(&onb.v)).abs() < 1e-12);
+        assert!((onb.u.dot(&onb.w)).abs() < 1e-12);
+        assert!((onb.u.len() - 1.).abs() < 1e-12);
+
+        let a = Vec3 {
+            x: 0.3,
+            y: -0.2,
+            z: 0.9,
+        };
+        let back = onb.to_local(&onb.local(&a));
+        assert!((back - a).len() < 1e-12);
+    }
+}
@@ -0,0 +1,189 @@
+use std::f64::consts::PI;
+
+use crate::vec3::*;
+
+/// How fisheye lenses map the angle from the optical axis to the distance
+/// from the image center
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FisheyeMapping {
+    /// Distance proportional to the angle
+    Equidistant,
+    /// Equal solid angles get equal areas of the image
+    Equisolid,
+}
+
+/// How the camera maps the image to rays
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Projection {
+    /// Pinhole camera, its vertical field of view set by `Camera::set_vertical_fov`
+    #[default]
+    Perspective,
+    /// Parallel rays, without foreshortening. `height` is the height of the
+    /// view in world units
+    Orthographic { height: f64 },
+    /// Circular image touching the top and bottom of the frame, covering
+    /// `fov` degrees across its diameter. Pixels outside the circle are black
+    Fisheye { mapping: FisheyeMapping, fov: f64 },
+    /// Longitude across the width and latitude across the height, a 2:1
+    /// image covering the whole sphere around the camera
+    Equirectangular,
+}
+
+impl Projection {
+    /// Origin and direction in camera space, looking down -z with y up, of
+    /// the ray through the point (x, y) of the viewport. The viewport spans
+    /// [-half_width, half_width] horizontally and [-1, 1] vertically at a
+    /// distance of 1. None when no ray goes through the point
+    pub fn ray(&self, x: f64, y: f64, half_width: f64) -> Option<(Point3, Vec3)> {
+        let origin = Point3::default();
+        match *self {
+            Projection::Perspective => Some((origin, Vec3 { x, y, z: -1. })),
+            Projection::Orthographic { height } => Some((
+                Point3 {
+                    x: x * height / 2.,
+                    y: y * height / 2.,
+                    z: 0.,
+                },
+                Vec3 {
+                    x: 0.,
+                    y: 0.,
+                    z: -1.,
+                },
+            )),
+            Projection::Fisheye { mapping, fov } => {
+                let radius = (x * x + y * y).sqrt();
+                let half_fov = fov.to_radians() / 2.;
+                let theta = match mapping {
+                    FisheyeMapping::Equidistant => radius * half_fov,
+                    FisheyeMapping::Equisolid => {
+                        // r = 2f sin(θ / 2), with r = 1 at half the field of view
+                        let sine = radius * (half_fov / 2.).sin();
+                        if sine > 1. {
+                            return None;
+                        }
+                        2. * sine.asin()
+                    }
+                };
+                if radius > 1. || theta > PI {
+                    return None;
+                }
+                let (cos_phi, sin_phi) = if radius > 0. {
+                    (x / radius, y / radius)
+                } else {
+                    (1., 0.)
+                };
+                Some((
+                    origin,
+                    Vec3 {
+                        x: theta.sin() * cos_phi,
+                        y: theta.sin() * sin_phi,
+                        z: -theta.cos(),
+                    },
+                ))
+            }
+            Projection::Equirectangular => {
+                let longitude = x / half_width * PI;
+                let latitude = y * PI / 2.;
+                Some((
+                    origin,
+                    Vec3 {
+                        x: latitude.cos() * longitude.sin(),
+                        y: latitude.sin(),
+                        z: -latitude.cos() * longitude.cos(),
+                    },
+                ))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::projection::*;
+
+    fn assert_direction(ray: Option<(Point3, Vec3)>, expected: Vec3) {
+        let (_, direction) = ray.unwrap();
+        assert!((direction.unit_vector() - expected).len() < 1e-9);
+    }
+
+    #[test]
+    fn test_center_looks_forward() {
+        let forward = Vec3 {
+            x: 0.,
+            y: 0.,
+            z: -1.,
+        };
+        for projection in [
+            Projection::Perspective,
+            Projection::Orthographic { height: 4. },
+            Projection::Fisheye {
+                mapping: FisheyeMapping::Equisolid,
+                fov: 180.,
+            },
+            Projection::Equirectangular,
+        ] {
+            assert_direction(projection.ray(0., 0., 2.), forward);
+        }
+    }
+
+    #[test]
+    fn test_fisheye_edge() {
+        for mapping in [FisheyeMapping::Equidistant, FisheyeMapping::Equisolid] {
+            let fisheye = Projection::Fisheye { mapping, fov: 180. };
+            // The edge of the circle is 90° away from the axis
+            assert_direction(
+                fisheye.ray(0., 1., 1.),
+                Vec3 {
+                    x: 0.,
+                    y: 1.,
+                    z: 0.,
+                },
+            );
+            assert!(fisheye.ray(0.8, 0.8, 1.).is_none());
+        }
+    }
+
+    #[test]
+    fn test_equirectangular_covers_sphere() {
+        let panorama = Projection::Equirectangular;
+        assert_direction(
+            panorama.ray(2., 0., 2.),
+            Vec3 {
+                x: 0.,
+                y: 0.,
+                z: 1.,
+            },
+        );
+        assert_direction(
+            panorama.ray(1., 0., 2.),
+            Vec3 {
+                x: 1.,
+                y: 0.,
+                z: 0.,
+            },
+        );
+        assert_direction(
+            panorama.ray(0.3, 1., 2.),
+            Vec3 {
+                x: 0.,
+                y: 1.,
+                z: 0.,
+            },
+        );
+    }
+
+    #[test]
+    fn test_orthographic_rays_are_parallel() {
+        let orthographic = Projection::Orthographic { height: 4. };
+        let (origin, direction) = orthographic.ray(1., -0.5, 2.).unwrap();
+        assert_eq!(
+            origin,
+            Point3 {
+                x: 2.,
+                y: -1.,
+                z: 0.
+            }
+        );
+        assert_eq!(direction, orthographic.ray(0., 0., 2.).unwrap().1);
+    }
+}
@@ -1,14 +1,57 @@
-use crate::vec3::Vec3;
+use crate::medium::Medium;
+use crate::spectrum::Wavelengths;
+use crate::vec3::{Color, Vec3};
 
 type Point3 = Vec3;
 
 pub struct Ray {
     pub orig: Point3,
     pub dir: Vec3,
+    // Set when rendering spectrally, colors then hold values at these wavelengths
+    pub wavelengths: Option<Wavelengths>,
+    // Medium the ray travels through, None for empty space
+    pub medium: Option<Medium>,
+    // Moment in seconds the ray sees the scene at, for animated objects
+    pub time: f64,
+    // Camera sample the ray belongs to, seeding the random decisions taken
+    // without a sampler
+    pub sample_id: u64,
 }
 
 impl Ray {
+    pub fn new(orig: Point3, dir: Vec3) -> Self {
+        Ray {
+            orig,
+            dir,
+            wavelengths: None,
+            medium: None,
+            time: 0.,
+            sample_id: 0,
+        }
+    }
+
+    /// Ray continuing the path of this one from `orig` along `dir`
+    pub fn spawn(&self, orig: Point3, dir: Vec3) -> Self {
+        Ray {
+            orig,
+            dir,
+            wavelengths: self.wavelengths,
+            medium: self.medium,
+            time: self.time,
+            sample_id: self.sample_id,
+        }
+    }
+
     pub fn at(&self, t: f64) -> Vec3 {
         self.orig + self.dir * t
     }
+
+    /// Converts an RGB color to the values carried by the ray: the color
+    /// itself, or its spectrum at the ray wavelengths when rendering spectrally
+    pub fn sample_color(&self, color: &Color) -> Color {
+        match &self.wavelengths {
+            Some(wavelengths) => wavelengths.sample_color(color),
+            None => *color,
+        }
+    }
 }
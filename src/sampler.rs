@@ -0,0 +1,452 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// Source of the random numbers used to render a pixel sample. Every call
+/// consumes the next dimension of the sample, so the values are well
+/// distributed over the samples of a pixel for each dimension
+pub trait Sampler {
+    /// Starts the sample `index` of the pixel (x, y)
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32);
+
+    /// Uniform value in [0, 1)
+    fn get_1d(&mut self) -> f64;
+
+    /// Uniform point in [0, 1)²
+    fn get_2d(&mut self) -> (f64, f64);
+}
+
+/// Sampling strategy used by the camera. The values only depend on the seed,
+/// the pixel and the sample index, so renders are repeatable whatever the
+/// order the samples are taken in
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SamplerKind {
+    /// Independent uniform random values (white noise)
+    #[default]
+    Independent,
+    /// Jittered samples, one in each stratum of every dimension
+    Stratified,
+    /// Randomized Halton sequence
+    Halton,
+    /// Sobol sequence with Owen scrambling
+    Sobol,
+}
+
+impl SamplerKind {
+    pub fn create(&self, samples_per_pixel: u32, seed: u64) -> Box<dyn Sampler> {
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler::new(seed)),
+            SamplerKind::Stratified => Box::new(StratifiedSampler::new(samples_per_pixel, seed)),
+            SamplerKind::Halton => Box::new(HaltonSampler::new(seed)),
+            SamplerKind::Sobol => Box::new(SobolSampler::new(seed)),
+        }
+    }
+}
+
+/// Independent uniform values, each pixel sample getting its own random
+/// number generator seeded from the render seed
+pub struct IndependentSampler {
+    seed: u64,
+    rng: StdRng,
+}
+
+impl IndependentSampler {
+    pub fn new(seed: u64) -> Self {
+        IndependentSampler {
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        let pixel = ((y as u64) << 32) | x as u64;
+        self.rng = StdRng::seed_from_u64(hash(&[self.seed, pixel, index as u64]));
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        self.rng.gen::<f64>()
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.rng.gen::<f64>(), self.rng.gen::<f64>())
+    }
+}
+
+// Position of the current sample shared by the deterministic samplers
+struct SampleState {
+    seed: u64,
+    pixel: u64,
+    index: u32,
+    dimension: u32,
+}
+
+impl SampleState {
+    fn new(seed: u64) -> Self {
+        SampleState {
+            seed,
+            pixel: 0,
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn start(&mut self, x: u32, y: u32, index: u32) {
+        self.pixel = ((y as u64) << 32) | x as u64;
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    // Returns the dimension to use and moves on by `count` dimensions
+    fn next(&mut self, count: u32) -> u32 {
+        let dimension = self.dimension;
+        self.dimension += count;
+        dimension
+    }
+
+    // Random value specific to the seed, the pixel, the dimension and `extra`
+    fn hash(&self, dimension: u32, extra: u64) -> u64 {
+        hash(&[self.seed, self.pixel, dimension as u64, extra])
+    }
+}
+
+/// Stratifies every dimension over the samples of the pixel. Strata are
+/// shuffled per pixel and dimension so the dimensions are not correlated.
+/// Two dimensional samples use a grid as square as the sample count allows
+pub struct StratifiedSampler {
+    samples_per_pixel: u32,
+    grid: (u32, u32),
+    state: SampleState,
+}
+
+impl StratifiedSampler {
+    pub fn new(samples_per_pixel: u32, seed: u64) -> Self {
+        let samples_per_pixel = samples_per_pixel.max(1);
+        let columns = (1..=(samples_per_pixel as f64).sqrt() as u32)
+            .rev()
+            .find(|columns| samples_per_pixel.is_multiple_of(*columns))
+            .unwrap_or(1);
+        StratifiedSampler {
+            samples_per_pixel,
+            grid: (columns, samples_per_pixel / columns),
+            state: SampleState::new(seed),
+        }
+    }
+
+    fn stratum(&self, dimension: u32) -> u32 {
+        let seed = self.state.hash(dimension, 0);
+        permutation_element(
+            self.state.index % self.samples_per_pixel,
+            self.samples_per_pixel,
+            seed as u32,
+        )
+    }
+
+    fn jitter(&self, dimension: u32) -> f64 {
+        to_unit(self.state.hash(dimension, 1 + self.state.index as u64))
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.state.start(x, y, index);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let dimension = self.state.next(1);
+        let stratum = self.stratum(dimension);
+        (stratum as f64 + self.jitter(dimension)) / self.samples_per_pixel as f64
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let dimension = self.state.next(2);
+        let stratum = self.stratum(dimension);
+        let (columns, rows) = self.grid;
+        (
+            ((stratum % columns) as f64 + self.jitter(dimension)) / columns as f64,
+            ((stratum / columns) as f64 + self.jitter(dimension + 1)) / rows as f64,
+        )
+    }
+}
+
+// Bases of the Halton dimensions, later dimensions start over with another
+// random shift
+const PRIMES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+/// Halton sequence over the samples of a pixel, randomized per pixel and
+/// dimension with a random shift
+pub struct HaltonSampler {
+    state: SampleState,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> Self {
+        HaltonSampler {
+            state: SampleState::new(seed),
+        }
+    }
+
+    fn sample(&self, dimension: u32) -> f64 {
+        let base = PRIMES[dimension as usize % PRIMES.len()];
+        let shift = to_unit(self.state.hash(dimension, 0));
+        (radical_inverse(self.state.index, base) + shift).fract()
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.state.start(x, y, index);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let dimension = self.state.next(1);
+        self.sample(dimension)
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let dimension = self.state.next(2);
+        (self.sample(dimension), self.sample(dimension + 1))
+    }
+}
+
+/// Owen scrambled Sobol points. Each pair of dimensions uses the first two
+/// Sobol dimensions with an independently shuffled sample order (Burley 2020)
+pub struct SobolSampler {
+    state: SampleState,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> Self {
+        SobolSampler {
+            state: SampleState::new(seed),
+        }
+    }
+
+    fn sample(&mut self) -> (f64, f64) {
+        let dimension = self.state.next(2);
+        let seed = self.state.hash(dimension, 0);
+        let index = nested_uniform_scramble(self.state.index, seed as u32);
+        let (x, y) = sobol_2d(index);
+        (
+            nested_uniform_scramble(x, (seed >> 32) as u32) as f64 / 4294967296.,
+            nested_uniform_scramble(y, self.state.hash(dimension, 1) as u32) as f64 / 4294967296.,
+        )
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.state.start(x, y, index);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        self.sample().0
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        self.sample()
+    }
+}
+
+/// Mirrors the digits of `index` in `base` around the radix point
+pub fn radical_inverse(mut index: u32, base: u32) -> f64 {
+    let inverse_base = 1. / base as f64;
+    let mut factor = inverse_base;
+    let mut result = 0.;
+    while index > 0 {
+        result += (index % base) as f64 * factor;
+        index /= base;
+        factor *= inverse_base;
+    }
+    result
+}
+
+// First two dimensions of the Sobol sequence as 32 bit fractions
+fn sobol_2d(index: u32) -> (u32, u32) {
+    let mut y = 0;
+    let mut direction: u32 = 1 << 31;
+    let mut bits = index;
+    while bits != 0 {
+        if bits & 1 != 0 {
+            y ^= direction;
+        }
+        bits >>= 1;
+        direction ^= direction >> 1;
+    }
+    (index.reverse_bits(), y)
+}
+
+// Hash based Owen scrambling of a 32 bit fraction (Burley 2020)
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    let mut x = x.reverse_bits().wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x.reverse_bits()
+}
+
+// Element `i` of a random permutation of [0, n) chosen by `seed` (Kensler 2013)
+fn permutation_element(mut i: u32, n: u32, seed: u32) -> u32 {
+    let mut w = n.wrapping_sub(1);
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < n {
+            break;
+        }
+    }
+    (i.wrapping_add(seed)) % n
+}
+
+// Finalizer of the 64 bit MurmurHash3
+fn mix_bits(mut v: u64) -> u64 {
+    v ^= v >> 33;
+    v = v.wrapping_mul(0xff51afd7ed558ccd);
+    v ^= v >> 33;
+    v = v.wrapping_mul(0xc4ceb9fe1a85ec53);
+    v ^ (v >> 33)
+}
+
+/// Well mixed hash of a few values
+pub fn hash(values: &[u64]) -> u64 {
+    values.iter().fold(0x9e3779b97f4a7c15, |hash, value| {
+        mix_bits(hash ^ value.wrapping_mul(0xbf58476d1ce4e5b9))
+    })
+}
+
+/// Maps a hash to a uniform value in [0, 1)
+pub fn to_unit(hash: u64) -> f64 {
+    (hash >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod test {
+    use crate::sampler::*;
+
+    // Checks that every one of the `count` intervals of [0, 1) holds one value
+    fn assert_stratified(mut values: Vec<f64>) {
+        let count = values.len();
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        for (i, value) in values.iter().enumerate() {
+            assert!((i as f64 / count as f64..(i + 1) as f64 / count as f64).contains(value));
+        }
+    }
+
+    fn first_dimensions(sampler: &mut dyn Sampler, count: u32) -> Vec<(f64, f64, f64)> {
+        (0..count)
+            .map(|index| {
+                sampler.start_pixel_sample(3, 7, index);
+                let (u, v) = sampler.get_2d();
+                (u, v, sampler.get_1d())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_radical_inverse() {
+        assert_eq!(radical_inverse(1, 2), 0.5);
+        assert_eq!(radical_inverse(6, 2), 0.375);
+        assert!((radical_inverse(5, 3) - 7. / 9.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_permutation_is_bijective() {
+        for n in [1, 5, 16, 100] {
+            let mut seen = vec![false; n as usize];
+            for i in 0..n {
+                seen[permutation_element(i, n, 1234567) as usize] = true;
+            }
+            assert!(seen.iter().all(|&seen| seen));
+        }
+    }
+
+    #[test]
+    fn test_samplers_are_stratified() {
+        let count = 16;
+        let mut sampler = SamplerKind::Sobol.create(count, 0);
+        let samples = first_dimensions(sampler.as_mut(), count);
+        assert_stratified(samples.iter().map(|sample| sample.0).collect());
+        assert_stratified(samples.iter().map(|sample| sample.1).collect());
+        assert_stratified(samples.iter().map(|sample| sample.2).collect());
+
+        // Two dimensional samples are only stratified over the grid cells
+        let mut sampler = SamplerKind::Stratified.create(count, 0);
+        let samples = first_dimensions(sampler.as_mut(), count);
+        assert_stratified(samples.iter().map(|sample| sample.2).collect());
+        let mut cells = [0; 16];
+        for (u, v, _) in samples {
+            cells[(u * 4.) as usize + 4 * (v * 4.) as usize] += 1;
+        }
+        assert_eq!(cells, [1; 16]);
+    }
+
+    #[test]
+    fn test_stratified_grid_covers_cells() {
+        let mut sampler = StratifiedSampler::new(6, 0);
+        assert_eq!(sampler.grid, (2, 3));
+        let mut cells = [false; 6];
+        for index in 0..6 {
+            sampler.start_pixel_sample(0, 0, index);
+            let (u, v) = sampler.get_2d();
+            cells[(u * 2.) as usize + 2 * (v * 3.) as usize] = true;
+        }
+        assert!(cells.iter().all(|&covered| covered));
+    }
+
+    #[test]
+    fn test_samples_are_in_range_and_repeatable() {
+        for kind in [
+            SamplerKind::Independent,
+            SamplerKind::Stratified,
+            SamplerKind::Halton,
+            SamplerKind::Sobol,
+        ] {
+            let mut sampler = kind.create(8, 0);
+            for (u, v, w) in first_dimensions(sampler.as_mut(), 64) {
+                assert!((0. ..1.).contains(&u) && (0. ..1.).contains(&v) && (0. ..1.).contains(&w));
+            }
+        }
+    }
+
+    #[test]
+    fn test_seed_determines_samples() {
+        for kind in [
+            SamplerKind::Independent,
+            SamplerKind::Stratified,
+            SamplerKind::Halton,
+            SamplerKind::Sobol,
+        ] {
+            let mut sampler = kind.create(8, 42);
+            let mut same_seed = kind.create(8, 42);
+            let mut other_seed = kind.create(8, 43);
+            let samples = first_dimensions(sampler.as_mut(), 8);
+            assert_eq!(samples, first_dimensions(same_seed.as_mut(), 8));
+            assert_ne!(samples, first_dimensions(other_seed.as_mut(), 8));
+        }
+    }
+}
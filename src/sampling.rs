@@ -0,0 +1,183 @@
+/// Piecewise-constant distribution over [0, 1) built from a tabulated function.
+/// Used to importance sample images such as environment maps
+pub struct Distribution1D {
+    func: Vec<f64>,
+    cdf: Vec<f64>,
+    integral: f64,
+}
+
+impl Distribution1D {
+    /// Panics when `func` is empty
+    pub fn new(func: &[f64]) -> Self {
+        assert!(!func.is_empty(), "A distribution needs at least one value");
+        let n = func.len();
+        let mut cdf = vec![0.; n + 1];
+        for i in 1..=n {
+            cdf[i] = cdf[i - 1] + func[i - 1].abs() / n as f64;
+        }
+
+        let integral = cdf[n];
+        if integral == 0. {
+            // Nothing to importance sample, fall back to the uniform distribution
+            for (i, value) in cdf.iter_mut().enumerate() {
+                *value = i as f64 / n as f64;
+            }
+        } else {
+            for value in cdf.iter_mut() {
+                *value /= integral;
+            }
+        }
+
+        Distribution1D {
+            func: func.iter().map(|f| f.abs()).collect(),
+            cdf,
+            integral,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.func.len()
+    }
+
+    pub fn integral(&self) -> f64 {
+        self.integral
+    }
+
+    /// Maps the uniform sample `u` to a point in [0, 1) distributed according
+    /// to the function. Returns the point, its density and the bucket index
+    pub fn sample_continuous(&self, u: f64) -> (f64, f64, usize) {
+        let n = self.count();
+        let offset = self
+            .cdf
+            .partition_point(|&value| value <= u)
+            .saturating_sub(1)
+            .min(n - 1);
+
+        let mut du = u - self.cdf[offset];
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        if width > 0. {
+            du /= width;
+        }
+
+        (
+            (offset as f64 + du) / n as f64,
+            self.bucket_pdf(offset),
+            offset,
+        )
+    }
+
+    pub fn pdf(&self, x: f64) -> f64 {
+        let n = self.count();
+        let offset = ((x * n as f64) as usize).min(n - 1);
+        self.bucket_pdf(offset)
+    }
+
+    fn bucket_pdf(&self, offset: usize) -> f64 {
+        if self.integral > 0. {
+            self.func[offset] / self.integral
+        } else {
+            1.
+        }
+    }
+}
+
+/// Piecewise-constant distribution over [0, 1)^2. `func` is stored row by row,
+/// the first coordinate selects the column and the second one the row
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    /// Panics when the size is zero or `func` holds less than `width *
+    /// height` values
+    pub fn new(func: &[f64], width: usize, height: usize) -> Self {
+        assert!(
+            width > 0 && height > 0 && func.len() >= width * height,
+            "Invalid distribution size"
+        );
+        let conditional: Vec<Distribution1D> = func
+            .chunks(width)
+            .take(height)
+            .map(Distribution1D::new)
+            .collect();
+        let marginal_func: Vec<f64> = conditional.iter().map(|row| row.integral()).collect();
+
+        Distribution2D {
+            conditional,
+            marginal: Distribution1D::new(&marginal_func),
+        }
+    }
+
+    /// Returns the sampled point and its density
+    pub fn sample_continuous(&self, u0: f64, u1: f64) -> ((f64, f64), f64) {
+        let (v, pdf_v, row) = self.marginal.sample_continuous(u1);
+        let (u, pdf_u, _) = self.conditional[row].sample_continuous(u0);
+        ((u, v), pdf_u * pdf_v)
+    }
+
+    pub fn pdf(&self, u: f64, v: f64) -> f64 {
+        let rows = self.marginal.count();
+        let row = ((v * rows as f64) as usize).min(rows - 1);
+        self.conditional[row].pdf(u) * self.marginal.pdf(v)
+    }
+}
+
+/// Multiple importance sampling weight for a sample drawn from the strategy
+/// with density `pdf` when the other strategy has density `other_pdf`
+pub fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let pdf2 = pdf * pdf;
+    let other2 = other_pdf * other_pdf;
+    if pdf2 + other2 == 0. {
+        0.
+    } else {
+        pdf2 / (pdf2 + other2)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::sampling::{power_heuristic, Distribution1D, Distribution2D};
+
+    #[test]
+    fn test_distribution_1d_follows_function() {
+        let distribution = Distribution1D::new(&[1., 3.]);
+        assert_eq!(distribution.integral(), 2.);
+
+        let (x, pdf, offset) = distribution.sample_continuous(0.125);
+        assert_eq!(offset, 0);
+        assert_eq!(x, 0.25);
+        assert_eq!(pdf, 0.5);
+
+        let (x, pdf, offset) = distribution.sample_continuous(0.625);
+        assert_eq!(offset, 1);
+        assert_eq!(x, 0.75);
+        assert_eq!(pdf, 1.5);
+        assert_eq!(distribution.pdf(0.75), 1.5);
+    }
+
+    #[test]
+    fn test_distribution_1d_zero_function_is_uniform() {
+        let distribution = Distribution1D::new(&[0., 0., 0., 0.]);
+        let (x, pdf, offset) = distribution.sample_continuous(0.6);
+        assert_eq!(offset, 2);
+        assert!((x - 0.6).abs() < 1e-12);
+        assert_eq!(pdf, 1.);
+    }
+
+    #[test]
+    fn test_distribution_2d_picks_bright_cell() {
+        let distribution = Distribution2D::new(&[0., 0., 0., 1.], 2, 2);
+        let ((u, v), pdf) = distribution.sample_continuous(0.5, 0.5);
+        assert!(u >= 0.5 && v >= 0.5);
+        assert_eq!(pdf, 4.);
+        assert_eq!(distribution.pdf(0.25, 0.25), 0.);
+    }
+
+    #[test]
+    fn test_power_heuristic() {
+        assert_eq!(power_heuristic(1., 0.), 1.);
+        assert_eq!(power_heuristic(1., 1.), 0.5);
+        assert_eq!(power_heuristic(0., 0.), 0.);
+    }
+}
@@ -0,0 +1,587 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::sync::Arc;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::animation::{Animated, Interpolation, Keyframe, Track, Transform};
+use crate::camera::Camera;
+use crate::environment::ConstantEnvironment;
+use crate::geometry::{HittableList, Sphere, Triangle};
+use crate::lens::{Aperture, LensEffects};
+use crate::material::{Dielectric, Lambertian, Material, Metal};
+use crate::vec3::*;
+
+/// Names of the scenes given by `Scene::builtin`
+pub const BUILTIN_SCENES: [&str; 2] = ["spheres", "cover"];
+
+/// Objects of a scene and the viewpoint they are looked at from
+pub struct Scene {
+    pub world: HittableList<'static>,
+    pub look_from: Track<Point3>,
+    pub look_at: Track<Point3>,
+    pub view_up: Vec3,
+    /// Vertical field of view in degrees
+    pub vertical_fov: Track<f64>,
+    pub aperture: Option<Aperture>,
+    /// Uniform color seen by rays leaving the scene, the default sky
+    /// gradient without it
+    pub background: Option<Color>,
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Scene {
+            world: HittableList::new(),
+            look_from: Track::constant(Point3::default()),
+            look_at: Track::constant(Point3 {
+                x: 0.,
+                y: 0.,
+                z: -1.,
+            }),
+            view_up: Vec3 {
+                x: 0.,
+                y: 1.,
+                z: 0.,
+            },
+            vertical_fov: Track::constant(90.),
+            aperture: None,
+            background: None,
+        }
+    }
+}
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+// Object of a scene file with the tracks of its keys, if any
+struct SceneObject {
+    shape: Shape,
+    translation: Option<Track<Vec3>>,
+    rotation: Option<Track<Vec3>>,
+    scale: Option<Track<f64>>,
+}
+
+enum Shape {
+    Sphere(Sphere),
+    Triangle(Triangle),
+}
+
+impl SceneObject {
+    fn new(shape: Shape) -> Self {
+        SceneObject {
+            shape,
+            translation: None,
+            rotation: None,
+            scale: None,
+        }
+    }
+
+    fn add_to(self, world: &mut HittableList<'static>) {
+        if self.translation.is_none() && self.rotation.is_none() && self.scale.is_none() {
+            match self.shape {
+                Shape::Sphere(sphere) => world.add(sphere),
+                Shape::Triangle(triangle) => world.add(triangle),
+            }
+            return;
+        }
+        let default = Transform::default();
+        let transform = Transform {
+            translation: self.translation.unwrap_or(default.translation),
+            rotation: self.rotation.unwrap_or(default.rotation),
+            scale: self.scale.unwrap_or(default.scale),
+        };
+        match self.shape {
+            Shape::Sphere(object) => world.add(Animated { object, transform }),
+            Shape::Triangle(object) => world.add(Animated { object, transform }),
+        }
+    }
+}
+
+// Adds a key to a track, created with the first key
+fn add_key<T: Keyframe>(
+    track: &mut Option<Track<T>>,
+    interpolation: Interpolation,
+    time: f64,
+    value: T,
+) {
+    match track {
+        Some(track) => {
+            track.add_key(time, value);
+        }
+        None => *track = Some(Track::new(interpolation, time, value)),
+    }
+}
+
+impl Scene {
+    pub fn load(filename: &str) -> Result<Self> {
+        Self::parse(&fs::read_to_string(filename)?)
+    }
+
+    /// Reads a scene description, one statement per line. Lines starting
+    /// with `#` are comments. Materials are named before the objects using
+    /// them:
+    ///
+    /// ```text
+    /// look_from X Y Z
+    /// look_at X Y Z
+    /// view_up X Y Z
+    /// vertical_fov DEGREES
+    /// aperture RADIUS FOCUS_DISTANCE
+    /// background R G B
+    /// material NAME lambertian R G B
+    /// material NAME metal R G B FUZZ
+    /// material NAME dielectric IOR
+    /// sphere X Y Z RADIUS MATERIAL
+    /// triangle X0 Y0 Z0 X1 Y1 Z1 X2 Y2 Z2 MATERIAL
+    /// ```
+    ///
+    /// Keys at times in seconds animate the camera, or the object defined
+    /// last, which is scaled and rotated by angles in degrees around the
+    /// origin and then translated. Keys take the interpolation set before
+    /// the first key of their track, linear by default:
+    ///
+    /// ```text
+    /// interpolation linear|catmull_rom
+    /// key TIME look_from X Y Z
+    /// key TIME look_at X Y Z
+    /// key TIME vertical_fov DEGREES
+    /// key TIME translate X Y Z
+    /// key TIME rotate X Y Z
+    /// key TIME scale FACTOR
+    /// ```
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut scene = Scene::default();
+        let mut materials: HashMap<&str, Arc<dyn Material>> = HashMap::new();
+        let mut objects: Vec<SceneObject> = Vec::new();
+        let mut interpolation = Interpolation::Linear;
+        let mut look_from_keys = None;
+        let mut look_at_keys = None;
+        let mut vertical_fov_keys = None;
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: &str| invalid_data(&format!("Line {}: {message}", index + 1));
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let (keyword, arguments) = (words[0], &words[1..]);
+            let numbers = |values: &[&str], count: usize| {
+                values
+                    .iter()
+                    .map(|value| value.parse::<f64>())
+                    .collect::<std::result::Result<Vec<f64>, _>>()
+                    .ok()
+                    .filter(|numbers| numbers.len() == count)
+                    .ok_or_else(|| error(&format!("{keyword} needs {count} numbers")))
+            };
+            // Objects end with the name of their material
+            let object = |count: usize| {
+                let (name, values) = arguments
+                    .split_last()
+                    .ok_or_else(|| error(&format!("{keyword} needs a material")))?;
+                let material = materials
+                    .get(name)
+                    .cloned()
+                    .ok_or_else(|| error(&format!("Unknown material {name}")))?;
+                Ok::<_, Error>((numbers(values, count)?, material))
+            };
+
+            match keyword {
+                "look_from" => scene.look_from = vector(&numbers(arguments, 3)?).into(),
+                "look_at" => scene.look_at = vector(&numbers(arguments, 3)?).into(),
+                "view_up" => scene.view_up = vector(&numbers(arguments, 3)?),
+                "vertical_fov" => scene.vertical_fov = numbers(arguments, 1)?[0].into(),
+                "aperture" => {
+                    let values = numbers(arguments, 2)?;
+                    scene.aperture = Some(Aperture {
+                        radius: values[0],
+                        focus_distance: values[1],
+                        blades: 0,
+                        rotation: 0.,
+                    });
+                }
+                "background" => scene.background = Some(vector(&numbers(arguments, 3)?)),
+                "material" => {
+                    let [name, kind, values @ ..] = arguments else {
+                        return Err(error("material needs a name and a type"));
+                    };
+                    let material: Arc<dyn Material> = match *kind {
+                        "lambertian" => Arc::new(Lambertian {
+                            albedo: vector(&numbers(values, 3)?),
+                        }),
+                        "metal" => {
+                            let values = numbers(values, 4)?;
+                            Arc::new(Metal::new(vector(&values), values[3]))
+                        }
+                        "dielectric" => Arc::new(Dielectric::new(numbers(values, 1)?[0], 0.)),
+                        _ => return Err(error(&format!("Unknown material type {kind}"))),
+                    };
+                    materials.insert(name, material);
+                }
+                "sphere" => {
+                    let (values, material) = object(4)?;
+                    objects.push(SceneObject::new(Shape::Sphere(Sphere {
+                        center: vector(&values),
+                        radius: values[3],
+                        material,
+                    })));
+                }
+                "triangle" => {
+                    let (values, material) = object(9)?;
+                    let vertices = [
+                        vector(&values[0..3]),
+                        vector(&values[3..6]),
+                        vector(&values[6..9]),
+                    ];
+                    objects.push(SceneObject::new(Shape::Triangle(Triangle::new(
+                        vertices, material,
+                    ))));
+                }
+                "interpolation" => {
+                    interpolation = match arguments {
+                        ["linear"] => Interpolation::Linear,
+                        ["catmull_rom"] => Interpolation::CatmullRom,
+                        _ => return Err(error("interpolation is linear or catmull_rom")),
+                    };
+                }
+                "key" => {
+                    let [time, property, values @ ..] = arguments else {
+                        return Err(error("key needs a time and a property"));
+                    };
+                    let time = numbers(&[time], 1)?[0];
+                    let last_object = || error(&format!("No object to {property}"));
+                    match *property {
+                        "look_from" => {
+                            let value = vector(&numbers(values, 3)?);
+                            add_key(&mut look_from_keys, interpolation, time, value);
+                        }
+                        "look_at" => {
+                            let value = vector(&numbers(values, 3)?);
+                            add_key(&mut look_at_keys, interpolation, time, value);
+                        }
+                        "vertical_fov" => {
+                            let value = numbers(values, 1)?[0];
+                            add_key(&mut vertical_fov_keys, interpolation, time, value);
+                        }
+                        "translate" | "rotate" => {
+                            let value = vector(&numbers(values, 3)?);
+                            let object = objects.last_mut().ok_or_else(last_object)?;
+                            let track = if *property == "translate" {
+                                &mut object.translation
+                            } else {
+                                &mut object.rotation
+                            };
+                            add_key(track, interpolation, time, value);
+                        }
+                        "scale" => {
+                            let value = numbers(values, 1)?[0];
+                            // Rays are divided by the scale into the object
+                            if !(value > 0. && value.is_finite()) {
+                                return Err(error("scale must be more than 0"));
+                            }
+                            let object = objects.last_mut().ok_or_else(last_object)?;
+                            add_key(&mut object.scale, interpolation, time, value);
+                        }
+                        _ => return Err(error(&format!("Unknown animated property {property}"))),
+                    }
+                }
+                _ => return Err(error(&format!("Unknown statement {keyword}"))),
+            }
+        }
+
+        // Keys replace the fixed values
+        if let Some(track) = look_from_keys {
+            scene.look_from = track;
+        }
+        if let Some(track) = look_at_keys {
+            scene.look_at = track;
+        }
+        if let Some(track) = vertical_fov_keys {
+            scene.vertical_fov = track;
+        }
+        for object in objects {
+            object.add_to(&mut scene.world);
+        }
+        Ok(scene)
+    }
+
+    /// One of the scenes of `BUILTIN_SCENES`
+    pub fn builtin(name: &str) -> Option<Self> {
+        match name {
+            "spheres" => Some(Self::spheres()),
+            "cover" => Some(Self::cover()),
+            _ => None,
+        }
+    }
+
+    /// Places the camera at the viewpoint of the scene
+    pub fn set_up_camera(&self, camera: &mut Camera) {
+        camera.set_look_from(self.look_from.clone());
+        camera.set_look_at(self.look_at.clone());
+        camera.set_view_up(self.view_up);
+        camera.set_vertical_fov(self.vertical_fov.clone());
+        if let Some(aperture) = self.aperture {
+            camera.set_lens_effects(LensEffects {
+                aperture: Some(aperture),
+                ..Default::default()
+            });
+        }
+        if let Some(color) = self.background {
+            camera.set_environment(ConstantEnvironment { color });
+        }
+    }
+
+    // Diffuse, polished metal and brushed metal spheres on a yellow ground
+    fn spheres() -> Self {
+        let mut scene = Scene::default();
+        let mut add = |x, y, radius, material: Arc<dyn Material>| {
+            scene.world.add(Sphere {
+                center: Point3 { x, y, z: -1. },
+                radius,
+                material,
+            });
+        };
+        let albedo = rgb(0.1, 0.2, 0.5);
+        add(0., 0., 0.5, Arc::new(Lambertian { albedo }));
+        let albedo = rgb(0.8, 0.8, 0.);
+        add(0., -100.5, 100., Arc::new(Lambertian { albedo }));
+        add(-1., 0., 0.5, Arc::new(Metal::new(rgb(0.8, 0.8, 0.8), 0.3)));
+        add(1., 0., 0.5, Arc::new(Metal::new(rgb(0.8, 0.6, 0.2), 1.0)));
+        scene
+    }
+
+    // Field of small random spheres around three large ones, the cover of
+    // "Ray Tracing in One Weekend"
+    fn cover() -> Self {
+        let mut scene = Scene {
+            look_from: Track::constant(Point3 {
+                x: 13.,
+                y: 2.,
+                z: 3.,
+            }),
+            look_at: Track::constant(Point3::default()),
+            vertical_fov: Track::constant(20.),
+            aperture: Some(Aperture {
+                radius: 10. * 0.3f64.to_radians().tan(),
+                focus_distance: 10.,
+                blades: 0,
+                rotation: 0.,
+            }),
+            ..Default::default()
+        };
+        let glass: Arc<dyn Material> = Arc::new(Dielectric::new(1.5, 0.));
+        scene.world.add(Sphere {
+            center: Point3 {
+                x: 0.,
+                y: -1000.,
+                z: 0.,
+            },
+            radius: 1000.,
+            material: Arc::new(Lambertian {
+                albedo: rgb(0.5, 0.5, 0.5),
+            }),
+        });
+
+        // Always the same spheres, whatever the seed of the render
+        let mut rng = StdRng::seed_from_u64(0);
+        for a in -11..11 {
+            for b in -11..11 {
+                let center = Point3 {
+                    x: a as f64 + 0.9 * rng.gen::<f64>(),
+                    y: 0.2,
+                    z: b as f64 + 0.9 * rng.gen::<f64>(),
+                };
+                let choice = rng.gen::<f64>();
+                if (center
+                    - Point3 {
+                        x: 4.,
+                        y: 0.2,
+                        z: 0.,
+                    })
+                .len()
+                    <= 0.9
+                {
+                    continue;
+                }
+                let material: Arc<dyn Material> = if choice < 0.8 {
+                    let mut random = || rng.gen::<f64>() * rng.gen::<f64>();
+                    Arc::new(Lambertian {
+                        albedo: rgb(random(), random(), random()),
+                    })
+                } else if choice < 0.95 {
+                    let mut random = || rng.gen_range(0.5..1.);
+                    let albedo = rgb(random(), random(), random());
+                    Arc::new(Metal::new(albedo, rng.gen_range(0. ..0.5)))
+                } else {
+                    glass.clone()
+                };
+                scene.world.add(Sphere {
+                    center,
+                    radius: 0.2,
+                    material,
+                });
+            }
+        }
+
+        let large_spheres = [
+            (0., glass),
+            (
+                -4.,
+                Arc::new(Lambertian {
+                    albedo: rgb(0.4, 0.2, 0.1),
+                }),
+            ),
+            (4., Arc::new(Metal::new(rgb(0.7, 0.6, 0.5), 0.))),
+        ];
+        for (x, material) in large_spheres {
+            scene.world.add(Sphere {
+                center: Point3 { x, y: 1., z: 0. },
+                radius: 1.,
+                material,
+            });
+        }
+        scene
+    }
+}
+
+fn vector(values: &[f64]) -> Vec3 {
+    Vec3 {
+        x: values[0],
+        y: values[1],
+        z: values[2],
+    }
+}
+
+fn rgb(r: f64, g: f64, b: f64) -> Color {
+    Color { x: r, y: g, z: b }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::interval::Interval;
+    use crate::ray::Ray;
+    use crate::scene::*;
+
+    #[test]
+    fn test_parse_scene() {
+        let scene = Scene::parse(
+            "# A glass ball
+            look_from 0 1 5
+            vertical_fov 40
+            material glass dielectric 1.5
+            material floor lambertian 0.5 0.5 0.5
+            sphere 0 1 0 1 glass
+            triangle -5 0 -5  5 0 -5  0 0 5 floor",
+        )
+        .unwrap();
+        assert_eq!(scene.look_from.value(0.).z, 5.);
+        assert_eq!(scene.vertical_fov.value(0.), 40.);
+
+        let mut ray = Ray::new(
+            scene.look_from.value(0.),
+            Vec3 {
+                x: 0.,
+                y: 0.,
+                z: -1.,
+            },
+        );
+        let interval = Interval {
+            min: 0.001,
+            max: f64::INFINITY,
+        };
+        let record = scene.world.hit(&mut ray, &interval).unwrap();
+        assert!((record.t - 4.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_parse_keys() {
+        let scene = Scene::parse(
+            "look_from 0 0 5
+            key 0 vertical_fov 40
+            key 2 vertical_fov 60
+            interpolation catmull_rom
+            key 0 look_at 0 0 0
+            key 2 look_at 0 2 0
+            material red lambertian 1 0 0
+            sphere 0 0 0 1 red
+            key 0 translate 0 0 0
+            key 2 translate 4 0 0",
+        )
+        .unwrap();
+        assert_eq!(scene.look_from.value(1.).z, 5.);
+        assert_eq!(scene.vertical_fov.value(1.), 50.);
+        assert_eq!(scene.look_at.value(2.).y, 2.);
+
+        let interval = Interval {
+            min: 0.001,
+            max: f64::INFINITY,
+        };
+        let ray_at = |time| {
+            let mut ray = Ray::new(
+                Point3 {
+                    x: 4.,
+                    y: 0.,
+                    z: 5.,
+                },
+                Vec3 {
+                    x: 0.,
+                    y: 0.,
+                    z: -1.,
+                },
+            );
+            ray.time = time;
+            ray
+        };
+        assert!(scene.world.hit(&mut ray_at(0.), &interval).is_none());
+        assert!(scene.world.hit(&mut ray_at(2.), &interval).is_some());
+    }
+
+    #[test]
+    fn test_scene_errors_name_the_line() {
+        let error = |text| Scene::parse(text).err().unwrap().to_string();
+        assert_eq!(
+            error("look_from 0 1\n"),
+            "Line 1: look_from needs 3 numbers"
+        );
+        assert_eq!(
+            error("# Comment\n\nsphere 0 0 0 1 glass"),
+            "Line 3: Unknown material glass"
+        );
+        assert_eq!(
+            error("material red plastic 1 0 0"),
+            "Line 1: Unknown material type plastic"
+        );
+        assert_eq!(error("cube 0 0 0"), "Line 1: Unknown statement cube");
+        assert_eq!(error("key 0 scale 2"), "Line 1: No object to scale");
+        assert_eq!(
+            error("material red lambertian 1 0 0\nsphere 0 0 0 1 red\nkey 0 scale 0"),
+            "Line 3: scale must be more than 0"
+        );
+        assert_eq!(error("key 0 scale -1"), "Line 1: scale must be more than 0");
+        assert_eq!(
+            error("key 0 scale inf"),
+            "Line 1: scale must be more than 0"
+        );
+        assert_eq!(
+            error("key 0 color 1 0 0"),
+            "Line 1: Unknown animated property color"
+        );
+        assert_eq!(
+            error("interpolation cubic"),
+            "Line 1: interpolation is linear or catmull_rom"
+        );
+    }
+
+    #[test]
+    fn test_builtin_scenes() {
+        for name in BUILTIN_SCENES {
+            assert!(Scene::builtin(name).is_some());
+        }
+        assert!(Scene::builtin("teapot").is_none());
+    }
+}
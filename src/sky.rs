@@ -0,0 +1,277 @@
+use std::f64::consts::PI;
+
+use crate::environment::Environment;
+use crate::onb::Onb;
+use crate::sampler::Sampler;
+use crate::vec3::*;
+
+// Angular radius of the sun disk seen from the earth
+const SUN_ANGULAR_RADIUS: f64 = 0.00465;
+
+// Luminance of the sun before atmospheric extinction in kcd/m^2, the same unit
+// the sky model produces
+const SUN_LUMINANCE: f64 = 1.6e6;
+
+/// Coefficients of the Perez sky luminance distribution
+struct Perez {
+    a: f64,
+    b: f64,
+    c: f64,
+    d: f64,
+    e: f64,
+}
+
+impl Perez {
+    // `theta` is the view zenith angle and `gamma` the angle to the sun
+    fn eval(&self, theta: f64, gamma: f64) -> f64 {
+        (1. + self.a * (self.b / theta.cos()).exp())
+            * (1. + self.c * (self.d * gamma).exp() + self.e * gamma.cos().powi(2))
+    }
+}
+
+/// Analytic daylight model by Preetham, Shirley and Smits (1999) with a
+/// directly sampled sun disk
+pub struct PreethamSky {
+    sun_direction: Vec3,
+    sun_radiance: Color,
+    cos_sun_radius: f64,
+    // Perez distributions and zenith values for luminance and both chromaticities
+    perez: [Perez; 3],
+    zenith: [f64; 3],
+    // Perez normalization for each channel, the distribution value at the zenith
+    normalization: [f64; 3],
+    intensity: f64,
+}
+
+impl PreethamSky {
+    /// `sun_elevation` is measured in degrees above the horizon and
+    /// `sun_azimuth` in degrees from -Z towards +X. `turbidity` describes the
+    /// haziness of the atmosphere from 2 (clear) to 10 (hazy). The model works
+    /// in kcd/m^2, `intensity` scales it to the scene units
+    pub fn new(sun_elevation: f64, sun_azimuth: f64, turbidity: f64, intensity: f64) -> Self {
+        let t = turbidity.clamp(1.7, 10.);
+        let elevation = sun_elevation.to_radians();
+        let azimuth = sun_azimuth.to_radians();
+        let sun_direction = Vec3 {
+            x: elevation.cos() * azimuth.sin(),
+            y: elevation.sin(),
+            z: -elevation.cos() * azimuth.cos(),
+        };
+
+        // The model is only defined for the sun above the horizon
+        let theta_s = (PI / 2. - elevation).clamp(0., PI / 2. - 0.001);
+
+        let perez = [
+            Perez {
+                a: 0.1787 * t - 1.4630,
+                b: -0.3554 * t + 0.4275,
+                c: -0.0227 * t + 5.3251,
+                d: 0.1206 * t - 2.5771,
+                e: -0.0670 * t + 0.3703,
+            },
+            Perez {
+                a: -0.0193 * t - 0.2592,
+                b: -0.0665 * t + 0.0008,
+                c: -0.0004 * t + 0.2125,
+                d: -0.0641 * t - 0.8989,
+                e: -0.0033 * t + 0.0452,
+            },
+            Perez {
+                a: -0.0167 * t - 0.2608,
+                b: -0.0950 * t + 0.0092,
+                c: -0.0079 * t + 0.2102,
+                d: -0.0441 * t - 1.6537,
+                e: -0.0109 * t + 0.0529,
+            },
+        ];
+
+        let chi = (4. / 9. - t / 120.) * (PI - 2. * theta_s);
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+
+        let theta2 = theta_s * theta_s;
+        let theta3 = theta2 * theta_s;
+        let zenith_x = t * t * (0.00166 * theta3 - 0.00375 * theta2 + 0.00209 * theta_s)
+            + t * (-0.02903 * theta3 + 0.06377 * theta2 - 0.03202 * theta_s + 0.00394)
+            + (0.11693 * theta3 - 0.21196 * theta2 + 0.06052 * theta_s + 0.25886);
+        let zenith_y = t * t * (0.00275 * theta3 - 0.00610 * theta2 + 0.00317 * theta_s)
+            + t * (-0.04214 * theta3 + 0.08970 * theta2 - 0.04153 * theta_s + 0.00516)
+            + (0.15346 * theta3 - 0.26756 * theta2 + 0.06670 * theta_s + 0.26688);
+
+        let normalization = [
+            perez[0].eval(0., theta_s),
+            perez[1].eval(0., theta_s),
+            perez[2].eval(0., theta_s),
+        ];
+
+        // A sun below the horizon only leaves the twilight of the sky
+        let sun_radiance = if sun_elevation < 0. {
+            Color::default()
+        } else {
+            Self::sun_transmittance(theta_s, t) * SUN_LUMINANCE
+        };
+
+        PreethamSky {
+            sun_direction,
+            sun_radiance,
+            cos_sun_radius: SUN_ANGULAR_RADIUS.cos(),
+            perez,
+            zenith: [zenith_luminance.max(0.), zenith_x, zenith_y],
+            normalization,
+            intensity,
+        }
+    }
+
+    pub fn sun_direction(&self) -> Vec3 {
+        self.sun_direction
+    }
+
+    fn sun_below_horizon(&self) -> bool {
+        self.sun_direction.y < 0.
+    }
+
+    // Rayleigh and aerosol extinction of sunlight evaluated at representative
+    // red, green and blue wavelengths (from the appendix of the paper)
+    fn sun_transmittance(theta_s: f64, turbidity: f64) -> Color {
+        let relative_optical_mass =
+            1. / (theta_s.cos() + 0.15 * (93.885 - theta_s.to_degrees()).powf(-1.253));
+        let beta = 0.04608 * turbidity - 0.04586;
+        let transmittance = |lambda: f64| {
+            let rayleigh = (-0.008735 * lambda.powf(-4.08) * relative_optical_mass).exp();
+            let aerosol = (-beta * lambda.powf(-1.3) * relative_optical_mass).exp();
+            rayleigh * aerosol
+        };
+        Color {
+            x: transmittance(0.680),
+            y: transmittance(0.550),
+            z: transmittance(0.440),
+        }
+    }
+
+    fn sky_radiance(&self, direction: &Vec3) -> Color {
+        // Below the horizon we keep showing the horizon color
+        let theta = direction.y.clamp(0.001, 1.).acos();
+        let gamma = direction.dot(&self.sun_direction).clamp(-1., 1.).acos();
+
+        let value = |channel: usize| {
+            self.zenith[channel] * self.perez[channel].eval(theta, gamma)
+                / self.normalization[channel]
+        };
+        let luminance = value(0);
+        let x = value(1);
+        let y = value(2);
+        if y <= 0. {
+            return Color::default();
+        }
+
+        Vec3 {
+            x: x / y * luminance,
+            y: luminance,
+            z: (1. - x - y) / y * luminance,
+        }
+        .xyz_to_rgb()
+    }
+}
+
+impl Environment for PreethamSky {
+    fn color(&self, direction: &Vec3) -> Color {
+        let unit_direction = direction.unit_vector();
+        let mut radiance = self.sky_radiance(&unit_direction);
+        if unit_direction.dot(&self.sun_direction) >= self.cos_sun_radius {
+            radiance += self.sun_radiance;
+        }
+        self.intensity * radiance
+    }
+
+    // The sky is smooth enough for BSDF sampling, only the sun disk is sampled
+    // directly as it is the main source of noise
+    fn sample(&self, sampler: &mut dyn Sampler) -> Option<(Vec3, Color, f64)> {
+        let (u, v) = sampler.get_2d();
+        if self.sun_below_horizon() {
+            return None;
+        }
+        let cos_theta = 1. - u * (1. - self.cos_sun_radius);
+        let sin_theta = (1. - cos_theta * cos_theta).sqrt();
+        let phi = 2. * PI * v;
+        let direction = Onb::new(&self.sun_direction).local(&Vec3 {
+            x: phi.cos() * sin_theta,
+            y: phi.sin() * sin_theta,
+            z: cos_theta,
+        });
+
+        Some((direction, self.color(&direction), self.pdf(&direction)))
+    }
+
+    fn pdf(&self, direction: &Vec3) -> f64 {
+        if !self.sun_below_horizon()
+            && direction.unit_vector().dot(&self.sun_direction) >= self.cos_sun_radius
+        {
+            1. / (2. * PI * (1. - self.cos_sun_radius))
+        } else {
+            0.
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::environment::Environment;
+    use crate::sampler::IndependentSampler;
+    use crate::sky::PreethamSky;
+    use crate::vec3::Vec3;
+
+    #[test]
+    fn test_sun_direction() {
+        let sky = PreethamSky::new(90., 0., 3., 1.);
+        assert!((sky.sun_direction().y - 1.).abs() < 1e-12);
+
+        let sky = PreethamSky::new(0., 90., 3., 1.);
+        assert!((sky.sun_direction().x - 1.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_sky_is_brighter_near_the_sun() {
+        let sky = PreethamSky::new(30., 0., 3., 1.);
+        let towards_sun = Vec3 {
+            x: 0.,
+            y: 0.6,
+            z: -0.8,
+        };
+        let away_from_sun = Vec3 {
+            x: 0.,
+            y: 0.6,
+            z: 0.8,
+        };
+        let near = sky.sky_radiance(&towards_sun.unit_vector());
+        let far = sky.sky_radiance(&away_from_sun.unit_vector());
+        assert!(near.luminance() > far.luminance());
+        assert!(far.x > 0. && far.y > 0. && far.z > 0.);
+        // A clear sky away from the sun is blue
+        assert!(far.z > far.x);
+    }
+
+    #[test]
+    fn test_sun_samples_hit_the_disk() {
+        let mut sampler = IndependentSampler::new(0);
+        let sky = PreethamSky::new(45., 20., 2.5, 1.);
+        for _ in 0..16 {
+            let (direction, radiance, pdf) = sky.sample(&mut sampler).unwrap();
+            assert!(pdf > 0.);
+            assert_eq!(sky.pdf(&direction), pdf);
+            assert!(radiance.luminance() > 1e4);
+        }
+    }
+
+    #[test]
+    fn test_sun_below_horizon_is_dark() {
+        let mut sampler = IndependentSampler::new(0);
+        let sky = PreethamSky::new(-10., 0., 3., 1.);
+        assert!(sky.sample(&mut sampler).is_none());
+        let sun_direction = sky.sun_direction();
+        assert_eq!(sky.pdf(&sun_direction), 0.);
+        // Only the sky remains where the sun would be
+        assert_eq!(
+            sky.color(&sun_direction),
+            sky.sky_radiance(&sun_direction.unit_vector())
+        );
+    }
+}
@@ -0,0 +1,321 @@
+use std::sync::OnceLock;
+
+use crate::vec3::*;
+
+/// Range of wavelengths (nm) sampled by spectral rendering
+pub const LAMBDA_MIN: f64 = 380.;
+pub const LAMBDA_MAX: f64 = 720.;
+
+/// Wavelengths (nm) carried by a path in spectral rendering. The first one is
+/// the hero wavelength, the others are spread evenly over the visible range
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Wavelengths {
+    pub lambda: [f64; 3],
+    // Set once the path went through a wavelength dependent direction change
+    // and only the hero wavelength is still valid
+    pub secondary_terminated: bool,
+}
+
+impl Wavelengths {
+    /// Hero wavelength sampling for the uniform sample `u`
+    pub fn sample(u: f64) -> Self {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let hero = u * range;
+        Wavelengths {
+            lambda: [0., 1., 2.].map(|i| LAMBDA_MIN + (hero + i * range / 3.).rem_euclid(range)),
+            secondary_terminated: false,
+        }
+    }
+
+    /// Values of `color` at each wavelength
+    pub fn sample_color(&self, color: &Color) -> Color {
+        let spectrum = RgbSpectrum::new(color);
+        Color {
+            x: spectrum.value(self.lambda[0]),
+            y: spectrum.value(self.lambda[1]),
+            z: spectrum.value(self.lambda[2]),
+        }
+    }
+
+    /// Values at each wavelength of a physical quantity measured at 650, 550
+    /// and 450 nm, such as an index of refraction, interpolating linearly
+    /// between the measurements
+    pub fn interpolate_measurements(&self, values: &Color) -> Color {
+        let value = |lambda: f64| {
+            if lambda >= 650. {
+                values.x
+            } else if lambda >= 550. {
+                values.y + (lambda - 550.) / 100. * (values.x - values.y)
+            } else if lambda >= 450. {
+                values.z + (lambda - 450.) / 100. * (values.y - values.z)
+            } else {
+                values.z
+            }
+        };
+        Color {
+            x: value(self.lambda[0]),
+            y: value(self.lambda[1]),
+            z: value(self.lambda[2]),
+        }
+    }
+
+    /// Converts radiance carried at these wavelengths to linear sRGB
+    pub fn to_rgb(&self, values: &Color) -> Color {
+        let (y_integral, white) = film_normalization();
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+
+        let mut xyz = Vec3::default();
+        for (lambda, value) in self.lambda.iter().zip([values.x, values.y, values.z]) {
+            xyz += value * color_matching(*lambda);
+        }
+        // Monte Carlo estimate of the integral with uniformly sampled wavelengths
+        let rgb = (range / (3. * y_integral) * xyz).xyz_to_rgb();
+        Color {
+            x: rgb.x / white.x,
+            y: rgb.y / white.y,
+            z: rgb.z / white.z,
+        }
+    }
+}
+
+// Piecewise Gaussian used by the color matching function fit
+fn gaussian(lambda: f64, mean: f64, sigma_below: f64, sigma_above: f64) -> f64 {
+    let sigma = if lambda < mean {
+        sigma_below
+    } else {
+        sigma_above
+    };
+    let t = (lambda - mean) / sigma;
+    (-0.5 * t * t).exp()
+}
+
+/// CIE 1931 color matching functions, using the multi-lobe fit by Wyman,
+/// Sloan and Shirley (2013)
+pub fn color_matching(lambda: f64) -> Vec3 {
+    Vec3 {
+        x: 1.056 * gaussian(lambda, 599.8, 37.9, 31.0)
+            + 0.362 * gaussian(lambda, 442.0, 16.0, 26.7)
+            - 0.065 * gaussian(lambda, 501.1, 20.4, 26.2),
+        y: 0.821 * gaussian(lambda, 568.8, 46.9, 40.5)
+            + 0.286 * gaussian(lambda, 530.9, 16.3, 31.1),
+        z: 1.217 * gaussian(lambda, 437.0, 11.8, 36.0)
+            + 0.681 * gaussian(lambda, 459.0, 26.0, 13.8),
+    }
+}
+
+// Integral of the luminance matching function over the sampled range and the
+// color of a constant spectrum, used to keep white surfaces white
+fn film_normalization() -> (f64, Color) {
+    static NORMALIZATION: OnceLock<(f64, Color)> = OnceLock::new();
+    *NORMALIZATION.get_or_init(|| {
+        let steps = 1000;
+        let step = (LAMBDA_MAX - LAMBDA_MIN) / steps as f64;
+        let mut xyz = Vec3::default();
+        for i in 0..steps {
+            xyz += step * color_matching(LAMBDA_MIN + (i as f64 + 0.5) * step);
+        }
+        (xyz.y, (xyz / xyz.y).xyz_to_rgb())
+    })
+}
+
+// Spectra of Smits (1999) over ten bins covering the sampled range
+const SMITS_WHITE: [f64; 10] = [
+    1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000,
+];
+const SMITS_CYAN: [f64; 10] = [
+    0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000,
+];
+const SMITS_MAGENTA: [f64; 10] = [
+    1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959,
+];
+const SMITS_YELLOW: [f64; 10] = [
+    0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840,
+];
+const SMITS_RED: [f64; 10] = [
+    0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149,
+];
+const SMITS_GREEN: [f64; 10] = [
+    0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025,
+];
+const SMITS_BLUE: [f64; 10] = [
+    1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496,
+];
+
+/// Smooth spectrum reproducing an RGB color, built from a combination of the
+/// white, secondary and primary spectra of Smits
+struct RgbSpectrum {
+    bins: [f64; 10],
+}
+
+impl RgbSpectrum {
+    fn new(color: &Color) -> Self {
+        let (r, g, b) = (color.x, color.y, color.z);
+        let mut bins = [0.; 10];
+        let mut add = |weight: f64, spectrum: &[f64; 10]| {
+            for (bin, value) in bins.iter_mut().zip(spectrum) {
+                *bin += weight * value;
+            }
+        };
+
+        // Start from the smallest component as white, then add the secondary
+        // of the two others and the primary of the largest one
+        if r <= g && r <= b {
+            add(r, &SMITS_WHITE);
+            if g <= b {
+                add(g - r, &SMITS_CYAN);
+                add(b - g, &SMITS_BLUE);
+            } else {
+                add(b - r, &SMITS_CYAN);
+                add(g - b, &SMITS_GREEN);
+            }
+        } else if g <= r && g <= b {
+            add(g, &SMITS_WHITE);
+            if r <= b {
+                add(r - g, &SMITS_MAGENTA);
+                add(b - r, &SMITS_BLUE);
+            } else {
+                add(b - g, &SMITS_MAGENTA);
+                add(r - b, &SMITS_RED);
+            }
+        } else {
+            add(b, &SMITS_WHITE);
+            if r <= g {
+                add(r - b, &SMITS_YELLOW);
+                add(g - r, &SMITS_GREEN);
+            } else {
+                add(g - b, &SMITS_YELLOW);
+                add(r - g, &SMITS_RED);
+            }
+        }
+
+        RgbSpectrum { bins }
+    }
+
+    fn value(&self, lambda: f64) -> f64 {
+        let position = (lambda - LAMBDA_MIN) / (LAMBDA_MAX - LAMBDA_MIN) * 10.;
+        self.bins[(position.max(0.) as usize).min(9)]
+    }
+}
+
+/// Wavelength dependent index of refraction of a dielectric
+pub enum Dispersion {
+    /// n = a + b / λ², with λ in micrometers
+    Cauchy { a: f64, b: f64 },
+    /// n² = 1 + Σ b λ² / (λ² - c), with λ in micrometers
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Dispersion {
+    /// Borosilicate crown glass, the common optical glass
+    pub fn bk7() -> Self {
+        Dispersion::Sellmeier {
+            b: [1.03961212, 0.231792344, 1.01046945],
+            c: [0.00600069867, 0.0200179144, 103.560653],
+        }
+    }
+
+    /// Dense flint glass with strong dispersion, used for prisms
+    pub fn sf11() -> Self {
+        Dispersion::Sellmeier {
+            b: [1.73759695, 0.313747346, 1.89878101],
+            c: [0.013188707, 0.0623068142, 155.23629],
+        }
+    }
+
+    /// Index of refraction at the wavelength `lambda` (nm)
+    pub fn ior(&self, lambda: f64) -> f64 {
+        let micrometers = lambda / 1000.;
+        let lambda2 = micrometers * micrometers;
+        match self {
+            Dispersion::Cauchy { a, b } => a + b / lambda2,
+            Dispersion::Sellmeier { b, c } => {
+                let sum: f64 = b
+                    .iter()
+                    .zip(c)
+                    .map(|(b, c)| b * lambda2 / (lambda2 - c))
+                    .sum();
+                (1. + sum).sqrt()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::spectrum::*;
+
+    #[test]
+    fn test_wavelengths_cover_the_range() {
+        let wavelengths = Wavelengths::sample(0.9);
+        for lambda in wavelengths.lambda {
+            assert!((LAMBDA_MIN..LAMBDA_MAX).contains(&lambda));
+        }
+        let spacing = (LAMBDA_MAX - LAMBDA_MIN) / 3.;
+        let distance =
+            (wavelengths.lambda[1] - wavelengths.lambda[0]).rem_euclid(LAMBDA_MAX - LAMBDA_MIN);
+        assert!((distance - spacing).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_white_stays_white() {
+        let white = Color {
+            x: 1.,
+            y: 1.,
+            z: 1.,
+        };
+        let samples = 3000;
+        let mut total = Color::default();
+        for i in 0..samples {
+            let wavelengths = Wavelengths::sample((i as f64 + 0.5) / samples as f64);
+            total += wavelengths.to_rgb(&wavelengths.sample_color(&white));
+        }
+        let average = total / samples as f64;
+        assert!((average.x - 1.).abs() < 0.01);
+        assert!((average.y - 1.).abs() < 0.01);
+        assert!((average.z - 1.).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_red_round_trip() {
+        let red = Color {
+            x: 0.8,
+            y: 0.1,
+            z: 0.1,
+        };
+        let samples = 3000;
+        let mut total = Color::default();
+        for i in 0..samples {
+            let wavelengths = Wavelengths::sample((i as f64 + 0.5) / samples as f64);
+            total += wavelengths.to_rgb(&wavelengths.sample_color(&red));
+        }
+        let average = total / samples as f64;
+        assert!(average.x > 0.6 && average.y < 0.2 && average.z < 0.2);
+    }
+
+    #[test]
+    fn test_interpolate_measurements() {
+        let wavelengths = Wavelengths {
+            lambda: [400., 600., 700.],
+            secondary_terminated: false,
+        };
+        let index = Color {
+            x: 0.2,
+            y: 0.9,
+            z: 1.1,
+        };
+        let values = wavelengths.interpolate_measurements(&index);
+        assert_eq!(values.x, 1.1);
+        assert!((values.y - 0.55).abs() < 1e-12);
+        assert_eq!(values.z, 0.2);
+    }
+
+    #[test]
+    fn test_dispersion() {
+        let glass = Dispersion::bk7();
+        assert!((glass.ior(587.6) - 1.5168).abs() < 1e-3);
+        assert!(glass.ior(450.) > glass.ior(650.));
+
+        let cauchy = Dispersion::Cauchy { a: 1.5, b: 0.01 };
+        assert!((cauchy.ior(1000.) - 1.51).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,308 @@
+use std::path::Path;
+
+use crate::projection::Projection;
+use crate::vec3::*;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Eye {
+    Left,
+    Right,
+}
+
+impl Eye {
+    // -1 for the left eye, 1 for the right one
+    fn side(&self) -> f64 {
+        match self {
+            Eye::Left => -1.,
+            Eye::Right => 1.,
+        }
+    }
+
+    /// Inserts the eye before the extension: `image.png` gives
+    /// `image_left.png` for the left eye
+    pub fn file_name(&self, filename: &str) -> String {
+        let suffix = match self {
+            Eye::Left => "left",
+            Eye::Right => "right",
+        };
+        let path = Path::new(filename);
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let name = match path.extension() {
+            Some(extension) => format!("{stem}_{suffix}.{}", extension.to_string_lossy()),
+            None => format!("{stem}_{suffix}"),
+        };
+        path.with_file_name(name).to_string_lossy().into_owned()
+    }
+}
+
+/// How the views of the two eyes are aimed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Convergence {
+    /// Parallel views, everything appears in front of the screen
+    Parallel,
+    /// Each eye is rotated toward the convergence point. Simple but adds
+    /// vertical disparity at the corners
+    ToeIn,
+    /// Parallel eyes with frustums shifted to meet at the convergence
+    /// distance, without vertical disparity
+    OffAxis,
+}
+
+/// How the two eye images are written
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StereoLayout {
+    /// One file per eye, see `Eye::file_name`
+    SeparateFiles,
+    /// Left eye on the left half of a twice as wide image
+    SideBySide,
+    /// Left eye on the top half of a twice as high image
+    TopBottom,
+}
+
+/// Stereo pair settings. Objects at `convergence_distance` appear at the
+/// depth of the screen. Equirectangular panoramas are rendered as
+/// omnidirectional stereo, the eyes turning with the view direction and
+/// coming together toward the poles, and the toe-in and off-axis modes
+/// both make the rays of the eyes meet at the convergence distance
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stereo {
+    pub interocular_distance: f64,
+    pub convergence_distance: f64,
+    pub convergence: Convergence,
+    pub layout: StereoLayout,
+}
+
+impl Stereo {
+    /// Moves a camera space ray of the mono camera to the eye
+    pub fn eye_ray(
+        &self,
+        eye: Eye,
+        projection: &Projection,
+        origin: Point3,
+        direction: Vec3,
+    ) -> (Point3, Vec3) {
+        let half_distance = eye.side() * self.interocular_distance / 2.;
+
+        if *projection == Projection::Equirectangular {
+            let unit_direction = direction.unit_vector();
+            // Perpendicular to the horizontal view direction, shorter toward
+            // the poles where the views of the eyes would swap
+            let offset = half_distance
+                * Vec3 {
+                    x: -unit_direction.z,
+                    y: 0.,
+                    z: unit_direction.x,
+                };
+            return match self.convergence {
+                Convergence::Parallel => (origin + offset, direction),
+                Convergence::ToeIn | Convergence::OffAxis => (
+                    origin + offset,
+                    self.convergence_distance * unit_direction - offset,
+                ),
+            };
+        }
+
+        let offset = Vec3 {
+            x: half_distance,
+            y: 0.,
+            z: 0.,
+        };
+        match self.convergence {
+            Convergence::Parallel => (origin + offset, direction),
+            Convergence::ToeIn => {
+                let angle = half_distance.atan2(self.convergence_distance);
+                (
+                    rotate_y(&origin, angle) + offset,
+                    rotate_y(&direction, angle),
+                )
+            }
+            Convergence::OffAxis => {
+                // Flat projections converge on a plane, fisheyes on a sphere
+                let distance = match projection {
+                    Projection::Fisheye { .. } => self.convergence_distance / direction.len(),
+                    _ => self.convergence_distance / -direction.z,
+                };
+                (origin + offset, distance * direction - offset)
+            }
+        }
+    }
+
+    /// Size and pixels of the image holding both eyes, each stored row by row
+    pub fn combine(
+        &self,
+        width: u32,
+        height: u32,
+        left: &[Color],
+        right: &[Color],
+    ) -> (u32, u32, Vec<Color>) {
+        match self.layout {
+            StereoLayout::SideBySide => {
+                let rows = left
+                    .chunks(width as usize)
+                    .zip(right.chunks(width as usize))
+                    .flat_map(|(left_row, right_row)| left_row.iter().chain(right_row))
+                    .copied()
+                    .collect();
+                (2 * width, height, rows)
+            }
+            StereoLayout::TopBottom | StereoLayout::SeparateFiles => {
+                (width, 2 * height, [left, right].concat())
+            }
+        }
+    }
+}
+
+// Rotation around the vertical axis, turning -z toward -x for positive angles
+fn rotate_y(v: &Vec3, angle: f64) -> Vec3 {
+    let (sin, cos) = angle.sin_cos();
+    Vec3 {
+        x: v.x * cos + v.z * sin,
+        y: v.y,
+        z: -v.x * sin + v.z * cos,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::projection::FisheyeMapping;
+    use crate::stereo::*;
+
+    fn stereo(convergence: Convergence) -> Stereo {
+        Stereo {
+            interocular_distance: 0.064,
+            convergence_distance: 2.,
+            convergence,
+            layout: StereoLayout::SeparateFiles,
+        }
+    }
+
+    // Point where the ray reaches the depth
+    fn at_depth(ray: (Point3, Vec3), depth: f64) -> Point3 {
+        let (origin, direction) = ray;
+        origin + ((-depth - origin.z) / direction.z) * direction
+    }
+
+    #[test]
+    fn test_eyes_converge() {
+        let viewport_point = Vec3 {
+            x: 0.3,
+            y: -0.2,
+            z: -1.,
+        };
+        for convergence in [Convergence::ToeIn, Convergence::OffAxis] {
+            let stereo = stereo(convergence);
+            let ray = |eye, direction| {
+                stereo.eye_ray(eye, &Projection::Perspective, Point3::default(), direction)
+            };
+            // The center rays of both eyes meet on the axis
+            let forward = Vec3 {
+                x: 0.,
+                y: 0.,
+                z: -1.,
+            };
+            let left = at_depth(ray(Eye::Left, forward), 2.);
+            let right = at_depth(ray(Eye::Right, forward), 2.);
+            assert!((left - 2. * forward).len() < 1e-9);
+            assert!((right - 2. * forward).len() < 1e-9);
+
+            // Off-axis views meet on the whole convergence plane
+            if convergence == Convergence::OffAxis {
+                let left = at_depth(ray(Eye::Left, viewport_point), 2.);
+                let right = at_depth(ray(Eye::Right, viewport_point), 2.);
+                assert!((left - right).len() < 1e-9);
+                assert!((left - 2. * viewport_point).len() < 1e-9);
+            }
+        }
+
+        let fisheye = Projection::Fisheye {
+            mapping: FisheyeMapping::Equidistant,
+            fov: 180.,
+        };
+        let stereo = stereo(Convergence::OffAxis);
+        let (origin, direction) =
+            stereo.eye_ray(Eye::Left, &fisheye, Point3::default(), viewport_point);
+        assert!((origin + direction - 2. * viewport_point.unit_vector()).len() < 1e-9);
+    }
+
+    #[test]
+    fn test_parallel_eyes() {
+        let stereo = stereo(Convergence::Parallel);
+        let direction = Vec3 {
+            x: 0.5,
+            y: 0.,
+            z: -1.,
+        };
+        let (left_origin, left) = stereo.eye_ray(
+            Eye::Left,
+            &Projection::Perspective,
+            Point3::default(),
+            direction,
+        );
+        let (right_origin, right) = stereo.eye_ray(
+            Eye::Right,
+            &Projection::Perspective,
+            Point3::default(),
+            direction,
+        );
+        assert_eq!(left, right);
+        assert!((right_origin.x - left_origin.x - 0.064).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_panorama_eyes_follow_view_direction() {
+        let stereo = stereo(Convergence::Parallel);
+        // Looking toward +x, the right eye is toward +z
+        let (origin, _) = stereo.eye_ray(
+            Eye::Right,
+            &Projection::Equirectangular,
+            Point3::default(),
+            Vec3 {
+                x: 1.,
+                y: 0.,
+                z: 0.,
+            },
+        );
+        assert!((origin.z - 0.032).abs() < 1e-12 && origin.x.abs() < 1e-12);
+        // Both eyes are at the center when looking straight up
+        let (origin, _) = stereo.eye_ray(
+            Eye::Right,
+            &Projection::Equirectangular,
+            Point3::default(),
+            Vec3 {
+                x: 0.,
+                y: 1.,
+                z: 0.,
+            },
+        );
+        assert!(origin.len() < 1e-12);
+    }
+
+    #[test]
+    fn test_layouts() {
+        let gray = |value: f64| Color {
+            x: value,
+            y: value,
+            z: value,
+        };
+        let left = [gray(0.), gray(1.), gray(2.), gray(3.)];
+        let right = [gray(4.), gray(5.), gray(6.), gray(7.)];
+        let mut stereo = stereo(Convergence::Parallel);
+
+        stereo.layout = StereoLayout::SideBySide;
+        let (width, height, colors) = stereo.combine(2, 2, &left, &right);
+        assert_eq!((width, height), (4, 2));
+        let values: Vec<f64> = colors.iter().map(|color| color.x).collect();
+        assert_eq!(values, vec![0., 1., 4., 5., 2., 3., 6., 7.]);
+
+        stereo.layout = StereoLayout::TopBottom;
+        let (width, height, colors) = stereo.combine(2, 2, &left, &right);
+        assert_eq!((width, height), (2, 4));
+        assert_eq!(colors[4], gray(4.));
+    }
+
+    #[test]
+    fn test_eye_file_names() {
+        assert_eq!(Eye::Left.file_name("out/image.png"), "out/image_left.png");
+        assert_eq!(Eye::Right.file_name("image"), "image_right");
+    }
+}
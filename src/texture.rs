@@ -0,0 +1,195 @@
+use std::sync::Arc;
+
+use raster::error::RasterError;
+
+use crate::vec3::*;
+
+/// Spatially varying value looked up by surface coordinates. Scalar
+/// parameters use the first channel
+pub trait Texture: Send + Sync {
+    fn value(&self, u: f64, v: f64, point: &Point3) -> Color;
+}
+
+pub struct SolidColor {
+    pub albedo: Color,
+}
+
+impl SolidColor {
+    pub fn new(albedo: Color) -> Self {
+        SolidColor { albedo }
+    }
+
+    /// Texture holding the same value in every channel
+    pub fn gray(value: f64) -> Self {
+        SolidColor {
+            albedo: Color {
+                x: value,
+                y: value,
+                z: value,
+            },
+        }
+    }
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _u: f64, _v: f64, _point: &Point3) -> Color {
+        self.albedo
+    }
+}
+
+/// Image mapped over the [0, 1] texture coordinates, repeating outside of them.
+/// `v` goes from the bottom to the top of the image
+pub struct ImageTexture {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+}
+
+impl ImageTexture {
+    /// Loads a color image, converting the sRGB encoded pixels to linear values
+    pub fn load(filename: &str) -> Result<Self, RasterError> {
+        Self::load_with(filename, Self::srgb_to_linear)
+    }
+
+    /// Loads an image holding data (roughness, normals...) which is used as is
+    pub fn load_linear(filename: &str) -> Result<Self, RasterError> {
+        Self::load_with(filename, |value| value)
+    }
+
+    fn load_with(filename: &str, decode: fn(f64) -> f64) -> Result<Self, RasterError> {
+        let image = raster::open(filename)?;
+        let pixels = image
+            .bytes
+            .chunks_exact(4)
+            .map(|rgba| Color {
+                x: decode(rgba[0] as f64 / 255.),
+                y: decode(rgba[1] as f64 / 255.),
+                z: decode(rgba[2] as f64 / 255.),
+            })
+            .collect::<Vec<_>>();
+        if pixels.len() != image.width as usize * image.height as usize {
+            return Err(RasterError::Unexpected);
+        }
+
+        Ok(Self::new(
+            image.width as usize,
+            image.height as usize,
+            pixels,
+        ))
+    }
+
+    /// `pixels` are stored row by row starting from the top-left corner, and
+    /// there must be `width * height` of them
+    pub fn new(width: usize, height: usize, pixels: Vec<Color>) -> Self {
+        if pixels.len() != width * height {
+            panic!(
+                "A {width}x{height} image texture needs {} pixels, got {}",
+                width * height,
+                pixels.len()
+            );
+        }
+        ImageTexture {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    fn srgb_to_linear(value: f64) -> f64 {
+        if value <= 0.04045 {
+            value / 12.92
+        } else {
+            ((value + 0.055) / 1.055).powf(2.4)
+        }
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _point: &Point3) -> Color {
+        if self.pixels.is_empty() {
+            return Color::default();
+        }
+
+        let u = u.rem_euclid(1.);
+        let v = 1. - v.rem_euclid(1.);
+        let i = ((u * self.width as f64) as usize).min(self.width - 1);
+        let j = ((v * self.height as f64) as usize).min(self.height - 1);
+        self.pixels[j * self.width + i]
+    }
+}
+
+/// Single channel of another texture, e.g. roughness stored in the green
+/// channel of a packed glTF metallic-roughness image
+pub struct ChannelTexture {
+    pub texture: Arc<dyn Texture>,
+    pub channel: usize,
+}
+
+impl Texture for ChannelTexture {
+    fn value(&self, u: f64, v: f64, point: &Point3) -> Color {
+        let color = self.texture.value(u, v, point);
+        let value = match self.channel {
+            0 => color.x,
+            1 => color.y,
+            _ => color.z,
+        };
+        Color {
+            x: value,
+            y: value,
+            z: value,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::texture::*;
+
+    #[test]
+    fn test_image_lookup_wraps() {
+        let red = Color {
+            x: 1.,
+            y: 0.,
+            z: 0.,
+        };
+        let blue = Color {
+            x: 0.,
+            y: 0.,
+            z: 1.,
+        };
+        // Top row red, bottom row blue
+        let texture = ImageTexture::new(1, 2, vec![red, blue]);
+        let point = Point3::default();
+        assert_eq!(texture.value(0.5, 0.75, &point), red);
+        assert_eq!(texture.value(0.5, 0.25, &point), blue);
+        assert_eq!(texture.value(1.5, -0.75, &point), blue);
+    }
+
+    #[test]
+    #[should_panic(expected = "A 2x2 image texture needs 4 pixels, got 3")]
+    fn test_image_needs_all_pixels() {
+        ImageTexture::new(2, 2, vec![Color::default(); 3]);
+    }
+
+    #[test]
+    fn test_channel_texture() {
+        let texture = ChannelTexture {
+            texture: Arc::new(SolidColor::new(Color {
+                x: 0.1,
+                y: 0.2,
+                z: 0.3,
+            })),
+            channel: 1,
+        };
+        assert_eq!(texture.value(0., 0., &Point3::default()).z, 0.2);
+    }
+
+    #[test]
+    fn test_srgb_decoding() {
+        assert_eq!(ImageTexture::srgb_to_linear(0.), 0.);
+        assert!((ImageTexture::srgb_to_linear(1.) - 1.).abs() < 1e-12);
+        assert!((ImageTexture::srgb_to_linear(0.5) - 0.214).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,220 @@
+use std::f64::consts::PI;
+use std::ops;
+
+use crate::vec3::Color;
+
+/// Complex number for the wave optics of thin films
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Complex {
+    pub re: f64,
+    pub im: f64,
+}
+
+impl Complex {
+    pub fn new(re: f64, im: f64) -> Self {
+        Complex { re, im }
+    }
+
+    pub fn real(re: f64) -> Self {
+        Complex { re, im: 0. }
+    }
+
+    pub fn norm_squared(&self) -> f64 {
+        self.re * self.re + self.im * self.im
+    }
+
+    /// Principal square root
+    pub fn sqrt(&self) -> Self {
+        let norm = self.norm_squared().sqrt();
+        let re = ((norm + self.re) / 2.).max(0.).sqrt();
+        let im = ((norm - self.re) / 2.).max(0.).sqrt();
+        Complex {
+            re,
+            im: if self.im < 0. { -im } else { im },
+        }
+    }
+
+    /// e raised to `i * self`
+    pub fn exp_i(&self) -> Self {
+        let magnitude = (-self.im).exp();
+        Complex {
+            re: magnitude * self.re.cos(),
+            im: magnitude * self.re.sin(),
+        }
+    }
+}
+
+impl ops::Add for Complex {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Complex::new(self.re + rhs.re, self.im + rhs.im)
+    }
+}
+
+impl ops::Sub for Complex {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        Complex::new(self.re - rhs.re, self.im - rhs.im)
+    }
+}
+
+impl ops::Mul for Complex {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        Complex::new(
+            self.re * rhs.re - self.im * rhs.im,
+            self.re * rhs.im + self.im * rhs.re,
+        )
+    }
+}
+
+impl ops::Div for Complex {
+    type Output = Self;
+
+    fn div(self, rhs: Self) -> Self {
+        let denominator = rhs.norm_squared();
+        Complex::new(
+            (self.re * rhs.re + self.im * rhs.im) / denominator,
+            (self.im * rhs.re - self.re * rhs.im) / denominator,
+        )
+    }
+}
+
+// Cosine of the refracted angle going from the medium `n_from` to `n_to`
+fn refracted_cos(n_from: Complex, n_to: Complex, sin_from: Complex) -> Complex {
+    let ratio = n_from / n_to * sin_from;
+    (Complex::real(1.) - ratio * ratio).sqrt()
+}
+
+// Amplitude reflection coefficients for s and p polarized light
+fn amplitude_reflection(n1: Complex, cos1: Complex, n2: Complex, cos2: Complex) -> [Complex; 2] {
+    [
+        (n1 * cos1 - n2 * cos2) / (n1 * cos1 + n2 * cos2),
+        (n2 * cos1 - n1 * cos2) / (n2 * cos1 + n1 * cos2),
+    ]
+}
+
+/// Reflectance of a film with the thickness `thickness` (nm) and the index of
+/// refraction `film_ior` lying on a substrate with the (complex, for metals)
+/// index `substrate_ior`, seen from a medium with the index `outside_ior`.
+/// Accounts for the interference of the light reflected inside the film at
+/// the wavelength `wavelength` (nm)
+pub fn thin_film_reflectance(
+    cos_theta: f64,
+    thickness: f64,
+    outside_ior: f64,
+    film_ior: f64,
+    substrate_ior: Complex,
+    wavelength: f64,
+) -> f64 {
+    let cos1 = Complex::real(cos_theta.clamp(0., 1.));
+    let sin1 = Complex::real((1. - cos_theta * cos_theta).max(0.).sqrt());
+    let n1 = Complex::real(outside_ior);
+    let n2 = Complex::real(film_ior);
+    let n3 = substrate_ior;
+
+    let cos2 = refracted_cos(n1, n2, sin1);
+    let cos3 = refracted_cos(n1, n3, sin1);
+
+    let r12 = amplitude_reflection(n1, cos1, n2, cos2);
+    let r23 = amplitude_reflection(n2, cos2, n3, cos3);
+
+    // Phase difference accumulated by the light travelling through the film
+    // and back
+    let phase = Complex::real(4. * PI * thickness / wavelength) * n2 * cos2;
+    let shift = phase.exp_i();
+
+    let reflectance: f64 = (0..2)
+        .map(|polarization| {
+            let r12 = r12[polarization];
+            let r23 = r23[polarization];
+            ((r12 + r23 * shift) / (Complex::real(1.) + r12 * r23 * shift)).norm_squared()
+        })
+        .sum();
+    (reflectance / 2.).clamp(0., 1.)
+}
+
+// Wavelength ranges (nm) averaged for the red, green and blue channels
+const RGB_BANDS: [(f64, f64); 3] = [(600., 700.), (500., 600.), (400., 500.)];
+const SAMPLES_PER_BAND: usize = 8;
+
+/// Thin film reflectance averaged over the wavelengths of each color channel.
+/// `substrate_ior` holds the complex index of refraction for each channel
+pub fn thin_film_reflectance_rgb(
+    cos_theta: f64,
+    thickness: f64,
+    outside_ior: f64,
+    film_ior: f64,
+    substrate_ior: [Complex; 3],
+) -> Color {
+    let band = |channel: usize| {
+        let (start, end) = RGB_BANDS[channel];
+        let sum: f64 = (0..SAMPLES_PER_BAND)
+            .map(|i| {
+                let wavelength = start + (end - start) * (i as f64 + 0.5) / SAMPLES_PER_BAND as f64;
+                thin_film_reflectance(
+                    cos_theta,
+                    thickness,
+                    outside_ior,
+                    film_ior,
+                    substrate_ior[channel],
+                    wavelength,
+                )
+            })
+            .sum();
+        sum / SAMPLES_PER_BAND as f64
+    };
+
+    Color {
+        x: band(0),
+        y: band(1),
+        z: band(2),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::microfacet::fresnel_dielectric;
+    use crate::thinfilm::*;
+
+    #[test]
+    fn test_complex_arithmetic() {
+        let a = Complex::new(1., 2.);
+        let b = Complex::new(3., -1.);
+        assert_eq!(a * b, Complex::new(5., 5.));
+        let quotient = (a * b) / b;
+        assert!((quotient.re - 1.).abs() < 1e-12 && (quotient.im - 2.).abs() < 1e-12);
+        let root = Complex::new(-4., 0.).sqrt();
+        assert!(root.re.abs() < 1e-12 && (root.im - 2.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_vanishing_film_is_plain_interface() {
+        for cos_theta in [1., 0.7, 0.2] {
+            let reflectance =
+                thin_film_reflectance(cos_theta, 0., 1., 1.33, Complex::real(1.5), 550.);
+            assert!((reflectance - fresnel_dielectric(cos_theta, 1.5)).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_quarter_wave_coating_cancels_reflection() {
+        // A film with the geometric mean index and a quarter wavelength optical
+        // thickness is a perfect anti-reflective coating at normal incidence
+        let film_ior = 1.5f64.sqrt();
+        let thickness = 550. / (4. * film_ior);
+        let reflectance =
+            thin_film_reflectance(1., thickness, 1., film_ior, Complex::real(1.5), 550.);
+        assert!(reflectance < 1e-9);
+    }
+
+    #[test]
+    fn test_soap_film_is_colored() {
+        let water = Complex::real(1.);
+        let reflectance = thin_film_reflectance_rgb(1., 300., 1., 1.33, [water; 3]);
+        assert!((reflectance.y - reflectance.x).abs() > 0.01);
+    }
+}
@@ -1,5 +1,8 @@
+use std::f64::consts::PI;
 use std::ops;
 
+use crate::sampler::Sampler;
+
 pub type Color = Vec3;
 pub type Point3 = Vec3;
 
@@ -24,21 +27,39 @@ impl Vec3 {
         *self / self.len()
     }
 
-    fn random_in_unit_sphere() -> Self {
-        loop {
-            let p = Self::random(-1., 1.);
-            if p.len_squared() < 1. {
-                return p;
-            }
+    /// Relative luminance of a linear Rec. 709 color
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.x + 0.7152 * self.y + 0.0722 * self.z
+    }
+
+    /// Converts CIE XYZ tristimulus values to linear sRGB
+    pub fn xyz_to_rgb(&self) -> Self {
+        Vec3 {
+            x: 3.2404542 * self.x - 1.5371385 * self.y - 0.4985314 * self.z,
+            y: -0.9692660 * self.x + 1.8760108 * self.y + 0.0415560 * self.z,
+            z: 0.0556434 * self.x - 0.2040259 * self.y + 1.0572252 * self.z,
         }
     }
 
-    pub fn random_unit_vector() -> Self {
-        Self::random_in_unit_sphere().unit_vector()
+    pub fn random_in_unit_sphere(sampler: &mut dyn Sampler) -> Self {
+        let radius = sampler.get_1d().cbrt();
+        radius * Self::random_unit_vector(sampler)
+    }
+
+    pub fn random_unit_vector(sampler: &mut dyn Sampler) -> Self {
+        let (u, v) = sampler.get_2d();
+        let z = 1. - 2. * u;
+        let r = (1. - z * z).max(0.).sqrt();
+        let phi = 2. * PI * v;
+        Vec3 {
+            x: r * phi.cos(),
+            y: r * phi.sin(),
+            z,
+        }
     }
 
-    pub fn random_on_hemisphere(normal: &Self) -> Self {
-        let v = Self::random_unit_vector();
+    pub fn random_on_hemisphere(normal: &Self, sampler: &mut dyn Sampler) -> Self {
+        let v = Self::random_unit_vector(sampler);
         if v.dot(normal) > 0. {
             v
         } else {
@@ -66,20 +87,22 @@ impl Vec3 {
         }
     }
 
-    pub fn random_unit() -> Self {
+    pub fn random_unit(sampler: &mut dyn Sampler) -> Self {
+        let (x, y) = sampler.get_2d();
         Vec3 {
-            x: rand::random::<f64>(),
-            y: rand::random::<f64>(),
-            z: rand::random::<f64>(),
+            x,
+            y,
+            z: sampler.get_1d(),
         }
     }
 
-    pub fn random(min: f64, max: f64) -> Self {
+    pub fn random(min: f64, max: f64, sampler: &mut dyn Sampler) -> Self {
         let scale = max - min;
+        let unit = Self::random_unit(sampler);
         Vec3 {
-            x: min + scale * rand::random::<f64>(),
-            y: min + scale * rand::random::<f64>(),
-            z: min + scale * rand::random::<f64>(),
+            x: min + scale * unit.x,
+            y: min + scale * unit.y,
+            z: min + scale * unit.z,
         }
     }
 }